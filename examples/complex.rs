@@ -1,12 +1,18 @@
 use std::{thread::available_parallelism, time::Instant};
 
-use crate::network::Network;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-pub fn complex_example() {
+use rusting_brain::{Network, Trainer};
+
+const SEED: u64 = 42;
+
+fn main() {
     let start = Instant::now();
 
     let layers = vec![8, 16, 1];
-    let mut net = Network::new(layers, 0.01);
+    let mut net = Network::new_with_seed(layers, 0.01, SEED);
+    let mut rng = StdRng::seed_from_u64(SEED);
 
     let samples = 10_000;
     let mut inputs = Vec::with_capacity(samples);
@@ -15,7 +21,7 @@ pub fn complex_example() {
     for _ in 0..samples {
         let mut x = Vec::with_capacity(8);
         for _ in 0..8 {
-            let v = rand::random::<f32>();
+            let v = rng.gen_range(0.0..1.0);
             x.push(v);
         }
         let y = x.iter().sum::<f32>() / 8.0;
@@ -26,11 +32,13 @@ pub fn complex_example() {
     let num_threads = available_parallelism().map(|n| n.get()).unwrap_or(1);
 
     println!("number of threads: {}", num_threads);
-    for _ in 0..5_000 {
-        net.train_batch_parallel(&inputs, &targets, num_threads);
-    }
+    Trainer::new(5_000, samples)
+        .with_shuffle(false)
+        .with_threads(num_threads)
+        .fit(&mut net, &inputs, &targets)
+        .unwrap();
 
-    let test_input: Vec<f32> = (0..8).map(|_| rand::random::<f32>()).collect();
+    let test_input: Vec<f32> = (0..8).map(|_| rng.gen_range(0.0..1.0)).collect();
     let expected = test_input.iter().sum::<f32>() / 8.0;
     let output = net.forward(&test_input);
 
@@ -0,0 +1,223 @@
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use rusting_brain::{
+    Adam, EarlyStopping, GradientClipping, Initializer, Matrix, Network, Trainer,
+};
+
+const SEED: u64 = 42;
+
+// cargo run --release --example tf_compare [sanity | large_linear]
+fn main() {
+    match std::env::args().nth(1).as_deref() {
+        Some("sanity") => learning_sanity_test(),
+        Some("large_linear") => large_model_learning_test(),
+        _ => tensorflow_like_example(),
+    }
+}
+
+fn tensorflow_like_example() {
+    let input_size = 512usize;
+    let hidden_sizes = [1024usize, 1024usize, 512usize];
+    let output_size = 10usize;
+    let batch_size = 256usize;
+    let samples = 20_000usize;
+    let iterations = 50usize;
+
+    let mut layers = Vec::new();
+    layers.push(input_size);
+    layers.extend_from_slice(&hidden_sizes);
+    layers.push(output_size);
+
+    let mut net = Network::new_with_seed(layers, 0.001, SEED)
+        .with_initializer(Initializer::HeNormal, Initializer::Zeros)
+        .with_optimizer(Adam::default())
+        .with_gradient_clipping(GradientClipping::GlobalNorm(1.0))
+        .with_batch_norm(1)
+        .with_batch_norm(2)
+        .with_batch_norm(3);
+
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let true_w = Matrix::random_uniform(output_size, input_size, 0.0, 1.0, &mut rng);
+    let target_scale = 0.01f32;
+
+    let mut inputs = Vec::with_capacity(samples);
+    let mut targets = Vec::with_capacity(samples);
+
+    for _ in 0..samples {
+        let mut x = Vec::with_capacity(input_size);
+        for _ in 0..input_size {
+            x.push(rng.gen_range(0.0..1.0));
+        }
+
+        let mut x_matrix = Matrix::new(input_size, 1);
+        x_matrix.copy_from_slice(&x);
+
+        let mut y_matrix = Matrix::new(output_size, 1);
+        true_w.dot(&x_matrix, &mut y_matrix);
+
+        for v in &mut y_matrix.data {
+            *v *= target_scale;
+        }
+
+        inputs.push(x);
+        targets.push(y_matrix.data.clone());
+    }
+
+    let start = Instant::now();
+
+    Trainer::new(iterations, batch_size)
+        .with_verbose(true)
+        .fit(&mut net, &inputs, &targets)
+        .unwrap();
+
+    let duration = start.elapsed();
+
+    let mut test_input = Vec::with_capacity(input_size);
+    for _ in 0..input_size {
+        test_input.push(rng.gen_range(0.0..1.0));
+    }
+    let mut test_x_matrix = Matrix::new(input_size, 1);
+    test_x_matrix.copy_from_slice(&test_input);
+    let mut test_y_matrix = Matrix::new(output_size, 1);
+    true_w.dot(&test_x_matrix, &mut test_y_matrix);
+    for v in &mut test_y_matrix.data {
+        *v *= target_scale;
+    }
+    let expected = test_y_matrix.data.clone();
+    let output = net.forward(&test_input);
+
+    println!("Large model test (Rust NN):");
+    println!("Input len: {}", test_input.len());
+    println!("Output len: {}", output.len());
+    println!("Time taken (seconds): {:.6}", duration.as_secs_f64());
+    println!("Example test target (first 3):   {:?}", &expected[0..3]);
+    println!("Example test predicted (first 3): {:?}", &output[0..3]);
+
+    for i in 0..5 {
+        let out = net.forward(&inputs[i]);
+        println!("Target:  {:?}", &targets[i][0..3]);
+        println!("Predicted: {:?}", &out[0..3]);
+        println!();
+    }
+}
+
+fn learning_sanity_test() {
+    let input_size = 2usize;
+    let hidden_sizes = [8usize];
+    let output_size = 1usize;
+    let batch_size = 32usize;
+    let samples = 1024usize;
+    let epochs = 50usize;
+
+    let mut layers = Vec::new();
+    layers.push(input_size);
+    layers.extend_from_slice(&hidden_sizes);
+    layers.push(output_size);
+
+    let mut net = Network::new_with_seed(layers, 0.05, SEED);
+    let mut rng = StdRng::seed_from_u64(SEED);
+
+    let mut inputs = Vec::with_capacity(samples);
+    let mut targets = Vec::with_capacity(samples);
+
+    for _ in 0..samples {
+        let x0 = rng.gen_range(0.0..1.0);
+        let x1 = rng.gen_range(0.0..1.0);
+        let y = x0 + x1;
+
+        inputs.push(vec![x0, x1]);
+        targets.push(vec![y]);
+    }
+
+    let start_idx = 0usize;
+    let end_idx = batch_size.min(samples);
+    let initial_loss = net.evaluate(&inputs[start_idx..end_idx], &targets[start_idx..end_idx]);
+
+    Trainer::new(epochs, batch_size)
+        .with_threads(1)
+        .fit(&mut net, &inputs, &targets)
+        .unwrap();
+
+    let final_loss = net.evaluate(&inputs[start_idx..end_idx], &targets[start_idx..end_idx]);
+
+    println!("Learning sanity test (y = x0 + x1):");
+    println!("Initial MSE: {}", initial_loss);
+    println!("Final   MSE: {}", final_loss);
+}
+
+fn large_model_learning_test() {
+    let input_size = 512usize;
+    let output_size = 10usize;
+    let batch_size = 256usize;
+    let samples = 40_000usize;
+    let epochs = 200usize;
+
+    let layers = vec![input_size, output_size];
+
+    let mut net = Network::new_with_seed(layers, 0.01, SEED);
+
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let true_w = Matrix::random_uniform(output_size, input_size, 0.0, 1.0, &mut rng);
+    let target_scale = 0.01f32;
+
+    let mut inputs = Vec::with_capacity(samples);
+    let mut targets = Vec::with_capacity(samples);
+
+    for _ in 0..samples {
+        let mut x = Vec::with_capacity(input_size);
+        for _ in 0..input_size {
+            x.push(rng.gen_range(0.0..1.0));
+        }
+
+        let mut x_matrix = Matrix::new(input_size, 1);
+        x_matrix.copy_from_slice(&x);
+
+        let mut y_matrix = Matrix::new(output_size, 1);
+        true_w.dot(&x_matrix, &mut y_matrix);
+
+        for v in &mut y_matrix.data {
+            *v *= target_scale;
+        }
+
+        inputs.push(x);
+        targets.push(y_matrix.data.clone());
+    }
+
+    let initial_loss = net.evaluate(&inputs[0..batch_size], &targets[0..batch_size]);
+
+    Trainer::new(epochs, batch_size)
+        .with_shuffle(false)
+        .with_threads(1)
+        .with_verbose(true)
+        .with_callback(
+            EarlyStopping::new(10)
+                .with_min_delta(1e-6)
+                .with_restore_best_weights(true),
+        )
+        .fit_with_validation(
+            &mut net,
+            &inputs,
+            &targets,
+            &inputs[0..batch_size],
+            &targets[0..batch_size],
+        )
+        .unwrap();
+
+    let final_loss = net.evaluate(&inputs[0..batch_size], &targets[0..batch_size]);
+
+    println!("Large model linear mapping test:");
+    println!("Initial MSE: {}", initial_loss);
+    println!("Final   MSE: {}", final_loss);
+    println!("\nExample predictions:");
+
+    for i in 0..5 {
+        let out = net.forward(&inputs[i]);
+        println!("Target:  {:?}", &targets[i][0..3]);
+        println!("Predicted: {:?}", &out[0..3]);
+        println!();
+    }
+
+}
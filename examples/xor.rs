@@ -1,20 +1,20 @@
 use std::time::Instant;
 
-use crate::network::Network;
+use rusting_brain::Network;
 
-pub fn xor() {
+fn main() {
     let start = Instant::now();
 
     let layers = vec![2, 3, 1];
-    let mut net = Network::new(layers, 0.01); 
+    let mut net = Network::new_with_seed(layers, 0.01, 42); 
 
-    let inputs = vec![
+    let inputs = [
         vec![0.0, 0.0],
         vec![0.0, 1.0],
         vec![1.0, 0.0],
         vec![1.0, 1.0],
     ];
-    let targets = vec![
+    let targets = [
         vec![0.0],
         vec![1.0],
         vec![1.0],
@@ -0,0 +1,285 @@
+use crate::matrix::Matrix;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Activation {
+    Identity,
+    Sigmoid,
+    Tanh,
+    Relu,
+    LeakyRelu(f32),
+    Elu(f32),
+    Gelu,
+    Silu,
+    Softplus,
+    Selu,
+    Softmax,
+}
+
+impl Activation {
+    pub const SWISH: Activation = Activation::Silu;
+
+    #[inline(always)]
+    pub fn activate(&self, x: f32) -> f32 {
+        match *self {
+            Activation::Identity => x,
+            Activation::Sigmoid => sigmoid(x),
+            Activation::Tanh => x.tanh(),
+            Activation::Relu => {
+                if x > 0.0 { x } else { 0.0 }
+            }
+            Activation::LeakyRelu(alpha) => {
+                if x > 0.0 { x } else { alpha * x }
+            }
+            Activation::Elu(alpha) => {
+                if x > 0.0 { x } else { alpha * (x.exp() - 1.0) }
+            }
+            Activation::Gelu => 0.5 * x * (1.0 + gelu_inner(x).tanh()),
+            Activation::Silu => x * sigmoid(x),
+            Activation::Softplus => softplus(x),
+            Activation::Selu => {
+                SELU_SCALE * if x > 0.0 { x } else { SELU_ALPHA * (x.exp() - 1.0) }
+            }
+            Activation::Softmax => {
+                panic!("softmax normalizes a whole layer, use Activation::apply")
+            }
+        }
+    }
+
+    // Applies the activation to every column of `z`. Columns are samples, so
+    // softmax is normalized down each column rather than across the matrix.
+    pub fn apply(&self, z: &Matrix, target: &mut Matrix) {
+        debug_assert_eq!(z.data.len(), target.data.len());
+
+        if *self == Activation::Softmax {
+            softmax_columns(z, target);
+            return;
+        }
+
+        for (a, &x) in target.data.iter_mut().zip(&z.data) {
+            *a = self.activate(x);
+        }
+    }
+
+    // Derivatives are taken with respect to the pre-activation value `z`,
+    // which is what the network keeps around in `weighted_sums`.
+    #[inline(always)]
+    pub fn derivative(&self, z: f32) -> f32 {
+        match *self {
+            Activation::Identity => 1.0,
+            Activation::Sigmoid => {
+                let s = sigmoid(z);
+                s * (1.0 - s)
+            }
+            Activation::Tanh => {
+                let t = z.tanh();
+                1.0 - t * t
+            }
+            Activation::Relu => {
+                if z > 0.0 { 1.0 } else { 0.0 }
+            }
+            Activation::LeakyRelu(alpha) => {
+                if z > 0.0 { 1.0 } else { alpha }
+            }
+            Activation::Elu(alpha) => {
+                if z > 0.0 { 1.0 } else { alpha * z.exp() }
+            }
+            Activation::Gelu => {
+                let t = gelu_inner(z).tanh();
+                let d_inner = GELU_COEFF * (1.0 + 3.0 * 0.044715 * z * z);
+                0.5 * (1.0 + t) + 0.5 * z * (1.0 - t * t) * d_inner
+            }
+            Activation::Silu => {
+                let s = sigmoid(z);
+                s * (1.0 + z * (1.0 - s))
+            }
+            Activation::Softplus => sigmoid(z),
+            Activation::Selu => {
+                SELU_SCALE * if z > 0.0 { 1.0 } else { SELU_ALPHA * z.exp() }
+            }
+            Activation::Softmax => {
+                panic!("softmax has no element-wise derivative")
+            }
+        }
+    }
+}
+
+// Self-normalizing constants from Klambauer et al. (2017).
+pub const SELU_ALPHA: f32 = 1.673_263_2;
+pub const SELU_SCALE: f32 = 1.050_701;
+
+// sqrt(2 / pi), used by the tanh approximation of GELU.
+const GELU_COEFF: f32 = 0.797_884_6;
+
+#[inline(always)]
+fn gelu_inner(x: f32) -> f32 {
+    GELU_COEFF * (x + 0.044715 * x * x * x)
+}
+
+#[inline(always)]
+fn sigmoid(x: f32) -> f32 {
+    if x >= 0.0 {
+        1.0 / (1.0 + (-x).exp())
+    } else {
+        let e = x.exp();
+        e / (1.0 + e)
+    }
+}
+
+#[inline(always)]
+fn softplus(x: f32) -> f32 {
+    if x > 20.0 { x } else { x.exp().ln_1p() }
+}
+
+fn softmax_columns(z: &Matrix, target: &mut Matrix) {
+    let rows = z.rows;
+    let cols = z.cols;
+
+    for c in 0..cols {
+        let mut max = f32::NEG_INFINITY;
+        for r in 0..rows {
+            max = max.max(z.data[r * cols + c]);
+        }
+
+        let mut sum = 0.0;
+        for r in 0..rows {
+            let e = (z.data[r * cols + c] - max).exp();
+            target.data[r * cols + c] = e;
+            sum += e;
+        }
+
+        let inv = 1.0 / sum;
+        for r in 0..rows {
+            target.data[r * cols + c] *= inv;
+        }
+    }
+}
+
+// Numerically stable log(sum(exp(x))).
+pub fn log_sum_exp(values: &[f32]) -> f32 {
+    let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    if max == f32::NEG_INFINITY {
+        return max;
+    }
+    let sum: f32 = values.iter().map(|v| (v - max).exp()).sum();
+    max + sum.ln()
+}
+
+// Cross-entropy of softmax(logits) against a target distribution, computed
+// from the logits directly so large values never overflow through exp.
+pub fn softmax_cross_entropy(logits: &[f32], target: &[f32]) -> f32 {
+    let lse = log_sum_exp(logits);
+    target
+        .iter()
+        .zip(logits)
+        .map(|(&t, &z)| t * (lse - z))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Activation; 10] = [
+        Activation::Identity,
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Relu,
+        Activation::LeakyRelu(0.1),
+        Activation::Elu(1.5),
+        Activation::Gelu,
+        Activation::Silu,
+        Activation::Softplus,
+        Activation::Selu,
+    ];
+
+    #[test]
+    fn derivatives_match_central_differences() {
+        let eps = 1e-3;
+        // Points away from the kink at 0 of ReLU and friends.
+        for activation in ALL {
+            for &z in &[-3.0f32, -0.7, -0.2, 0.3, 1.1, 4.0] {
+                let numeric =
+                    (activation.activate(z + eps) - activation.activate(z - eps)) / (2.0 * eps);
+                let analytic = activation.derivative(z);
+                assert!(
+                    (numeric - analytic).abs() < 2e-3,
+                    "{:?} at {}: {} vs {}",
+                    activation,
+                    z,
+                    analytic,
+                    numeric
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(Activation::Relu.activate(-2.0), 0.0);
+        assert_eq!(Activation::LeakyRelu(0.1).activate(-2.0), -0.2);
+        assert_eq!(Activation::Sigmoid.activate(0.0), 0.5);
+        assert_eq!(Activation::SWISH, Activation::Silu);
+        assert!((Activation::Elu(1.0).activate(-1.0) - (-0.632_120_6)).abs() < 1e-6);
+        assert!((Activation::Softplus.activate(0.0) - std::f32::consts::LN_2).abs() < 1e-6);
+        assert!((Activation::Gelu.activate(1.0) - 0.841_192).abs() < 1e-5);
+    }
+
+    #[test]
+    fn softmax_normalizes_each_column() {
+        let z = Matrix {
+            rows: 3,
+            cols: 2,
+            data: vec![1.0, 1000.0, 2.0, 1000.0, 3.0, -1000.0],
+        };
+        let mut a = Matrix::new(3, 2);
+        Activation::Softmax.apply(&z, &mut a);
+        for c in 0..2 {
+            let sum: f32 = (0..3).map(|r| a.data[r * 2 + c]).sum();
+            assert!((sum - 1.0).abs() < 1e-6);
+        }
+        assert!((a.data[4] - 0.665_240_9).abs() < 1e-6);
+        // Huge logits do not overflow.
+        assert_eq!(&[a.data[1], a.data[3], a.data[5]], &[0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn softmax_cross_entropy_gradient_is_output_minus_target() {
+        let logits = [0.2f32, -1.3, 2.0];
+        let target = [0.0f32, 1.0, 0.0];
+        let z = Matrix {
+            rows: 3,
+            cols: 1,
+            data: logits.to_vec(),
+        };
+        let mut softmax = Matrix::new(3, 1);
+        Activation::Softmax.apply(&z, &mut softmax);
+
+        let eps = 1e-3;
+        for i in 0..3 {
+            let mut plus = logits;
+            plus[i] += eps;
+            let mut minus = logits;
+            minus[i] -= eps;
+            let numeric = (softmax_cross_entropy(&plus, &target)
+                - softmax_cross_entropy(&minus, &target))
+                / (2.0 * eps);
+            assert!((numeric - (softmax.data[i] - target[i])).abs() < 1e-3);
+        }
+        let expected = -softmax.data[1].ln();
+        assert!((softmax_cross_entropy(&logits, &target) - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn log_sum_exp_handles_large_and_empty_inputs() {
+        assert!((log_sum_exp(&[1000.0, 1000.0]) - (1000.0 + std::f32::consts::LN_2)).abs() < 1e-3);
+        assert_eq!(log_sum_exp(&[]), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn sigmoid_and_softplus_are_stable_for_large_inputs() {
+        assert_eq!(Activation::Sigmoid.activate(-200.0), 0.0);
+        assert_eq!(Activation::Sigmoid.activate(200.0), 1.0);
+        assert_eq!(Activation::Softplus.activate(100.0), 100.0);
+        assert!(Activation::Softplus.activate(-100.0).is_finite());
+    }
+}
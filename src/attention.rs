@@ -0,0 +1,449 @@
+use rand::rngs::StdRng;
+
+use crate::conv::sequence_shape;
+use crate::error::{Result, RustingBrainError};
+use crate::initializer::Initializer;
+use crate::layer::{Cache, Layer, Pass, add_to};
+use crate::matrix::Matrix;
+
+// Attention over one sequence, with features in rows and tokens in columns
+// (`d_k x L` queries and keys, `d_v x L` values). Returns the `d_v x L`
+// output and the `L x L` attention weights, one row per query. A causal
+// mask keeps every query from attending to later tokens.
+pub fn scaled_dot_product_attention(
+    q: &Matrix,
+    k: &Matrix,
+    v: &Matrix,
+    causal: bool,
+) -> (Matrix, Matrix) {
+    let len = q.cols;
+    let scale = 1.0 / (q.rows as f32).sqrt();
+    let mut weights = Matrix::new(len, k.cols);
+    q.dot_self_transposed(k, &mut weights);
+
+    for i in 0..len {
+        let row = &mut weights.data[i * k.cols..(i + 1) * k.cols];
+        let visible = if causal { i + 1 } else { row.len() };
+        let max = row[..visible]
+            .iter()
+            .fold(f32::NEG_INFINITY, |m, &s| m.max(s * scale));
+        let mut sum = 0.0;
+        for (j, s) in row.iter_mut().enumerate() {
+            *s = if j < visible {
+                (*s * scale - max).exp()
+            } else {
+                0.0
+            };
+            sum += *s;
+        }
+        for s in row.iter_mut() {
+            *s /= sum;
+        }
+    }
+
+    let mut out = Matrix::new(v.rows, len);
+    v.dot_rhs_transposed(&weights, &mut out);
+    (out, weights)
+}
+
+// Errors at `q`, `k` and `v` given the error at the attention output and
+// the weights from the forward pass. Masked weights are zero, so the mask
+// needs no special handling here.
+pub(crate) fn scaled_dot_product_attention_backward(
+    error: &Matrix,
+    q: &Matrix,
+    k: &Matrix,
+    v: &Matrix,
+    weights: &Matrix,
+) -> (Matrix, Matrix, Matrix) {
+    let scale = 1.0 / (q.rows as f32).sqrt();
+    let mut dv = Matrix::new(v.rows, v.cols);
+    error.dot(weights, &mut dv);
+
+    let mut d_scores = Matrix::new(weights.rows, weights.cols);
+    error.dot_self_transposed(v, &mut d_scores);
+    for i in 0..weights.rows {
+        let p = &weights.data[i * weights.cols..(i + 1) * weights.cols];
+        let d = &mut d_scores.data[i * weights.cols..(i + 1) * weights.cols];
+        let dot: f32 = p.iter().zip(d.iter()).map(|(a, b)| a * b).sum();
+        for (d, &p) in d.iter_mut().zip(p) {
+            *d = p * (*d - dot) * scale;
+        }
+    }
+
+    let mut dq = Matrix::new(q.rows, q.cols);
+    k.dot_rhs_transposed(&d_scores, &mut dq);
+    let mut dk = Matrix::new(k.rows, k.cols);
+    q.dot(&d_scores, &mut dk);
+    (dq, dk, dv)
+}
+
+// Token-major view of a `[features, steps]` batch: the same data as a
+// `features x (steps * batch)` matrix, one token of one sample per column.
+pub(crate) fn tokens(mut m: Matrix, features: usize) -> Matrix {
+    m.cols = m.rows * m.cols / features;
+    m.rows = features;
+    m
+}
+
+pub(crate) fn sequences(mut m: Matrix, batch: usize) -> Matrix {
+    m.rows = m.rows * m.cols / batch;
+    m.cols = batch;
+    m
+}
+
+// Rows `rows` of the tokens of sample `n` out of a token-major matrix.
+fn gather(m: &Matrix, rows: std::ops::Range<usize>, n: usize, batch: usize) -> Matrix {
+    let len = m.cols / batch;
+    let mut out = Matrix::new(rows.len(), len);
+    for (r, row) in rows.enumerate() {
+        for t in 0..len {
+            out.data[r * len + t] = m.data[row * m.cols + t * batch + n];
+        }
+    }
+    out
+}
+
+fn scatter(m: &mut Matrix, first_row: usize, n: usize, batch: usize, part: &Matrix) {
+    let len = part.cols;
+    for r in 0..part.rows {
+        for t in 0..len {
+            m.data[(first_row + r) * m.cols + t * batch + n] = part.data[r * len + t];
+        }
+    }
+}
+
+fn add_bias(m: &mut Matrix, bias: &Matrix) {
+    for (row, &b) in m.data.chunks_mut(m.cols).zip(&bias.data) {
+        for v in row {
+            *v += b;
+        }
+    }
+}
+
+fn add_row_sums(gradient: &mut Matrix, error: &Matrix) {
+    for (g, row) in gradient.data.iter_mut().zip(error.data.chunks(error.cols)) {
+        *g += row.iter().sum::<f32>();
+    }
+}
+
+// Multi-head self-attention over `[d_model, steps]` sequences. Queries, keys
+// and values come from one fused projection, every head attends over its
+// `d_model / heads` slice, and the concatenated heads are projected back to
+// `d_model`.
+pub struct MultiHeadAttention {
+    heads: usize,
+    causal: bool,
+    weight_init: Initializer,
+    model_dim: usize,
+    // Query, key and value projections stacked into `3 * d_model` rows.
+    qkv_weights: Matrix,
+    qkv_biases: Matrix,
+    output_weights: Matrix,
+    output_biases: Matrix,
+    output_shape: Vec<usize>,
+}
+
+impl MultiHeadAttention {
+    pub fn new(heads: usize) -> Self {
+        MultiHeadAttention {
+            heads,
+            causal: false,
+            weight_init: Initializer::XavierUniform,
+            model_dim: 0,
+            qkv_weights: Matrix::new(0, 0),
+            qkv_biases: Matrix::new(0, 0),
+            output_weights: Matrix::new(0, 0),
+            output_biases: Matrix::new(0, 0),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_causal_mask(mut self, causal: bool) -> Self {
+        self.causal = causal;
+        self
+    }
+
+    // Initializer of the projection weights; biases start at zero.
+    pub fn with_initializer(mut self, weight_init: Initializer) -> Self {
+        self.weight_init = weight_init;
+        self
+    }
+
+    pub fn heads(&self) -> usize {
+        self.heads
+    }
+
+    fn head_dim(&self) -> usize {
+        self.model_dim / self.heads
+    }
+}
+
+impl Layer for MultiHeadAttention {
+    fn name(&self) -> &'static str {
+        "multi_head_attention"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let (model_dim, _) = sequence_shape(self.name(), input_shape)?;
+        if self.heads == 0 || model_dim % self.heads != 0 {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "{} heads do not divide a model dimension of {}",
+                self.heads, model_dim
+            )));
+        }
+        self.model_dim = model_dim;
+        self.qkv_weights = self.weight_init.initialize(3 * model_dim, model_dim, rng);
+        self.qkv_biases = Matrix::new(3 * model_dim, 1);
+        self.output_weights = self.weight_init.initialize(model_dim, model_dim, rng);
+        self.output_biases = Matrix::new(model_dim, 1);
+        self.output_shape = input_shape.to_vec();
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        vec![
+            &self.qkv_weights,
+            &self.qkv_biases,
+            &self.output_weights,
+            &self.output_biases,
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        vec![
+            &mut self.qkv_weights,
+            &mut self.qkv_biases,
+            &mut self.output_weights,
+            &mut self.output_biases,
+        ]
+    }
+
+    // Caches the token-major input, the projections, the concatenated heads
+    // and then the attention weights of every sample and head.
+    fn forward(&self, input: &Matrix, cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let (d, dk, batch) = (self.model_dim, self.head_dim(), input.cols);
+        let x = tokens(input.clone(), d);
+        let mut qkv = Matrix::new(3 * d, x.cols);
+        self.qkv_weights.dot(&x, &mut qkv);
+        add_bias(&mut qkv, &self.qkv_biases);
+
+        let mut heads = Matrix::new(d, x.cols);
+        let mut weights = Vec::with_capacity(batch * self.heads);
+        for n in 0..batch {
+            for h in 0..self.heads {
+                let q = gather(&qkv, h * dk..(h + 1) * dk, n, batch);
+                let k = gather(&qkv, d + h * dk..d + (h + 1) * dk, n, batch);
+                let v = gather(&qkv, 2 * d + h * dk..2 * d + (h + 1) * dk, n, batch);
+                let (out, w) = scaled_dot_product_attention(&q, &k, &v, self.causal);
+                scatter(&mut heads, h * dk, n, batch, &out);
+                weights.push(w);
+            }
+        }
+
+        let mut out = Matrix::new(d, x.cols);
+        self.output_weights.dot(&heads, &mut out);
+        add_bias(&mut out, &self.output_biases);
+        cache.push(x);
+        cache.push(qkv);
+        cache.push(heads);
+        cache.extend(weights);
+        sequences(out, batch)
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        let (d, dk, batch) = (self.model_dim, self.head_dim(), error.cols);
+        let (x, qkv, heads) = (&cache[0], &cache[1], &cache[2]);
+        let error = tokens(error.clone(), d);
+
+        let mut d_output_weights = Matrix::new(d, d);
+        error.dot_rhs_transposed(heads, &mut d_output_weights);
+        add_to(&mut gradients[2], &d_output_weights);
+        add_row_sums(&mut gradients[3], &error);
+        let mut d_heads = Matrix::new(d, error.cols);
+        self.output_weights
+            .dot_self_transposed(&error, &mut d_heads);
+
+        let mut d_qkv = Matrix::new(3 * d, error.cols);
+        for n in 0..batch {
+            for h in 0..self.heads {
+                let rows = |offset: usize| offset + h * dk..offset + (h + 1) * dk;
+                let q = gather(qkv, rows(0), n, batch);
+                let k = gather(qkv, rows(d), n, batch);
+                let v = gather(qkv, rows(2 * d), n, batch);
+                let e = gather(&d_heads, rows(0), n, batch);
+                let w = &cache[3 + n * self.heads + h];
+                let (dq, dk_, dv) = scaled_dot_product_attention_backward(&e, &q, &k, &v, w);
+                scatter(&mut d_qkv, h * dk, n, batch, &dq);
+                scatter(&mut d_qkv, d + h * dk, n, batch, &dk_);
+                scatter(&mut d_qkv, 2 * d + h * dk, n, batch, &dv);
+            }
+        }
+
+        let mut d_qkv_weights = Matrix::new(3 * d, d);
+        d_qkv.dot_rhs_transposed(x, &mut d_qkv_weights);
+        add_to(&mut gradients[0], &d_qkv_weights);
+        add_row_sums(&mut gradients[1], &d_qkv);
+        let mut input_error = Matrix::new(d, error.cols);
+        self.qkv_weights
+            .dot_self_transposed(&d_qkv, &mut input_error);
+        sequences(input_error, batch)
+    }
+}
+
+// Adds a position signal to `[d_model, steps]` sequences: the fixed sine
+// and cosine waves of the original Transformer, or a learned table.
+pub struct PositionalEncoding {
+    learned: bool,
+    encoding: Matrix,
+    output_shape: Vec<usize>,
+}
+
+impl PositionalEncoding {
+    pub fn sinusoidal() -> Self {
+        Self::new(false)
+    }
+
+    pub fn learned() -> Self {
+        Self::new(true)
+    }
+
+    fn new(learned: bool) -> Self {
+        PositionalEncoding {
+            learned,
+            encoding: Matrix::new(0, 0),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn encoding(&self) -> &Matrix {
+        &self.encoding
+    }
+}
+
+impl Layer for PositionalEncoding {
+    fn name(&self) -> &'static str {
+        "positional_encoding"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let (model_dim, steps) = sequence_shape(self.name(), input_shape)?;
+        self.encoding = if self.learned {
+            Matrix::random_normal(model_dim * steps, 1, 0.0, 0.02, rng)
+        } else {
+            let mut encoding = Matrix::new(model_dim * steps, 1);
+            for f in 0..model_dim {
+                let rate = 10000f32.powf(-((f - f % 2) as f32) / model_dim as f32);
+                for t in 0..steps {
+                    let angle = t as f32 * rate;
+                    encoding.data[f * steps + t] =
+                        if f % 2 == 0 { angle.sin() } else { angle.cos() };
+                }
+            }
+            encoding
+        };
+        self.output_shape = input_shape.to_vec();
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        if self.learned {
+            vec![&self.encoding]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        if self.learned {
+            vec![&mut self.encoding]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn forward(&self, input: &Matrix, _cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let mut out = input.clone();
+        add_bias(&mut out, &self.encoding);
+        out
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        _cache: &Cache,
+        gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        if self.learned {
+            add_row_sums(&mut gradients[0], error);
+        }
+        error.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::{check_layer, random};
+    use rand::SeedableRng;
+
+    #[test]
+    fn multi_head_attention_gradients() {
+        check_layer(MultiHeadAttention::new(2), &[4, 3], 2);
+        check_layer(MultiHeadAttention::new(2).with_causal_mask(true), &[4, 3], 2);
+    }
+
+    #[test]
+    fn positional_encoding_gradients() {
+        check_layer(PositionalEncoding::learned(), &[4, 3], 2);
+        check_layer(PositionalEncoding::sinusoidal(), &[4, 3], 2);
+    }
+
+    #[test]
+    fn causal_weights_ignore_later_tokens() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let (q, k, v) = (random(2, 3, &mut rng), random(2, 3, &mut rng), random(2, 3, &mut rng));
+        let (output, weights) = scaled_dot_product_attention(&q, &k, &v, true);
+        for i in 0..3 {
+            let row = &weights.data[i * 3..(i + 1) * 3];
+            assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+            assert!(row[i + 1..].iter().all(|&w| w == 0.0));
+        }
+        // The first token can only attend to itself.
+        assert_eq!([output.data[0], output.data[3]], [v.data[0], v.data[3]]);
+    }
+
+    #[test]
+    fn sinusoidal_encoding_alternates_sine_and_cosine() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut encoding = PositionalEncoding::sinusoidal();
+        encoding.build(&[4, 3], &mut rng).unwrap();
+        let mut pass = Pass {
+            training: false,
+            rng: &mut rng,
+            statistics: None,
+            batch_size: 1,
+        };
+        let out = encoding.forward(&Matrix::new(12, 1), &mut Cache::new(), &mut pass);
+        // Features 0 and 1 turn at rate 1, features 2 and 3 at 1 / 100.
+        assert_eq!(&out.data[0..3], &[0.0, 1f32.sin(), 2f32.sin()]);
+        assert_eq!(&out.data[3..6], &[1.0, 1f32.cos(), 2f32.cos()]);
+        assert!((out.data[7] - 0.01f32.sin()).abs() < 1e-7);
+        assert!(encoding.parameters().is_empty());
+    }
+}
@@ -0,0 +1,531 @@
+use std::cell::{Ref, RefCell};
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::activation::Activation;
+use crate::layer::{activation_backward, add_to};
+use crate::matrix::Matrix;
+
+// Records every operation on its variables so `Variable::backward` can walk
+// them in reverse, e.g. a custom loss:
+//
+//     let tape = Tape::new();
+//     let w = tape.variable(weights);
+//     let x = tape.variable(inputs);
+//     let y = w.matmul(x).activation(Activation::Sigmoid);
+//     let loss = (y - tape.variable(targets)).powf(2.0).mean();
+//     let grads = loss.backward();
+//     let dw = grads.get(w);
+//
+// Unlike `Gradients` and layer errors, the tape holds plain derivatives, not
+// the descent direction. A tape only grows, so build a fresh one per pass.
+pub struct Tape {
+    nodes: RefCell<Vec<TapeNode>>,
+}
+
+struct TapeNode {
+    value: Matrix,
+    op: Op,
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Leaf,
+    MatMul(usize, usize),
+    // Elementwise, with either side broadcast along axes of length one.
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Scale(usize, f32),
+    Activation(usize, Activation),
+    Exp(usize),
+    Ln(usize),
+    Powf(usize, f32),
+    Sum(usize),
+    Mean(usize),
+    Reshape(usize),
+    Transpose(usize),
+}
+
+// Handle to a value on a `Tape`; cheap to copy.
+#[derive(Clone, Copy)]
+pub struct Variable<'t> {
+    tape: &'t Tape,
+    index: usize,
+}
+
+// Derivatives of the value `backward` started from with respect to every
+// variable recorded before it, intermediate ones included.
+pub struct TapeGradients {
+    grads: Vec<Option<Matrix>>,
+}
+
+impl TapeGradients {
+    pub fn get(&self, variable: Variable) -> Option<&Matrix> {
+        self.grads.get(variable.index)?.as_ref()
+    }
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Tape {
+            nodes: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn variable(&self, value: Matrix) -> Variable<'_> {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn scalar(&self, value: f32) -> Variable<'_> {
+        self.variable(Matrix {
+            rows: 1,
+            cols: 1,
+            data: vec![value],
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&self, value: Matrix, op: Op) -> Variable<'_> {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(TapeNode { value, op });
+        Variable {
+            tape: self,
+            index: nodes.len() - 1,
+        }
+    }
+
+    fn value(&self, index: usize) -> Ref<'_, Matrix> {
+        Ref::map(self.nodes.borrow(), |nodes| &nodes[index].value)
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Shape of an elementwise result: every axis must match or be one.
+fn broadcast_shape(a: &Matrix, b: &Matrix) -> (usize, usize) {
+    let axis = |x: usize, y: usize| {
+        assert!(
+            x == y || x == 1 || y == 1,
+            "cannot broadcast {}x{} with {}x{}",
+            a.rows,
+            a.cols,
+            b.rows,
+            b.cols
+        );
+        x.max(y)
+    };
+    (axis(a.rows, b.rows), axis(a.cols, b.cols))
+}
+
+fn broadcast_at(m: &Matrix, r: usize, c: usize) -> f32 {
+    m.data[(r % m.rows) * m.cols + c % m.cols]
+}
+
+fn elementwise(a: &Matrix, b: &Matrix, f: impl Fn(f32, f32) -> f32) -> Matrix {
+    let (rows, cols) = broadcast_shape(a, b);
+    let mut out = Matrix::new(rows, cols);
+    for r in 0..rows {
+        for c in 0..cols {
+            out.data[r * cols + c] = f(broadcast_at(a, r, c), broadcast_at(b, r, c));
+        }
+    }
+    out
+}
+
+// Sums a gradient back down to the shape of a broadcast operand.
+fn unbroadcast(grad: Matrix, rows: usize, cols: usize) -> Matrix {
+    if (grad.rows, grad.cols) == (rows, cols) {
+        return grad;
+    }
+    let mut out = Matrix::new(rows, cols);
+    for r in 0..grad.rows {
+        for c in 0..grad.cols {
+            out.data[(r % rows) * cols + c % cols] += grad.data[r * grad.cols + c];
+        }
+    }
+    out
+}
+
+fn map(m: &Matrix, f: impl Fn(f32) -> f32) -> Matrix {
+    Matrix {
+        rows: m.rows,
+        cols: m.cols,
+        data: m.data.iter().map(|&x| f(x)).collect(),
+    }
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let mut out = Matrix::new(m.cols, m.rows);
+    for r in 0..m.rows {
+        for c in 0..m.cols {
+            out.data[c * m.rows + r] = m.data[r * m.cols + c];
+        }
+    }
+    out
+}
+
+fn filled(rows: usize, cols: usize, value: f32) -> Matrix {
+    Matrix {
+        rows,
+        cols,
+        data: vec![value; rows * cols],
+    }
+}
+
+impl<'t> Variable<'t> {
+    // Borrows the tape; drop it before recording further operations.
+    pub fn value(&self) -> Ref<'t, Matrix> {
+        self.tape.value(self.index)
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        let value = self.value();
+        (value.rows, value.cols)
+    }
+
+    fn unary(self, op: Op, f: impl FnOnce(&Matrix) -> Matrix) -> Variable<'t> {
+        let value = f(&self.value());
+        self.tape.push(value, op)
+    }
+
+    fn binary(
+        self,
+        other: Variable<'t>,
+        op: Op,
+        f: impl FnOnce(&Matrix, &Matrix) -> Matrix,
+    ) -> Variable<'t> {
+        assert!(
+            std::ptr::eq(self.tape, other.tape),
+            "variables live on different tapes"
+        );
+        let value = f(&self.value(), &other.value());
+        self.tape.push(value, op)
+    }
+
+    pub fn matmul(self, other: Variable<'t>) -> Variable<'t> {
+        self.binary(other, Op::MatMul(self.index, other.index), |a, b| {
+            let mut out = Matrix::new(a.rows, b.cols);
+            a.dot(b, &mut out);
+            out
+        })
+    }
+
+    pub fn scale(self, factor: f32) -> Variable<'t> {
+        self.unary(Op::Scale(self.index, factor), |a| map(a, |x| x * factor))
+    }
+
+    // Softmax normalizes each column, like everywhere else in the crate.
+    pub fn activation(self, activation: Activation) -> Variable<'t> {
+        self.unary(Op::Activation(self.index, activation), |a| {
+            let mut out = Matrix::new(a.rows, a.cols);
+            activation.apply(a, &mut out);
+            out
+        })
+    }
+
+    pub fn exp(self) -> Variable<'t> {
+        self.unary(Op::Exp(self.index), |a| map(a, f32::exp))
+    }
+
+    pub fn ln(self) -> Variable<'t> {
+        self.unary(Op::Ln(self.index), |a| map(a, f32::ln))
+    }
+
+    pub fn powf(self, exponent: f32) -> Variable<'t> {
+        self.unary(Op::Powf(self.index, exponent), |a| {
+            map(a, |x| x.powf(exponent))
+        })
+    }
+
+    // Sum of every element, as a 1x1 variable.
+    pub fn sum(self) -> Variable<'t> {
+        self.unary(Op::Sum(self.index), |a| filled(1, 1, a.data.iter().sum()))
+    }
+
+    pub fn mean(self) -> Variable<'t> {
+        self.unary(Op::Mean(self.index), |a| {
+            filled(1, 1, a.data.iter().sum::<f32>() / a.data.len() as f32)
+        })
+    }
+
+    // Same elements in the same row-major order.
+    pub fn reshape(self, rows: usize, cols: usize) -> Variable<'t> {
+        self.unary(Op::Reshape(self.index), |a| {
+            assert_eq!(
+                rows * cols,
+                a.data.len(),
+                "cannot reshape {}x{} into {}x{}",
+                a.rows,
+                a.cols,
+                rows,
+                cols
+            );
+            Matrix {
+                rows,
+                cols,
+                data: a.data.clone(),
+            }
+        })
+    }
+
+    pub fn transpose(self) -> Variable<'t> {
+        self.unary(Op::Transpose(self.index), transpose)
+    }
+
+    // Derivatives of the sum of this variable's elements, which for a 1x1
+    // loss is the loss itself.
+    pub fn backward(self) -> TapeGradients {
+        let (rows, cols) = self.shape();
+        self.backward_with(filled(rows, cols, 1.0))
+    }
+
+    // Starts from a given derivative of this variable, e.g. the error a
+    // layer receives in `Layer::backward`.
+    pub fn backward_with(self, seed: Matrix) -> TapeGradients {
+        assert_eq!(
+            self.shape(),
+            (seed.rows, seed.cols),
+            "seed does not match the variable's shape"
+        );
+        let nodes = self.tape.nodes.borrow();
+        let mut grads: Vec<Option<Matrix>> = vec![None; self.index + 1];
+        grads[self.index] = Some(seed);
+
+        for i in (0..=self.index).rev() {
+            let (earlier, rest) = grads.split_at_mut(i);
+            let Some(grad) = rest[0].as_ref() else {
+                continue;
+            };
+            let value = |j: usize| &nodes[j].value;
+            let mut send = |j: usize, g: Matrix| match &mut earlier[j] {
+                Some(total) => add_to(total, &g),
+                slot => *slot = Some(g),
+            };
+
+            match nodes[i].op {
+                Op::Leaf => {}
+                Op::MatMul(a, b) => {
+                    let (a_val, b_val) = (value(a), value(b));
+                    let mut da = Matrix::new(a_val.rows, a_val.cols);
+                    grad.dot_rhs_transposed(b_val, &mut da);
+                    let mut db = Matrix::new(b_val.rows, b_val.cols);
+                    a_val.dot_self_transposed(grad, &mut db);
+                    send(a, da);
+                    send(b, db);
+                }
+                Op::Add(a, b) => {
+                    send(a, unbroadcast(grad.clone(), value(a).rows, value(a).cols));
+                    send(b, unbroadcast(grad.clone(), value(b).rows, value(b).cols));
+                }
+                Op::Sub(a, b) => {
+                    send(a, unbroadcast(grad.clone(), value(a).rows, value(a).cols));
+                    send(
+                        b,
+                        unbroadcast(map(grad, |g| -g), value(b).rows, value(b).cols),
+                    );
+                }
+                Op::Mul(a, b) => {
+                    let da = elementwise(grad, value(b), |g, y| g * y);
+                    let db = elementwise(grad, value(a), |g, x| g * x);
+                    send(a, unbroadcast(da, value(a).rows, value(a).cols));
+                    send(b, unbroadcast(db, value(b).rows, value(b).cols));
+                }
+                Op::Scale(a, factor) => send(a, map(grad, |g| g * factor)),
+                Op::Activation(a, activation) => {
+                    send(a, activation_backward(activation, value(a), grad));
+                }
+                Op::Exp(a) => send(a, elementwise(grad, value(i), |g, y| g * y)),
+                Op::Ln(a) => send(a, elementwise(grad, value(a), |g, x| g / x)),
+                Op::Powf(a, p) => {
+                    send(
+                        a,
+                        elementwise(grad, value(a), |g, x| g * p * x.powf(p - 1.0)),
+                    );
+                }
+                Op::Sum(a) => send(a, filled(value(a).rows, value(a).cols, grad.data[0])),
+                Op::Mean(a) => {
+                    let n = value(a).data.len() as f32;
+                    send(a, filled(value(a).rows, value(a).cols, grad.data[0] / n));
+                }
+                Op::Reshape(a) => send(
+                    a,
+                    Matrix {
+                        rows: value(a).rows,
+                        cols: value(a).cols,
+                        data: grad.data.clone(),
+                    },
+                ),
+                Op::Transpose(a) => send(a, transpose(grad)),
+            }
+        }
+        TapeGradients { grads }
+    }
+}
+
+impl<'t> Add for Variable<'t> {
+    type Output = Variable<'t>;
+
+    fn add(self, other: Variable<'t>) -> Variable<'t> {
+        self.binary(other, Op::Add(self.index, other.index), |a, b| {
+            elementwise(a, b, |x, y| x + y)
+        })
+    }
+}
+
+impl<'t> Sub for Variable<'t> {
+    type Output = Variable<'t>;
+
+    fn sub(self, other: Variable<'t>) -> Variable<'t> {
+        self.binary(other, Op::Sub(self.index, other.index), |a, b| {
+            elementwise(a, b, |x, y| x - y)
+        })
+    }
+}
+
+impl<'t> Mul for Variable<'t> {
+    type Output = Variable<'t>;
+
+    // Elementwise; see `matmul` for the matrix product.
+    fn mul(self, other: Variable<'t>) -> Variable<'t> {
+        self.binary(other, Op::Mul(self.index, other.index), |a, b| {
+            elementwise(a, b, |x, y| x * y)
+        })
+    }
+}
+
+impl<'t> Neg for Variable<'t> {
+    type Output = Variable<'t>;
+
+    fn neg(self) -> Variable<'t> {
+        self.scale(-1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::{assert_close, central_difference, random};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // Checks the tape's derivatives of `weights . f(inputs)` against central
+    // differences, for every element of every input.
+    fn check(inputs: &[Matrix], f: impl for<'t> Fn(&[Variable<'t>]) -> Variable<'t>) {
+        let mut rng = StdRng::seed_from_u64(5);
+        let (rows, cols) = {
+            let tape = Tape::new();
+            let vars: Vec<_> = inputs.iter().map(|m| tape.variable(m.clone())).collect();
+            f(&vars).shape()
+        };
+        let weights = random(rows, cols, &mut rng);
+        // The objective and its derivatives in every input.
+        let objective = |inputs: &[Matrix]| {
+            let tape = Tape::new();
+            let vars: Vec<_> = inputs.iter().map(|m| tape.variable(m.clone())).collect();
+            let out = (f(&vars) * tape.variable(weights.clone())).sum();
+            let value = out.value().data[0] as f64;
+            let grads = out.backward();
+            // Inputs `f` never reads have no entry, which is a zero derivative.
+            let grads: Vec<Matrix> = vars
+                .iter()
+                .zip(inputs)
+                .map(|(&v, m)| grads.get(v).cloned().unwrap_or(Matrix::new(m.rows, m.cols)))
+                .collect();
+            (value, grads)
+        };
+
+        let (_, analytic) = objective(inputs);
+        let mut inputs = inputs.to_vec();
+        for (k, analytic) in analytic.iter().enumerate() {
+            for i in 0..inputs[k].data.len() {
+                let mut data = inputs[k].data.clone();
+                let numeric = central_difference(&mut data, i, |data| {
+                    inputs[k].data.copy_from_slice(data);
+                    objective(&inputs).0
+                });
+                inputs[k].data.copy_from_slice(&data);
+                assert_close(&format!("input {} element {}", k, i), analytic.data[i], numeric);
+            }
+        }
+    }
+
+    fn inputs(shapes: &[(usize, usize)]) -> Vec<Matrix> {
+        let mut rng = StdRng::seed_from_u64(9);
+        shapes.iter().map(|&(r, c)| random(r, c, &mut rng)).collect()
+    }
+
+    #[test]
+    fn elementwise_gradients() {
+        let x = inputs(&[(2, 3), (2, 3)]);
+        check(&x, |v| v[0] + v[1]);
+        check(&x, |v| v[0] - v[1]);
+        check(&x, |v| v[0] * v[1]);
+        check(&x, |v| -v[0]);
+        check(&x, |v| v[0].scale(2.5));
+        // A variable used twice collects both contributions.
+        check(&x, |v| v[0] * v[0] + v[1]);
+    }
+
+    #[test]
+    fn broadcast_gradients() {
+        let x = inputs(&[(2, 3), (1, 3), (2, 1), (1, 1)]);
+        check(&x, |v| v[0] + v[1] - v[2]);
+        check(&x, |v| v[2] * v[1]);
+        check(&x, |v| v[0] * v[3] - v[1]);
+    }
+
+    #[test]
+    fn unary_gradients() {
+        let x = inputs(&[(2, 3)]);
+        check(&x, |v| v[0].exp());
+        check(&x, |v| v[0].powf(2.0));
+        check(&x, |v| v[0].powf(3.0));
+        for activation in [Activation::Sigmoid, Activation::Tanh, Activation::Softmax] {
+            check(&x, move |v| v[0].activation(activation));
+        }
+
+        let mut positive = x[0].clone();
+        for p in &mut positive.data {
+            *p = p.abs() + 0.5;
+        }
+        check(&[positive.clone()], |v| v[0].ln());
+        check(&[positive], |v| v[0].powf(0.5));
+    }
+
+    #[test]
+    fn matmul_and_shape_gradients() {
+        let x = inputs(&[(2, 3), (3, 4)]);
+        check(&x, |v| v[0].matmul(v[1]));
+        check(&x, |v| v[0].transpose());
+        check(&x, |v| v[0].reshape(3, 2));
+        check(&x, |v| v[1].transpose().matmul(v[0].reshape(6, 1).reshape(3, 2)));
+    }
+
+    #[test]
+    fn reduction_gradients() {
+        let x = inputs(&[(2, 3)]);
+        check(&x, |v| v[0].sum());
+        check(&x, |v| v[0].mean());
+        check(&x, |v| v[0].exp().sum().ln());
+    }
+
+    #[test]
+    fn composite_gradients() {
+        let x = inputs(&[(2, 3), (3, 4), (2, 4)]);
+        check(&x, |v| {
+            let y = v[0].matmul(v[1]).activation(Activation::Sigmoid);
+            (y - v[2]).powf(2.0).mean()
+        });
+    }
+}
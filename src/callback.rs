@@ -0,0 +1,454 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::trainer::EpochStats;
+
+// Hooks run by `Trainer` around every epoch and mini-batch. All of them
+// default to doing nothing; returning an error aborts training with it.
+pub trait Callback {
+    fn on_train_begin(&mut self, _net: &mut dyn Model) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_epoch_begin(&mut self, _epoch: usize, _net: &mut dyn Model) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_batch_begin(&mut self, _batch: usize, _net: &mut dyn Model) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_batch_end(&mut self, _batch: usize, _loss: f32, _net: &mut dyn Model) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_epoch_end(&mut self, _stats: &EpochStats, _net: &mut dyn Model) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_train_end(&mut self, _net: &mut dyn Model) -> Result<()> {
+        Ok(())
+    }
+
+    // Checked after every epoch; training ends once any callback asks to.
+    fn stop_training(&self) -> bool {
+        false
+    }
+}
+
+// Which epoch metric a callback watches. Lower is always better.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Monitor {
+    TrainLoss,
+    // Falls back to the training loss when no validation data is given.
+    ValLoss,
+}
+
+impl Monitor {
+    pub fn value(&self, stats: &EpochStats) -> f32 {
+        match self {
+            Monitor::TrainLoss => stats.train_loss,
+            Monitor::ValLoss => stats.val_loss.unwrap_or(stats.train_loss),
+        }
+    }
+}
+
+pub struct EarlyStopping {
+    pub monitor: Monitor,
+    pub patience: usize,
+    pub min_delta: f32,
+    pub restore_best_weights: bool,
+    best: f32,
+    wait: usize,
+    stopped: bool,
+    best_parameters: Option<Vec<Matrix>>,
+}
+
+impl EarlyStopping {
+    pub fn new(patience: usize) -> Self {
+        EarlyStopping {
+            monitor: Monitor::ValLoss,
+            patience,
+            min_delta: 0.0,
+            restore_best_weights: false,
+            best: f32::INFINITY,
+            wait: 0,
+            stopped: false,
+            best_parameters: None,
+        }
+    }
+
+    pub fn with_monitor(mut self, monitor: Monitor) -> Self {
+        self.monitor = monitor;
+        self
+    }
+
+    pub fn with_min_delta(mut self, min_delta: f32) -> Self {
+        self.min_delta = min_delta.abs();
+        self
+    }
+
+    pub fn with_restore_best_weights(mut self, restore: bool) -> Self {
+        self.restore_best_weights = restore;
+        self
+    }
+
+    pub fn best(&self) -> f32 {
+        self.best
+    }
+}
+
+impl Callback for EarlyStopping {
+    fn on_train_begin(&mut self, _net: &mut dyn Model) -> Result<()> {
+        self.best = f32::INFINITY;
+        self.wait = 0;
+        self.stopped = false;
+        self.best_parameters = None;
+        Ok(())
+    }
+
+    fn on_epoch_end(&mut self, stats: &EpochStats, net: &mut dyn Model) -> Result<()> {
+        let current = self.monitor.value(stats);
+
+        if current < self.best - self.min_delta {
+            self.best = current;
+            self.wait = 0;
+            if self.restore_best_weights {
+                self.best_parameters = Some(net.snapshot());
+            }
+            return Ok(());
+        }
+
+        self.wait += 1;
+        if self.wait >= self.patience {
+            self.stopped = true;
+        }
+        Ok(())
+    }
+
+    fn on_train_end(&mut self, net: &mut dyn Model) -> Result<()> {
+        if let Some(snapshot) = self.best_parameters.take() {
+            net.restore(snapshot)?;
+        }
+        Ok(())
+    }
+
+    fn stop_training(&self) -> bool {
+        self.stopped
+    }
+}
+
+// Saves the model after each epoch. An `{epoch}` placeholder in the path
+// is replaced with the 1-based epoch number; paths ending in `.json` use the
+// JSON format, everything else the binary one.
+pub struct ModelCheckpoint {
+    pub path: PathBuf,
+    pub monitor: Monitor,
+    pub save_best_only: bool,
+    best: f32,
+}
+
+impl ModelCheckpoint {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        ModelCheckpoint {
+            path: path.as_ref().to_path_buf(),
+            monitor: Monitor::ValLoss,
+            save_best_only: false,
+            best: f32::INFINITY,
+        }
+    }
+
+    pub fn with_monitor(mut self, monitor: Monitor) -> Self {
+        self.monitor = monitor;
+        self
+    }
+
+    pub fn with_save_best_only(mut self, save_best_only: bool) -> Self {
+        self.save_best_only = save_best_only;
+        self
+    }
+
+    fn path_for(&self, epoch: usize) -> PathBuf {
+        let path = self.path.to_string_lossy();
+        if path.contains("{epoch}") {
+            PathBuf::from(path.replace("{epoch}", &(epoch + 1).to_string()))
+        } else {
+            self.path.clone()
+        }
+    }
+}
+
+impl Callback for ModelCheckpoint {
+    fn on_train_begin(&mut self, _net: &mut dyn Model) -> Result<()> {
+        self.best = f32::INFINITY;
+        Ok(())
+    }
+
+    fn on_epoch_end(&mut self, stats: &EpochStats, net: &mut dyn Model) -> Result<()> {
+        if self.save_best_only {
+            let current = self.monitor.value(stats);
+            if current >= self.best {
+                return Ok(());
+            }
+            self.best = current;
+        }
+
+        let path = self.path_for(stats.epoch);
+        if path.extension().is_some_and(|ext| ext == "json") {
+            net.save_json(&path)
+        } else {
+            net.save(&path)
+        }
+    }
+}
+
+// Writes one `epoch,loss,val_loss,lr` row per epoch. `val_loss` is left empty
+// when training without validation data.
+pub struct CsvLogger {
+    pub path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl CsvLogger {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        CsvLogger {
+            path: path.as_ref().to_path_buf(),
+            writer: None,
+        }
+    }
+}
+
+impl Callback for CsvLogger {
+    fn on_train_begin(&mut self, _net: &mut dyn Model) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        writeln!(writer, "epoch,loss,val_loss,lr")?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn on_epoch_end(&mut self, stats: &EpochStats, _net: &mut dyn Model) -> Result<()> {
+        if let Some(writer) = &mut self.writer {
+            let val_loss = stats.val_loss.map(|v| v.to_string()).unwrap_or_default();
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                stats.epoch + 1,
+                stats.train_loss,
+                val_loss,
+                stats.learning_rate
+            )?;
+            // Flushed every epoch so the log is readable while training runs.
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn on_train_end(&mut self, _net: &mut dyn Model) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::network::Network;
+    use crate::trainer::Trainer;
+
+    fn stats(epoch: usize, loss: f32) -> EpochStats {
+        EpochStats {
+            epoch,
+            train_loss: loss,
+            val_loss: None,
+            learning_rate: 0.1,
+        }
+    }
+
+    fn dataset() -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let inputs: Vec<Vec<f32>> = (0..8).map(|i| vec![i as f32 / 8.0]).collect();
+        let targets = inputs.iter().map(|x| vec![1.0 - x[0]]).collect();
+        (inputs, targets)
+    }
+
+    // Unique path in the temp directory; removed by the caller.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rb-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn early_stopping_stops_after_exactly_patience_stalled_epochs() {
+        let (inputs, targets) = dataset();
+        // A zero rate and a fixed order keep the loss flat, so only the first
+        // epoch improves.
+        let mut net = Network::new(vec![1, 2, 1], 0.0);
+        let history = Trainer::new(20, 4)
+            .with_shuffle(false)
+            .with_callback(EarlyStopping::new(3).with_monitor(Monitor::TrainLoss))
+            .fit(&mut net, &inputs, &targets)
+            .unwrap();
+        assert_eq!(history.epochs.len(), 1 + 3);
+
+        let mut stopping = EarlyStopping::new(2);
+        stopping.on_train_begin(&mut net).unwrap();
+        for (epoch, loss) in [3.0, 2.0, 2.5, 1.0, 1.5].into_iter().enumerate() {
+            stopping.on_epoch_end(&stats(epoch, loss), &mut net).unwrap();
+            assert!(!stopping.stop_training(), "epoch {}", epoch);
+        }
+        stopping.on_epoch_end(&stats(5, 1.0), &mut net).unwrap();
+        assert!(stopping.stop_training());
+    }
+
+    #[test]
+    fn early_stopping_ignores_gains_below_min_delta() {
+        let mut net = Network::new(vec![1, 1], 0.1);
+        let mut stopping = EarlyStopping::new(2).with_min_delta(0.1);
+        stopping.on_train_begin(&mut net).unwrap();
+        for (epoch, loss) in [1.0, 0.5, 0.45, 0.42, 0.41, 0.40].into_iter().enumerate() {
+            stopping.on_epoch_end(&stats(epoch, loss), &mut net).unwrap();
+        }
+        assert_eq!(stopping.best(), 0.5);
+        assert!(stopping.stop_training());
+
+        // A new run starts from scratch.
+        stopping.on_train_begin(&mut net).unwrap();
+        assert!(!stopping.stop_training());
+        assert_eq!(stopping.best(), f32::INFINITY);
+    }
+
+    #[test]
+    fn early_stopping_restores_the_best_weights() {
+        let mut net = Network::new_with_seed(vec![1, 2, 1], 0.1, 4);
+        let mut stopping = EarlyStopping::new(5).with_restore_best_weights(true);
+        stopping.on_train_begin(&mut net).unwrap();
+        stopping.on_epoch_end(&stats(0, 1.0), &mut net).unwrap();
+        let best = net.weights()[0].data.clone();
+
+        net.train(&[0.5], &[3.0]);
+        stopping.on_epoch_end(&stats(1, 2.0), &mut net).unwrap();
+        assert_ne!(net.weights()[0].data, best);
+        stopping.on_train_end(&mut net).unwrap();
+        assert_eq!(net.weights()[0].data, best);
+    }
+
+    #[test]
+    fn trainer_runs_every_hook_and_honours_stop_requests() {
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+        impl Callback for Recorder {
+            fn on_train_begin(&mut self, _net: &mut dyn Model) -> Result<()> {
+                self.0.borrow_mut().push("train".to_string());
+                Ok(())
+            }
+            fn on_epoch_begin(&mut self, epoch: usize, _net: &mut dyn Model) -> Result<()> {
+                self.0.borrow_mut().push(format!("epoch {}", epoch));
+                Ok(())
+            }
+            fn on_batch_end(&mut self, batch: usize, _loss: f32, _net: &mut dyn Model) -> Result<()> {
+                self.0.borrow_mut().push(format!("batch {}", batch));
+                Ok(())
+            }
+            fn on_train_end(&mut self, _net: &mut dyn Model) -> Result<()> {
+                self.0.borrow_mut().push("end".to_string());
+                Ok(())
+            }
+            fn stop_training(&self) -> bool {
+                self.0.borrow().len() >= 6
+            }
+        }
+
+        let (inputs, targets) = dataset();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut net = Network::new(vec![1, 2, 1], 0.1);
+        let history = Trainer::new(10, 5)
+            .with_callback(Recorder(log.clone()))
+            .fit(&mut net, &inputs, &targets)
+            .unwrap();
+        assert_eq!(history.epochs.len(), 2);
+        assert_eq!(
+            *log.borrow(),
+            ["train", "epoch 0", "batch 0", "batch 1", "epoch 1", "batch 0", "batch 1", "end"]
+        );
+    }
+
+    #[test]
+    fn checkpoint_saves_every_or_only_improving_epochs() {
+        let mut net = Network::new_with_seed(vec![1, 2, 1], 0.1, 5);
+        let pattern = temp_path("ckpt-{epoch}.json");
+        let mut checkpoint = ModelCheckpoint::new(&pattern)
+            .with_monitor(Monitor::TrainLoss)
+            .with_save_best_only(true);
+        checkpoint.on_train_begin(&mut net).unwrap();
+        for (epoch, loss) in [0.5, 0.7, 0.4].into_iter().enumerate() {
+            checkpoint.on_epoch_end(&stats(epoch, loss), &mut net).unwrap();
+        }
+        let saved: Vec<bool> = (1..=3)
+            .map(|e| temp_path(&format!("ckpt-{}.json", e)).exists())
+            .collect();
+        assert_eq!(saved, [true, false, true]);
+        let loaded = Network::load_json(temp_path("ckpt-3.json")).unwrap();
+        assert_eq!(loaded.weights()[0].data, net.weights()[0].data);
+        for e in [1, 3] {
+            std::fs::remove_file(temp_path(&format!("ckpt-{}.json", e))).unwrap();
+        }
+
+        let path = temp_path("ckpt.bin");
+        let mut checkpoint = ModelCheckpoint::new(&path);
+        checkpoint.on_epoch_end(&stats(0, 1.0), &mut net).unwrap();
+        assert!(Network::load(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn csv_logger_writes_one_row_per_epoch() {
+        let (inputs, targets) = dataset();
+        let path = temp_path("log.csv");
+        let mut net = Network::new(vec![1, 2, 1], 0.1);
+        let history = Trainer::new(3, 4)
+            .with_callback(CsvLogger::new(&path))
+            .fit_with_validation(&mut net, &inputs, &targets, &inputs, &targets)
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "epoch,loss,val_loss,lr");
+        let last = &history.epochs[2];
+        assert_eq!(
+            lines[3],
+            format!("3,{},{},0.1", last.train_loss, last.val_loss.unwrap())
+        );
+    }
+
+    #[test]
+    fn early_stopping_restores_any_model() {
+        use crate::layer::{Dense, NormalizationLayer};
+        use crate::sequential::Sequential;
+        let mut model = Sequential::new(vec![1], 0.1)
+            .with_layer(Dense::new(3))
+            .with_layer(NormalizationLayer::batch())
+            .with_layer(Dense::new(1))
+            .with_seed(2);
+        let (inputs, targets) = dataset();
+        let mut stopping = EarlyStopping::new(5).with_restore_best_weights(true);
+        stopping.on_train_begin(&mut model).unwrap();
+        stopping.on_epoch_end(&stats(0, 1.0), &mut model).unwrap();
+        let best = model.snapshot();
+
+        // Running statistics are rolled back along with the parameters.
+        model.train_batch_parallel(&inputs, &targets, 2);
+        stopping.on_epoch_end(&stats(1, 2.0), &mut model).unwrap();
+        stopping.on_train_end(&mut model).unwrap();
+        for (a, b) in model.snapshot().iter().zip(&best) {
+            assert_eq!(a.data, b.data);
+        }
+    }
+}
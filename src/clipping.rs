@@ -0,0 +1,133 @@
+use crate::matrix::Matrix;
+use crate::network::Gradients;
+
+// Optional limit applied to the gradients right before the optimizer step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientClipping {
+    // Clamp every component to [-limit, limit].
+    Value(f32),
+    // Rescale each weight/bias gradient whose own L2 norm exceeds the limit.
+    Norm(f32),
+    // Rescale all gradients together when their global L2 norm exceeds the limit.
+    GlobalNorm(f32),
+}
+
+impl GradientClipping {
+    // Clips `grads` in place and returns their global norm from before clipping.
+    pub fn apply(&self, grads: &mut Gradients) -> f32 {
+        self.clip(&mut grads.all_mut().collect::<Vec<_>>())
+    }
+
+    // Same as `apply` for a flat list of gradient tensors.
+    pub fn apply_to(&self, grads: &mut [Matrix]) -> f32 {
+        self.clip(&mut grads.iter_mut().collect::<Vec<_>>())
+    }
+
+    pub(crate) fn clip(&self, grads: &mut [&mut Matrix]) -> f32 {
+        match *self {
+            GradientClipping::Value(limit) => {
+                let norm = global_norm(grads.iter().map(|m| &**m));
+                clip_by_value(grads, limit);
+                norm
+            }
+            GradientClipping::Norm(max_norm) => {
+                let norm = global_norm(grads.iter().map(|m| &**m));
+                clip_by_norm(grads, max_norm);
+                norm
+            }
+            GradientClipping::GlobalNorm(max_norm) => clip_by_global_norm(grads, max_norm),
+        }
+    }
+}
+
+// L2 norm over every gradient taken together.
+pub(crate) fn global_norm<'a>(grads: impl IntoIterator<Item = &'a Matrix>) -> f32 {
+    grads
+        .into_iter()
+        .flat_map(|m| &m.data)
+        .map(|x| x * x)
+        .sum::<f32>()
+        .sqrt()
+}
+
+pub(crate) fn clip_by_value(grads: &mut [&mut Matrix], limit: f32) {
+    for x in grads.iter_mut().flat_map(|m| &mut m.data) {
+        *x = x.clamp(-limit, limit);
+    }
+}
+
+// Rescales each gradient tensor on its own so that none has an L2 norm
+// above `max_norm`.
+pub(crate) fn clip_by_norm(grads: &mut [&mut Matrix], max_norm: f32) {
+    for m in grads.iter_mut() {
+        let norm = global_norm([&**m]);
+        if norm > max_norm {
+            scale(m, max_norm / norm);
+        }
+    }
+}
+
+// Rescales all gradients by the same factor so their global norm is at most
+// `max_norm`, keeping the update direction. Returns the norm from before
+// clipping.
+pub(crate) fn clip_by_global_norm(grads: &mut [&mut Matrix], max_norm: f32) -> f32 {
+    let norm = global_norm(grads.iter().map(|m| &**m));
+    if norm > max_norm {
+        for m in grads.iter_mut() {
+            scale(m, max_norm / norm);
+        }
+    }
+    norm
+}
+
+fn scale(m: &mut Matrix, factor: f32) {
+    for x in &mut m.data {
+        *x *= factor;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1x2 weight gradient of norm 5 and a bias gradient of norm 12.
+    fn grads() -> Gradients {
+        let mut grads = Gradients::new(&[2, 1]);
+        grads.d_weights[0].data = vec![3.0, -4.0];
+        grads.d_biases[0].data = vec![12.0];
+        grads
+    }
+
+    fn values(grads: &Gradients) -> (Vec<f32>, Vec<f32>) {
+        (grads.d_weights[0].data.clone(), grads.d_biases[0].data.clone())
+    }
+
+    #[test]
+    fn value_clipping_clamps_each_component() {
+        let mut g = grads();
+        assert_eq!(GradientClipping::Value(3.5).apply(&mut g), 13.0);
+        assert_eq!(values(&g), (vec![3.0, -3.5], vec![3.5]));
+    }
+
+    #[test]
+    fn norm_clipping_rescales_each_tensor_on_its_own() {
+        let mut g = grads();
+        assert_eq!(GradientClipping::Norm(5.0).apply(&mut g), 13.0);
+        assert_eq!(values(&g), (vec![3.0, -4.0], vec![5.0]));
+
+        let mut g = grads();
+        GradientClipping::Norm(2.5).apply(&mut g);
+        assert_eq!(values(&g), (vec![1.5, -2.0], vec![2.5]));
+    }
+
+    #[test]
+    fn global_norm_clipping_keeps_the_direction() {
+        let mut g = grads();
+        assert_eq!(GradientClipping::GlobalNorm(6.5).apply(&mut g), 13.0);
+        assert_eq!(values(&g), (vec![1.5, -2.0], vec![6.0]));
+
+        let mut g = grads();
+        GradientClipping::GlobalNorm(13.0).apply(&mut g);
+        assert_eq!(values(&g), values(&grads()));
+    }
+}
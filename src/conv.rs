@@ -0,0 +1,616 @@
+use rand::rngs::StdRng;
+
+use crate::activation::Activation;
+use crate::error::{Result, RustingBrainError};
+use crate::initializer::Initializer;
+use crate::layer::{Cache, Layer, Pass, activation_backward, add_to};
+use crate::matrix::Matrix;
+use crate::regularizer::Regularizer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Padding {
+    // No padding; windows only cover the input.
+    Valid,
+    // Zero padding so the output is `ceil(input / stride)` long, split as
+    // evenly as possible with the extra row/column at the end.
+    Same,
+    // Zero padding at the start only, so output step `t` never sees inputs
+    // after `t`. Meant for sequences; on images it pads the top and left.
+    Causal,
+}
+
+// Where the windows of a convolution or pooling layer fall on a
+// `[channels, height, width]` input.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Geometry {
+    pub in_h: usize,
+    pub in_w: usize,
+    pub out_h: usize,
+    pub out_w: usize,
+    pub kernel: (usize, usize),
+    pub stride: (usize, usize),
+    pub dilation: (usize, usize),
+    pub pad_top: usize,
+    pub pad_left: usize,
+}
+
+impl Geometry {
+    pub fn new(
+        input: (usize, usize),
+        kernel: (usize, usize),
+        stride: (usize, usize),
+        dilation: (usize, usize),
+        padding: Padding,
+    ) -> Result<Self> {
+        let (out_h, pad_top) = output_size(input.0, kernel.0, stride.0, dilation.0, padding)?;
+        let (out_w, pad_left) = output_size(input.1, kernel.1, stride.1, dilation.1, padding)?;
+        Ok(Geometry {
+            in_h: input.0,
+            in_w: input.1,
+            out_h,
+            out_w,
+            kernel,
+            stride,
+            dilation,
+            pad_top,
+            pad_left,
+        })
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.kernel.0 * self.kernel.1
+    }
+
+    pub fn output_positions(&self) -> usize {
+        self.out_h * self.out_w
+    }
+
+    // Input position `y * in_w + x` seen by kernel tap (i, j) of output
+    // position (oy, ox), or `None` where the tap lands in the padding.
+    #[inline(always)]
+    pub fn source(&self, oy: usize, ox: usize, i: usize, j: usize) -> Option<usize> {
+        let y = (oy * self.stride.0 + i * self.dilation.0).checked_sub(self.pad_top)?;
+        let x = (ox * self.stride.1 + j * self.dilation.1).checked_sub(self.pad_left)?;
+        (y < self.in_h && x < self.in_w).then_some(y * self.in_w + x)
+    }
+}
+
+// Output length and leading padding along one axis.
+fn output_size(
+    input: usize,
+    kernel: usize,
+    stride: usize,
+    dilation: usize,
+    padding: Padding,
+) -> Result<(usize, usize)> {
+    if kernel == 0 || stride == 0 || dilation == 0 {
+        return Err(RustingBrainError::InvalidArchitecture(
+            "kernel size, stride and dilation must be positive".to_string(),
+        ));
+    }
+    let span = dilation * (kernel - 1) + 1;
+    match padding {
+        Padding::Valid => {
+            if input < span {
+                return Err(RustingBrainError::InvalidArchitecture(format!(
+                    "window of {} does not fit an input of {}",
+                    span, input
+                )));
+            }
+            Ok(((input - span) / stride + 1, 0))
+        }
+        Padding::Same => {
+            let out = input.div_ceil(stride);
+            let total = ((out - 1) * stride + span).saturating_sub(input);
+            Ok((out, total / 2))
+        }
+        Padding::Causal => {
+            let lead = span - 1;
+            Ok(((input + lead - span) / stride + 1, lead))
+        }
+    }
+}
+
+// Splits a `[channels, height, width]` shape, for layers that need one.
+pub(crate) fn image_shape(name: &str, shape: &[usize]) -> Result<(usize, usize, usize)> {
+    match *shape {
+        [c, h, w] if c > 0 && h > 0 && w > 0 => Ok((c, h, w)),
+        _ => Err(RustingBrainError::InvalidArchitecture(format!(
+            "{} expects a [channels, height, width] input, got shape {:?}",
+            name, shape
+        ))),
+    }
+}
+
+// 2D convolution over `[channels, height, width]` inputs, producing
+// `[filters, out_height, out_width]`. Runs as one sgemm per chunk: the
+// input patches are unrolled (im2col) into a `channels * kh * kw` by
+// `positions * batch` matrix, ordered so the product is already laid out
+// like the output batch matrix.
+pub struct Conv2D {
+    filters: usize,
+    kernel: (usize, usize),
+    stride: (usize, usize),
+    dilation: (usize, usize),
+    padding: Padding,
+    activation: Activation,
+    weight_init: Initializer,
+    bias_init: Initializer,
+    regularizer: Option<Regularizer>,
+    channels: usize,
+    geometry: Geometry,
+    // One row per filter, `channels * kh * kw` wide.
+    weights: Matrix,
+    biases: Matrix,
+    output_shape: Vec<usize>,
+}
+
+impl Conv2D {
+    pub fn new(filters: usize, kernel: (usize, usize)) -> Self {
+        Conv2D {
+            filters,
+            kernel,
+            stride: (1, 1),
+            dilation: (1, 1),
+            padding: Padding::Valid,
+            activation: Activation::Identity,
+            weight_init: Initializer::XavierUniform,
+            bias_init: Initializer::Zeros,
+            regularizer: None,
+            channels: 0,
+            geometry: Geometry::default(),
+            weights: Matrix::new(0, 0),
+            biases: Matrix::new(0, 0),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: (usize, usize)) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: (usize, usize)) -> Self {
+        self.dilation = dilation;
+        self
+    }
+
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    pub fn with_initializer(mut self, weight_init: Initializer, bias_init: Initializer) -> Self {
+        self.weight_init = weight_init;
+        self.bias_init = bias_init;
+        self
+    }
+
+    pub fn with_regularizer(mut self, regularizer: Regularizer) -> Self {
+        self.regularizer = Some(regularizer);
+        self
+    }
+
+    pub fn weights(&self) -> &Matrix {
+        &self.weights
+    }
+
+    pub fn biases(&self) -> &Matrix {
+        &self.biases
+    }
+
+    fn patch_size(&self) -> usize {
+        self.channels * self.geometry.window_size()
+    }
+
+    // Unrolls the input patches; column `p * batch + n` holds the patch of
+    // output position `p` in sample `n`.
+    fn im2col(&self, input: &Matrix) -> Matrix {
+        let g = &self.geometry;
+        let n = input.cols;
+        let positions = g.output_positions();
+        let plane = g.in_h * g.in_w;
+        let mut cols = Matrix::new(self.patch_size(), positions * n);
+
+        for c in 0..self.channels {
+            for i in 0..g.kernel.0 {
+                for j in 0..g.kernel.1 {
+                    let k = (c * g.kernel.0 + i) * g.kernel.1 + j;
+                    let row = &mut cols.data[k * positions * n..(k + 1) * positions * n];
+                    for oy in 0..g.out_h {
+                        for ox in 0..g.out_w {
+                            let Some(src) = g.source(oy, ox, i, j) else {
+                                continue;
+                            };
+                            let p = oy * g.out_w + ox;
+                            let from = (c * plane + src) * n;
+                            row[p * n..(p + 1) * n].copy_from_slice(&input.data[from..from + n]);
+                        }
+                    }
+                }
+            }
+        }
+        cols
+    }
+
+    // Scatters patch errors back onto the input, summing where patches
+    // overlap.
+    fn col2im(&self, cols: &Matrix, n: usize) -> Matrix {
+        let g = &self.geometry;
+        let positions = g.output_positions();
+        let plane = g.in_h * g.in_w;
+        let mut input_error = Matrix::new(self.channels * plane, n);
+
+        for c in 0..self.channels {
+            for i in 0..g.kernel.0 {
+                for j in 0..g.kernel.1 {
+                    let k = (c * g.kernel.0 + i) * g.kernel.1 + j;
+                    let row = &cols.data[k * positions * n..(k + 1) * positions * n];
+                    for oy in 0..g.out_h {
+                        for ox in 0..g.out_w {
+                            let Some(src) = g.source(oy, ox, i, j) else {
+                                continue;
+                            };
+                            let p = oy * g.out_w + ox;
+                            let to = (c * plane + src) * n;
+                            for (e, d) in input_error.data[to..to + n]
+                                .iter_mut()
+                                .zip(&row[p * n..(p + 1) * n])
+                            {
+                                *e += d;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        input_error
+    }
+}
+
+impl Layer for Conv2D {
+    fn name(&self) -> &'static str {
+        "conv2d"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let (channels, h, w) = image_shape(self.name(), input_shape)?;
+        if self.filters == 0 {
+            return Err(RustingBrainError::InvalidArchitecture(
+                "conv2d layer with zero filters".to_string(),
+            ));
+        }
+        self.channels = channels;
+        self.geometry = Geometry::new(
+            (h, w),
+            self.kernel,
+            self.stride,
+            self.dilation,
+            self.padding,
+        )?;
+        self.weights = self
+            .weight_init
+            .initialize(self.filters, self.patch_size(), rng);
+        self.biases = self.bias_init.initialize(self.filters, 1, rng);
+        self.output_shape = vec![self.filters, self.geometry.out_h, self.geometry.out_w];
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        vec![&self.weights, &self.biases]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        vec![&mut self.weights, &mut self.biases]
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let n = input.cols;
+        let positions = self.geometry.output_positions();
+        let cols = self.im2col(input);
+
+        // `filters x (positions * batch)` has the same data layout as the
+        // `(filters * positions) x batch` output.
+        let mut z = Matrix::new(self.filters, positions * n);
+        self.weights.dot(&cols, &mut z);
+        for f in 0..self.filters {
+            let b = self.biases.data[f];
+            for v in &mut z.data[f * positions * n..(f + 1) * positions * n] {
+                *v += b;
+            }
+        }
+        z.rows = self.filters * positions;
+        z.cols = n;
+
+        let mut a = Matrix::new(z.rows, n);
+        self.activation.apply(&z, &mut a);
+        cache.push(cols);
+        cache.push(z);
+        a
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        let error = activation_backward(self.activation, &cache[1], error);
+        self.backward_from_logits(&error, cache, gradients, pass)
+    }
+
+    fn output_activation<'c>(&self, cache: &'c Cache) -> Option<(Activation, &'c Matrix)> {
+        Some((self.activation, &cache[1]))
+    }
+
+    fn backward_from_logits(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        let cols = &cache[0];
+        let n = error.cols;
+        let width = self.geometry.output_positions() * n;
+        let error = Matrix {
+            rows: self.filters,
+            cols: width,
+            data: error.data.clone(),
+        };
+
+        let mut d_weights = Matrix::new(self.weights.rows, self.weights.cols);
+        error.dot_rhs_transposed(cols, &mut d_weights);
+        add_to(&mut gradients[0], &d_weights);
+        for f in 0..self.filters {
+            gradients[1].data[f] += error.data[f * width..(f + 1) * width].iter().sum::<f32>();
+        }
+
+        let mut d_cols = Matrix::new(cols.rows, cols.cols);
+        self.weights.dot_self_transposed(&error, &mut d_cols);
+        self.col2im(&d_cols, n)
+    }
+
+    fn regularization_loss(&self) -> f32 {
+        match &self.regularizer {
+            Some(reg) if reg.include_biases => {
+                reg.penalty(&self.weights) + reg.penalty(&self.biases)
+            }
+            Some(reg) => reg.penalty(&self.weights),
+            None => 0.0,
+        }
+    }
+
+    fn add_regularization_gradients(&self, gradients: &mut [Matrix]) {
+        if let Some(reg) = &self.regularizer {
+            reg.add_gradient(&self.weights, &mut gradients[0]);
+            if reg.include_biases {
+                reg.add_gradient(&self.biases, &mut gradients[1]);
+            }
+        }
+    }
+}
+
+// Splits a `[channels, length]` shape, for the sequence layers.
+pub(crate) fn sequence_shape(name: &str, shape: &[usize]) -> Result<(usize, usize)> {
+    match *shape {
+        [c, l] if c > 0 && l > 0 => Ok((c, l)),
+        _ => Err(RustingBrainError::InvalidArchitecture(format!(
+            "{} expects a [channels, length] input, got shape {:?}",
+            name, shape
+        ))),
+    }
+}
+
+// 1D convolution over `[channels, length]` inputs, producing
+// `[filters, out_length]`. A sequence is stored like a one-row image, so
+// this runs `Conv2D` with a `1 x kernel` window.
+pub struct Conv1D {
+    conv: Conv2D,
+    output_shape: Vec<usize>,
+}
+
+impl Conv1D {
+    pub fn new(filters: usize, kernel: usize) -> Self {
+        Conv1D {
+            conv: Conv2D::new(filters, (1, kernel)),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Self {
+        self.conv = self.conv.with_stride((1, stride));
+        self
+    }
+
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.conv = self.conv.with_padding(padding);
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: usize) -> Self {
+        self.conv = self.conv.with_dilation((1, dilation));
+        self
+    }
+
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.conv = self.conv.with_activation(activation);
+        self
+    }
+
+    pub fn with_initializer(mut self, weight_init: Initializer, bias_init: Initializer) -> Self {
+        self.conv = self.conv.with_initializer(weight_init, bias_init);
+        self
+    }
+
+    pub fn with_regularizer(mut self, regularizer: Regularizer) -> Self {
+        self.conv = self.conv.with_regularizer(regularizer);
+        self
+    }
+
+    pub fn weights(&self) -> &Matrix {
+        self.conv.weights()
+    }
+
+    pub fn biases(&self) -> &Matrix {
+        self.conv.biases()
+    }
+}
+
+impl Layer for Conv1D {
+    fn name(&self) -> &'static str {
+        "conv1d"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let (channels, length) = sequence_shape(self.name(), input_shape)?;
+        let out = self.conv.build(&[channels, 1, length], rng)?;
+        self.output_shape = vec![out[0], out[2]];
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        self.conv.parameters()
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        self.conv.parameters_mut()
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, pass: &mut Pass) -> Matrix {
+        self.conv.forward(input, cache, pass)
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        self.conv.backward(error, cache, gradients, pass)
+    }
+
+    fn output_activation<'c>(&self, cache: &'c Cache) -> Option<(Activation, &'c Matrix)> {
+        self.conv.output_activation(cache)
+    }
+
+    fn backward_from_logits(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        self.conv
+            .backward_from_logits(error, cache, gradients, pass)
+    }
+
+    fn regularization_loss(&self) -> f32 {
+        self.conv.regularization_loss()
+    }
+
+    fn add_regularization_gradients(&self, gradients: &mut [Matrix]) {
+        self.conv.add_regularization_gradients(gradients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::check_layer;
+    use rand::SeedableRng;
+
+    #[test]
+    fn conv2d_gradients() {
+        check_layer(Conv2D::new(2, (2, 2)), &[2, 4, 4], 2);
+        check_layer(
+            Conv2D::new(2, (3, 3))
+                .with_stride((2, 2))
+                .with_padding(Padding::Same)
+                .with_activation(Activation::Tanh),
+            &[1, 5, 5],
+            2,
+        );
+        check_layer(
+            Conv2D::new(1, (2, 2)).with_dilation((2, 2)),
+            &[2, 5, 5],
+            2,
+        );
+    }
+
+    #[test]
+    fn output_shapes_follow_the_padding() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut valid = Conv2D::new(4, (3, 3));
+        assert_eq!(valid.build(&[2, 7, 6], &mut rng).unwrap(), vec![4, 5, 4]);
+        let mut same = Conv2D::new(4, (3, 3)).with_stride((2, 2)).with_padding(Padding::Same);
+        assert_eq!(same.build(&[2, 7, 6], &mut rng).unwrap(), vec![4, 4, 3]);
+        let mut dilated = Conv2D::new(1, (2, 2)).with_dilation((3, 3));
+        assert_eq!(dilated.build(&[1, 4, 5], &mut rng).unwrap(), vec![1, 1, 2]);
+
+        let mut too_wide = Conv2D::new(1, (3, 3)).with_dilation((2, 2));
+        assert!(too_wide.build(&[1, 4, 4], &mut rng).is_err());
+        assert!(Conv2D::new(1, (2, 2)).build(&[16], &mut rng).is_err());
+    }
+
+    #[test]
+    fn same_padding_puts_the_extra_row_at_the_end() {
+        // Input 4, kernel 3, stride 2: two outputs covering 5 positions.
+        let geometry = Geometry::new((4, 4), (3, 3), (2, 2), (1, 1), Padding::Same).unwrap();
+        assert_eq!((geometry.out_h, geometry.pad_top), (2, 0));
+        assert_eq!(geometry.source(1, 1, 2, 2), None);
+        assert_eq!(geometry.source(1, 1, 1, 1), Some(3 * 4 + 3));
+    }
+
+    #[test]
+    fn conv1d_gradients() {
+        check_layer(Conv1D::new(2, 3), &[2, 6], 2);
+        check_layer(
+            Conv1D::new(2, 2)
+                .with_padding(Padding::Causal)
+                .with_dilation(2),
+            &[2, 6],
+            2,
+        );
+        check_layer(Conv1D::new(3, 3).with_stride(2).with_padding(Padding::Same), &[1, 7], 2);
+    }
+
+    #[test]
+    fn causal_outputs_only_see_the_past() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut conv = Conv1D::new(1, 3).with_padding(Padding::Causal).with_dilation(2);
+        assert_eq!(conv.build(&[1, 6], &mut rng).unwrap(), vec![1, 6]);
+
+        let forward = |input: Vec<f32>| {
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut pass = Pass {
+                training: false,
+                rng: &mut rng,
+                statistics: None,
+                batch_size: 1,
+            };
+            conv.forward(&Matrix::from_columns(&[input]), &mut Cache::new(), &mut pass)
+                .data
+        };
+        // Changing the last step leaves every earlier output alone.
+        let a = forward(vec![0.1, 0.2, 0.3, 0.4, 0.5, 0.6]);
+        let b = forward(vec![0.1, 0.2, 0.3, 0.4, 0.5, -3.0]);
+        assert_eq!(a[..5], b[..5]);
+        assert_ne!(a[5], b[5]);
+    }
+}
@@ -0,0 +1,117 @@
+use rand::Rng;
+
+use crate::activation::{SELU_ALPHA, SELU_SCALE};
+
+// Dropout applied to a hidden layer's outputs during training only. The rate
+// is the probability of dropping each unit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dropout {
+    // Inverted dropout: dropped units become zero and the survivors are
+    // scaled by 1 / (1 - rate), so inference needs no rescaling.
+    Standard(f32),
+    // Alpha dropout for SELU layers: dropped units are set to SELU's negative
+    // saturation value and an affine correction keeps the mean and variance
+    // of the activations unchanged.
+    Alpha(f32),
+}
+
+impl Dropout {
+    pub fn rate(&self) -> f32 {
+        match *self {
+            Dropout::Standard(rate) | Dropout::Alpha(rate) => rate,
+        }
+    }
+
+    // Drops units of `a` in place. `mask` receives the factor each output
+    // ended up multiplied by, which is its derivative for backprop.
+    pub fn apply<R: Rng + ?Sized>(&self, a: &mut [f32], mask: &mut [f32], rng: &mut R) {
+        debug_assert_eq!(a.len(), mask.len());
+
+        let rate = self.rate();
+        if rate <= 0.0 {
+            mask.fill(1.0);
+            return;
+        }
+        let keep_prob = 1.0 - rate;
+
+        match *self {
+            Dropout::Standard(_) => {
+                let scale = 1.0 / keep_prob;
+                for (x, m) in a.iter_mut().zip(mask.iter_mut()) {
+                    *m = if rng.gen_bool(keep_prob as f64) {
+                        scale
+                    } else {
+                        0.0
+                    };
+                    *x *= *m;
+                }
+            }
+            Dropout::Alpha(_) => {
+                let saturation = -SELU_SCALE * SELU_ALPHA;
+                let scale = (keep_prob * (1.0 + rate * saturation * saturation)).powf(-0.5);
+                let shift = -scale * saturation * rate;
+                for (x, m) in a.iter_mut().zip(mask.iter_mut()) {
+                    if rng.gen_bool(keep_prob as f64) {
+                        *m = scale;
+                        *x = scale * *x + shift;
+                    } else {
+                        *m = 0.0;
+                        *x = scale * saturation + shift;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::Activation;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn mean_and_variance(values: &[f32]) -> (f32, f32) {
+        let n = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / n;
+        let var = values.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+        (mean, var)
+    }
+
+    #[test]
+    fn standard_dropout_keeps_the_expected_value() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut a = vec![2.0; 20_000];
+        let mut mask = vec![0.0; a.len()];
+        Dropout::Standard(0.25).apply(&mut a, &mut mask, &mut rng);
+
+        let dropped = a.iter().filter(|&&x| x == 0.0).count() as f32 / a.len() as f32;
+        assert!((dropped - 0.25).abs() < 0.01);
+        assert!((mean_and_variance(&a).0 - 2.0).abs() < 0.03);
+        // The mask is what each unit got multiplied by.
+        assert!(a.iter().zip(&mask).all(|(x, m)| *x == 2.0 * m));
+    }
+
+    #[test]
+    fn alpha_dropout_keeps_selu_statistics() {
+        let mut rng = StdRng::seed_from_u64(2);
+        // SELU keeps unit-Gaussian inputs near zero mean and unit variance.
+        let z = crate::matrix::Matrix::random_normal(1, 50_000, 0.0, 1.0, &mut rng);
+        let mut a: Vec<f32> = z.data.iter().map(|&x| Activation::Selu.activate(x)).collect();
+        let (mean, var) = mean_and_variance(&a);
+        let mut mask = vec![0.0; a.len()];
+        Dropout::Alpha(0.2).apply(&mut a, &mut mask, &mut rng);
+        let (dropped_mean, dropped_var) = mean_and_variance(&a);
+        assert!((dropped_mean - mean).abs() < 0.03, "{} vs {}", dropped_mean, mean);
+        assert!((dropped_var - var).abs() < 0.05, "{} vs {}", dropped_var, var);
+    }
+
+    #[test]
+    fn zero_rate_is_a_no_op() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut a = vec![1.0, -2.0];
+        let mut mask = vec![0.0; 2];
+        Dropout::Alpha(0.0).apply(&mut a, &mut mask, &mut rng);
+        assert_eq!((a, mask), (vec![1.0, -2.0], vec![1.0, 1.0]));
+    }
+}
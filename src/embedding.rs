@@ -0,0 +1,203 @@
+use rand::rngs::StdRng;
+
+use crate::error::{Result, RustingBrainError};
+use crate::initializer::Initializer;
+use crate::layer::{Cache, Layer, Pass, RowGradient};
+use crate::matrix::Matrix;
+
+// Looks up a trainable `vocabulary x dim` table: every sample is a
+// `[steps]` vector of token indices (whole numbers stored as `f32`, exact
+// up to 2^24) and becomes a `[dim, steps]` sequence, the layout the
+// recurrent, convolution and attention layers take. A single categorical
+// feature is just `steps = 1`, followed by `Flatten` if needed.
+//
+// The table's gradient is row-sparse: only the rows of tokens in the batch
+// are reduced across chunks, clipped and handed to the optimizer, so large
+// vocabularies cost no more per step than small ones. Fed straight from a
+// model input, out-of-range indices make the `try_` methods fail with
+// `InvalidIndex`.
+pub struct Embedding {
+    vocabulary: usize,
+    dim: usize,
+    init: Initializer,
+    steps: usize,
+    table: Matrix,
+    output_shape: Vec<usize>,
+}
+
+impl Embedding {
+    pub fn new(vocabulary: usize, dim: usize) -> Self {
+        Embedding {
+            vocabulary,
+            dim,
+            init: Initializer::XavierUniform,
+            steps: 0,
+            table: Matrix::new(0, 0),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_initializer(mut self, init: Initializer) -> Self {
+        self.init = init;
+        self
+    }
+
+    // One row per token.
+    pub fn weights(&self) -> &Matrix {
+        &self.table
+    }
+
+    fn index(&self, value: f32) -> Result<usize> {
+        let index = value as usize;
+        if value >= 0.0 && value.fract() == 0.0 && index < self.vocabulary {
+            return Ok(index);
+        }
+        Err(RustingBrainError::InvalidIndex {
+            index: value,
+            limit: self.vocabulary,
+        })
+    }
+}
+
+impl Layer for Embedding {
+    fn name(&self) -> &'static str {
+        "embedding"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let &[steps] = input_shape else {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "embedding expects [steps] token indices, got shape {:?}",
+                input_shape
+            )));
+        };
+        if self.vocabulary == 0 || self.dim == 0 {
+            return Err(RustingBrainError::InvalidArchitecture(
+                "embedding with an empty vocabulary or zero dimensions".to_string(),
+            ));
+        }
+        self.steps = steps;
+        self.table = self.init.initialize(self.vocabulary, self.dim, rng);
+        self.output_shape = vec![self.dim, steps];
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        vec![&self.table]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        vec![&mut self.table]
+    }
+
+    // A batch only touches the rows of the tokens it holds.
+    fn row_sparse(&self) -> Vec<bool> {
+        vec![true]
+    }
+
+    fn check_input(&self, input: &Matrix) -> Result<()> {
+        for &value in &input.data {
+            self.index(value)?;
+        }
+        Ok(())
+    }
+
+    // Panics on indices `check_input` would reject.
+    fn forward(&self, input: &Matrix, cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let (n, steps, dim) = (input.cols, self.steps, self.dim);
+        let rows: Vec<usize> = input
+            .data
+            .iter()
+            .map(|&v| self.index(v).unwrap_or_else(|e| panic!("{}", e)))
+            .collect();
+        let mut out = Matrix::new(dim * steps, n);
+        for t in 0..steps {
+            for col in 0..n {
+                let row = rows[t * n + col];
+                let vector = &self.table.data[row * dim..(row + 1) * dim];
+                for (f, &v) in vector.iter().enumerate() {
+                    out.data[(f * steps + t) * n + col] = v;
+                }
+            }
+        }
+        cache.push_indices(rows);
+        out
+    }
+
+    // The indices themselves get a zero error; the table's gradient comes
+    // from `backward_rows`.
+    fn backward(
+        &self,
+        error: &Matrix,
+        _cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        Matrix::new(self.steps, error.cols)
+    }
+
+    fn backward_rows(&self, error: &Matrix, cache: &Cache, rows: &mut [RowGradient]) {
+        let (n, steps, dim) = (error.cols, self.steps, self.dim);
+        let indices = cache.indices(0);
+        let mut delta = vec![0.0; dim];
+        for t in 0..steps {
+            for col in 0..n {
+                for (f, d) in delta.iter_mut().enumerate() {
+                    *d = error.data[(f * steps + t) * n + col];
+                }
+                rows[0].add_row(indices[t * n + col], &delta);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::check_layer_on;
+    use rand::SeedableRng;
+
+    fn embedding() -> Embedding {
+        let mut layer = Embedding::new(5, 3);
+        layer.build(&[3], &mut StdRng::seed_from_u64(7)).unwrap();
+        layer
+    }
+
+    #[test]
+    fn table_gradients() {
+        let mut layer = embedding();
+        // Token 1 appears twice and tokens 0 and 4 never do.
+        let tokens = Matrix {
+            rows: 3,
+            cols: 2,
+            data: vec![1.0, 3.0, 2.0, 1.0, 3.0, 2.0],
+        };
+        check_layer_on(&mut layer, tokens, false);
+    }
+
+    #[test]
+    fn rejects_bad_indices() {
+        let layer = embedding();
+        for bad in [5.0, -1.0, 1.5, f32::NAN] {
+            let tokens = Matrix {
+                rows: 3,
+                cols: 1,
+                data: vec![0.0, bad, 4.0],
+            };
+            assert!(matches!(
+                layer.check_input(&tokens),
+                Err(RustingBrainError::InvalidIndex { limit: 5, .. })
+            ));
+        }
+        let tokens = Matrix {
+            rows: 3,
+            cols: 1,
+            data: vec![0.0, 2.0, 4.0],
+        };
+        assert!(layer.check_input(&tokens).is_ok());
+    }
+}
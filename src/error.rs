@@ -0,0 +1,102 @@
+use std::fmt;
+use std::io;
+
+use crate::persistence::VERSION;
+
+#[derive(Debug)]
+pub enum RustingBrainError {
+    ShapeMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    BatchSizeMismatch {
+        inputs: usize,
+        targets: usize,
+    },
+    EmptyBatch,
+    // A training batch smaller than the model can learn from, such as a
+    // single sample through batch normalization.
+    BatchTooSmall {
+        found: usize,
+        minimum: usize,
+    },
+    InvalidArchitecture(String),
+    // An input that has to be a whole number in `0..limit`, such as an
+    // embedding's token index, and is not.
+    InvalidIndex {
+        index: f32,
+        limit: usize,
+    },
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    UnknownActivation(u8),
+    UnknownLoss(u8),
+    UnknownNormalization(u8),
+    InvalidJson(String),
+}
+
+pub type Result<T> = std::result::Result<T, RustingBrainError>;
+
+impl fmt::Display for RustingBrainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RustingBrainError::ShapeMismatch { expected, found } => write!(
+                f,
+                "shape mismatch: expected {}x{}, found {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            RustingBrainError::BatchSizeMismatch { inputs, targets } => write!(
+                f,
+                "batch has {} inputs but {} targets",
+                inputs, targets
+            ),
+            RustingBrainError::EmptyBatch => write!(f, "batch is empty"),
+            RustingBrainError::BatchTooSmall { found, minimum } => write!(
+                f,
+                "training batch has {} samples but the model needs at least {}",
+                found, minimum
+            ),
+            RustingBrainError::InvalidArchitecture(msg) => {
+                write!(f, "invalid architecture: {}", msg)
+            }
+            RustingBrainError::InvalidIndex { index, limit } => {
+                write!(f, "index {} is not a whole number below {}", index, limit)
+            }
+            RustingBrainError::Io(e) => write!(f, "io error: {}", e),
+            RustingBrainError::BadMagic => write!(f, "not a RustingBrain model file"),
+            RustingBrainError::UnsupportedVersion(v) => {
+                write!(f, "unsupported model format version {} (expected at most {})", v, VERSION)
+            }
+            RustingBrainError::Truncated => write!(f, "model file is truncated"),
+            RustingBrainError::UnknownActivation(tag) => {
+                write!(f, "unknown activation tag {}", tag)
+            }
+            RustingBrainError::UnknownLoss(tag) => write!(f, "unknown loss tag {}", tag),
+            RustingBrainError::UnknownNormalization(tag) => {
+                write!(f, "unknown normalization tag {}", tag)
+            }
+            RustingBrainError::InvalidJson(msg) => write!(f, "invalid model json: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RustingBrainError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RustingBrainError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RustingBrainError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            RustingBrainError::Truncated
+        } else {
+            RustingBrainError::Io(e)
+        }
+    }
+}
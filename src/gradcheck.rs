@@ -0,0 +1,193 @@
+// Finite-difference gradient checks shared by the unit tests. Layer errors
+// and gradients hold the descent direction, so every analytic value is
+// compared with minus the central difference of the checked objective.
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::layer::{Cache, Layer, Pass, RowGradient};
+use crate::matrix::Matrix;
+use crate::model::Model;
+
+const EPSILON: f32 = 1e-3;
+
+pub(crate) fn assert_close(what: &str, analytic: f32, numeric: f32) {
+    let tolerance = 2e-3 + 2e-2 * analytic.abs().max(numeric.abs());
+    assert!(
+        (analytic - numeric).abs() <= tolerance,
+        "{}: analytic {} but numeric {}",
+        what,
+        analytic,
+        numeric
+    );
+}
+
+// Central difference of `f` in element `i` of `values`, which is left as
+// it was.
+pub(crate) fn central_difference(
+    values: &mut [f32],
+    i: usize,
+    mut f: impl FnMut(&[f32]) -> f64,
+) -> f32 {
+    let x = values[i];
+    values[i] = x + EPSILON;
+    let plus = f(values);
+    values[i] = x - EPSILON;
+    let minus = f(values);
+    values[i] = x;
+    ((plus - minus) / (2.0 * EPSILON as f64)) as f32
+}
+
+pub(crate) fn random(rows: usize, cols: usize, rng: &mut StdRng) -> Matrix {
+    Matrix::random_uniform(rows, cols, -1.0, 1.0, rng)
+}
+
+fn training_pass<'a>(rng: &'a mut StdRng, statistics: Option<&'a [f64]>, batch: usize) -> Pass<'a> {
+    Pass {
+        training: true,
+        rng,
+        statistics,
+        batch_size: batch,
+    }
+}
+
+// `weights . forward(input)` in training mode. Every run draws from the
+// same seed, so dropout masks match between runs.
+fn objective(layer: &dyn Layer, input: &Matrix, weights: &Matrix) -> f64 {
+    let mut rng = StdRng::seed_from_u64(0);
+    let stats = layer.forward_statistics(input);
+    let mut pass = training_pass(&mut rng, stats.as_deref(), input.cols);
+    let output = layer.forward(input, &mut Cache::new(), &mut pass);
+    output
+        .data
+        .iter()
+        .zip(&weights.data)
+        .map(|(&y, &w)| y as f64 * w as f64)
+        .sum()
+}
+
+// Builds `layer` on `input_shape` and checks the input error and every
+// parameter gradient of `backward` (and `backward_rows`) on a random batch.
+pub(crate) fn check_layer<L: Layer>(mut layer: L, input_shape: &[usize], batch: usize) {
+    let mut rng = StdRng::seed_from_u64(7);
+    layer.build(input_shape, &mut rng).unwrap();
+    let input = random(input_shape.iter().product(), batch, &mut rng);
+    check_layer_on(&mut layer, input, true);
+}
+
+// Same as `check_layer` for an already built layer and a given batch; the
+// input error is skipped when the input is not differentiable, such as
+// token indices.
+pub(crate) fn check_layer_on(layer: &mut dyn Layer, mut input: Matrix, check_input: bool) {
+    let mut rng = StdRng::seed_from_u64(11);
+    let batch = input.cols;
+    let outputs: usize = layer.output_shape().iter().product();
+    let weights = random(outputs, batch, &mut rng);
+
+    let mut forward_rng = StdRng::seed_from_u64(0);
+    let stats = layer.forward_statistics(&input);
+    let mut cache = Cache::new();
+    let mut pass = training_pass(&mut forward_rng, stats.as_deref(), batch);
+    layer.forward(&input, &mut cache, &mut pass);
+
+    let mut error = weights.clone();
+    for e in &mut error.data {
+        *e = -*e;
+    }
+    let stats = layer.backward_statistics(&error, &cache);
+    let mut gradients = layer.gradients();
+    let mut pass = training_pass(&mut forward_rng, stats.as_deref(), batch);
+    let input_error = layer.backward(&error, &cache, &mut gradients, &mut pass);
+
+    let sparse = layer.row_sparse();
+    let mut rows: Vec<RowGradient> = layer
+        .parameters()
+        .iter()
+        .zip(&sparse)
+        .filter(|(_, sparse)| **sparse)
+        .map(|(p, _)| RowGradient::new(p.cols))
+        .collect();
+    layer.backward_rows(&error, &cache, &mut rows);
+    let mut rows = rows.into_iter();
+    for ((gradient, param), sparse) in gradients.iter_mut().zip(layer.parameters()).zip(&sparse) {
+        if *sparse {
+            *gradient = rows.next().unwrap().to_dense(param.rows);
+        }
+    }
+
+    if check_input {
+        for i in 0..input.data.len() {
+            let numeric = central_difference(&mut input.data, i, |data| {
+                let probe = Matrix {
+                    rows: input_error.rows,
+                    cols: batch,
+                    data: data.to_vec(),
+                };
+                objective(layer, &probe, &weights)
+            });
+            assert_close(
+                &format!("{} input {}", layer.name(), i),
+                input_error.data[i],
+                -numeric,
+            );
+        }
+    }
+
+    for (k, gradient) in gradients.iter().enumerate() {
+        for i in 0..gradient.data.len() {
+            let x = layer.parameters()[k].data[i];
+            layer.parameters_mut()[k].data[i] = x + EPSILON;
+            let plus = objective(layer, &input, &weights);
+            layer.parameters_mut()[k].data[i] = x - EPSILON;
+            let minus = objective(layer, &input, &weights);
+            layer.parameters_mut()[k].data[i] = x;
+            let numeric = ((plus - minus) / (2.0 * EPSILON as f64)) as f32;
+            assert_close(
+                &format!("{} parameter {} element {}", layer.name(), k, i),
+                gradient.data[i],
+                -numeric,
+            );
+        }
+    }
+}
+
+// Checks a whole training step: `build(learning_rate)` must return the same
+// model, with plain `Sgd`, on every call. One step at rate 1 moves each
+// parameter by its batch-averaged descent direction, which is compared with
+// minus the central difference of the training loss, measured by steps at
+// rate 0. `parameter` picks the snapshot tensors that are trained.
+pub(crate) fn check_model<M: Model>(
+    build: impl Fn(f32) -> M,
+    inputs: &[Vec<f32>],
+    targets: &[Vec<f32>],
+    parameter: impl Fn(usize) -> bool,
+) {
+    let mut model = build(1.0);
+    let before = model.snapshot();
+    model.train_batch_parallel(inputs, targets, 2);
+    let after = model.snapshot();
+
+    let mut probe = build(0.0);
+    let mut base = probe.snapshot();
+    for k in (0..base.len()).filter(|&k| parameter(k)) {
+        for i in 0..base[k].data.len() {
+            let numeric = {
+                let x = base[k].data[i];
+                let mut loss_at = |value: f32| {
+                    base[k].data[i] = value;
+                    probe.restore(base.clone()).unwrap();
+                    probe.train_batch_parallel(inputs, targets, 2) as f64
+                };
+                let plus = loss_at(x + EPSILON);
+                let minus = loss_at(x - EPSILON);
+                base[k].data[i] = x;
+                ((plus - minus) / (2.0 * EPSILON as f64)) as f32
+            };
+            assert_close(
+                &format!("tensor {} element {}", k, i),
+                after[k].data[i] - before[k].data[i],
+                -numeric,
+            );
+        }
+    }
+}
@@ -0,0 +1,1186 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use rayon::prelude::*;
+
+use crate::activation::Activation;
+use crate::clipping::GradientClipping;
+use crate::error::{Result, RustingBrainError};
+use crate::layer::{Cache, Layer, Pass, RowGradient, add_to};
+use crate::loss::Loss;
+use crate::matrix::Matrix;
+use crate::model::{self, Gradient, Model, TrainingState};
+use crate::optimizer::Optimizer;
+use crate::persistence;
+use crate::scheduler::LrScheduler;
+
+// Handle to a value in a `Graph`, returned when the node is added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Node(usize);
+
+enum Op {
+    // Index into the model inputs.
+    Input(usize),
+    Layer(Box<dyn Layer>),
+    // Elementwise sum of equally shaped nodes, e.g. a residual connection.
+    Add,
+    // Stacks nodes along their first axis (features, or channels).
+    Concat,
+}
+
+struct GraphNode {
+    op: Op,
+    inputs: Vec<Node>,
+    shape: Vec<usize>,
+}
+
+// The wiring, kept apart from the optimizer so chunks can share it across
+// threads.
+struct Dag {
+    nodes: Vec<GraphNode>,
+    inputs: Vec<Node>,
+    outputs: Vec<(Node, Loss)>,
+}
+
+// Layers wired into a directed acyclic graph, for models a `Sequential`
+// stack cannot express: skip connections, merged branches, several inputs
+// or several outputs, e.g. a residual block:
+//
+//     let mut graph = Graph::new(0.01);
+//     let x = graph.input(vec![32]);
+//     let h = graph.layer(Dense::new(32).with_activation(Activation::Relu), x);
+//     let h = graph.add(&[x, h]);
+//     let y = graph.layer(Dense::new(1), h);
+//     graph.output(y, Loss::MeanSquaredError);
+//
+// Nodes can only consume nodes added before them, so insertion order is a
+// topological order: the forward pass walks it front to back and the
+// backward pass back to front. Samples passed to the training and
+// inference methods are all model inputs concatenated in the order they
+// were declared, and targets likewise all outputs; the loss is the sum of
+// the output losses. Layers no output depends on, such as a branch left
+// dangling, still run forward but are never trained: neither their
+// parameters nor their optimizer state change.
+pub struct Graph {
+    dag: Dag,
+    training: TrainingState,
+    rng: StdRng,
+}
+
+fn invalid<T>(msg: String) -> Result<T> {
+    Err(RustingBrainError::InvalidArchitecture(msg))
+}
+
+impl Graph {
+    pub fn new(learning_rate: f32) -> Self {
+        Graph {
+            dag: Dag {
+                nodes: Vec::new(),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+            },
+            training: TrainingState::new(learning_rate),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn input(&mut self, shape: Vec<usize>) -> Node {
+        let index = self.dag.inputs.len();
+        let node = self.push(Op::Input(index), Vec::new(), shape);
+        self.dag.inputs.push(node);
+        node
+    }
+
+    pub fn layer<L: Layer + 'static>(&mut self, layer: L, input: Node) -> Node {
+        self.try_layer(layer, input)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Builds `layer` on the shape of `input`, drawing its parameters from
+    // the model's random source.
+    pub fn try_layer<L: Layer + 'static>(&mut self, mut layer: L, input: Node) -> Result<Node> {
+        let shape = self.try_shape(input)?.to_vec();
+        let output_shape = layer.build(&shape, &mut self.rng)?;
+        Ok(self.push(Op::Layer(Box::new(layer)), vec![input], output_shape))
+    }
+
+    pub fn add(&mut self, inputs: &[Node]) -> Node {
+        self.try_add(inputs).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_add(&mut self, inputs: &[Node]) -> Result<Node> {
+        let shape = self.merged_shape("add", inputs)?.to_vec();
+        for &node in &inputs[1..] {
+            let other = self.shape(node);
+            if other != shape {
+                return invalid(format!("cannot add shapes {:?} and {:?}", shape, other));
+            }
+        }
+        Ok(self.push(Op::Add, inputs.to_vec(), shape))
+    }
+
+    pub fn concat(&mut self, inputs: &[Node]) -> Node {
+        self.try_concat(inputs).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // All inputs must agree on every axis but the first.
+    pub fn try_concat(&mut self, inputs: &[Node]) -> Result<Node> {
+        let mut shape = self.merged_shape("concat", inputs)?.to_vec();
+        for &node in &inputs[1..] {
+            let other = self.shape(node);
+            if other.len() != shape.len() || other[1..] != shape[1..] {
+                return invalid(format!(
+                    "cannot concatenate shapes {:?} and {:?}",
+                    shape, other
+                ));
+            }
+            shape[0] += other[0];
+        }
+        Ok(self.push(Op::Concat, inputs.to_vec(), shape))
+    }
+
+    pub fn output(&mut self, node: Node, loss: Loss) {
+        self.try_output(node, loss)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_output(&mut self, node: Node, loss: Loss) -> Result<()> {
+        self.try_shape(node)?;
+        if self.dag.outputs.iter().any(|&(n, _)| n == node) {
+            return invalid(format!("node {} is already an output", node.0));
+        }
+        self.dag.outputs.push((node, loss));
+        Ok(())
+    }
+
+    // Makes `node` the only output, as `Sequential` does after every layer.
+    pub(crate) fn set_output(&mut self, node: Node, loss: Loss) {
+        self.dag.outputs = vec![(node, loss)];
+    }
+
+    // Reseeds the model's random source and rebuilds every layer from it, so
+    // the same seed always yields the same starting parameters.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        for i in 0..self.dag.nodes.len() {
+            let (before, rest) = self.dag.nodes.split_at_mut(i);
+            let node = &mut rest[0];
+            if let Op::Layer(layer) = &mut node.op {
+                layer
+                    .build(&before[node.inputs[0].0].shape, &mut self.rng)
+                    .expect("layer accepted its input shape before");
+            }
+        }
+        self
+    }
+
+    pub fn with_optimizer<O: Optimizer + 'static>(mut self, optimizer: O) -> Self {
+        self.training.optimizer = Box::new(optimizer);
+        self
+    }
+
+    // The scheduler's clock starts from zero whenever one is attached.
+    pub fn with_scheduler<S: LrScheduler + 'static>(mut self, scheduler: S) -> Self {
+        self.training.set_scheduler(Box::new(scheduler));
+        self
+    }
+
+    pub fn with_gradient_clipping(mut self, clipping: GradientClipping) -> Self {
+        self.training.gradient_clipping = Some(clipping);
+        self
+    }
+
+    fn push(&mut self, op: Op, inputs: Vec<Node>, shape: Vec<usize>) -> Node {
+        self.dag.nodes.push(GraphNode { op, inputs, shape });
+        Node(self.dag.nodes.len() - 1)
+    }
+
+    fn try_shape(&self, node: Node) -> Result<&[usize]> {
+        match self.dag.nodes.get(node.0) {
+            Some(n) => Ok(&n.shape),
+            None => invalid(format!("node {} does not belong to this graph", node.0)),
+        }
+    }
+
+    fn merged_shape(&self, op: &str, inputs: &[Node]) -> Result<&[usize]> {
+        if inputs.len() < 2 {
+            return invalid(format!("{} needs at least two inputs", op));
+        }
+        for &node in inputs {
+            self.try_shape(node)?;
+        }
+        let shape = self.shape(inputs[0]);
+        if shape.is_empty() {
+            return invalid(format!("{} on a shapeless node", op));
+        }
+        Ok(shape)
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    pub fn shape(&self, node: Node) -> &[usize] {
+        &self.dag.nodes[node.0].shape
+    }
+
+    // The layer behind `node`, if it is a layer node.
+    pub fn layer_at(&self, node: Node) -> Option<&dyn Layer> {
+        match &self.dag.nodes[node.0].op {
+            Op::Layer(layer) => Some(layer.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn inputs(&self) -> &[Node] {
+        &self.dag.inputs
+    }
+
+    pub fn outputs(&self) -> Vec<Node> {
+        self.dag.outputs.iter().map(|&(node, _)| node).collect()
+    }
+
+    // Base rate, before any scheduler is applied.
+    pub fn learning_rate(&self) -> f32 {
+        self.training.learning_rate
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.training.learning_rate = learning_rate;
+    }
+
+    // Rate the next training step will use.
+    pub fn current_learning_rate(&self) -> f32 {
+        self.training.current_learning_rate()
+    }
+
+    // Advances per-epoch schedules and feeds them the monitored loss.
+    pub fn end_epoch(&mut self, metric: f32) {
+        self.training.end_epoch(metric);
+    }
+
+    // Global norm of the last applied gradients, measured before clipping.
+    pub fn gradient_norm(&self) -> f32 {
+        self.training.last_gradient_norm
+    }
+
+    pub(crate) fn layers(&self) -> impl Iterator<Item = &dyn Layer> {
+        self.dag.nodes.iter().filter_map(|n| match &n.op {
+            Op::Layer(layer) => Some(layer.as_ref()),
+            _ => None,
+        })
+    }
+
+    fn layers_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Layer>> {
+        self.dag.nodes.iter_mut().filter_map(|n| match &mut n.op {
+            Op::Layer(layer) => Some(layer),
+            _ => None,
+        })
+    }
+
+    // Every trainable tensor, in node order.
+    pub fn parameters(&self) -> Vec<&Matrix> {
+        self.layers().flat_map(|l| l.parameters()).collect()
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.parameters().iter().map(|p| p.data.len()).sum()
+    }
+
+    // Replaces every trainable tensor, e.g. to roll back to a snapshot taken
+    // from `parameters()`. Optimizer state is left untouched.
+    pub fn set_parameters(&mut self, parameters: Vec<Matrix>) -> Result<()> {
+        let targets = self.layers_mut().flat_map(|l| l.parameters_mut()).collect();
+        model::assign(targets, parameters)
+    }
+
+    // Every state tensor, such as running statistics, in node order.
+    pub fn state(&self) -> Vec<&Matrix> {
+        self.layers().flat_map(|l| l.state()).collect()
+    }
+
+    // Parameters, then state, as `Model::snapshot` and the saved files
+    // order them.
+    fn tensors(&self) -> Vec<&Matrix> {
+        let mut tensors = self.parameters();
+        tensors.extend(self.state());
+        tensors
+    }
+
+    // Saves the parameters and state, but not the wiring: load them with
+    // `load_parameters` into a graph built the same way.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        persistence::write_tensors(&mut writer, &self.tensors())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_parameters<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let tensors = persistence::read_tensors(&mut reader, &self.tensors())?;
+        Model::restore(self, tensors)
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, persistence::tensors_to_json(&self.tensors()))?;
+        Ok(())
+    }
+
+    pub fn load_parameters_json<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        let tensors = persistence::tensors_from_json(&text, &self.tensors())?;
+        Model::restore(self, tensors)
+    }
+
+    // Total regularization penalty of the current parameters. It is already
+    // included in the losses returned by `evaluate` and the training methods.
+    pub fn regularization_loss(&self) -> f32 {
+        self.layers().map(|l| l.regularization_loss()).sum()
+    }
+
+    fn check_vector(values: &[f32], expected: usize) -> Result<()> {
+        if values.len() != expected {
+            return Err(RustingBrainError::ShapeMismatch {
+                expected: (expected, 1),
+                found: (values.len(), 1),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_batch(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<()> {
+        if self.dag.outputs.is_empty() {
+            return invalid("graph has no outputs".to_string());
+        }
+        if inputs.is_empty() {
+            return Err(RustingBrainError::EmptyBatch);
+        }
+        if inputs.len() != targets.len() {
+            return Err(RustingBrainError::BatchSizeMismatch {
+                inputs: inputs.len(),
+                targets: targets.len(),
+            });
+        }
+
+        let (input_size, output_size) = (self.dag.input_size(), self.dag.output_size());
+        for (input, target) in inputs.iter().zip(targets) {
+            Self::check_vector(input, input_size)?;
+            Self::check_vector(target, output_size)?;
+        }
+        self.dag.check_inputs(inputs)
+    }
+
+    // Fewest samples a training batch may hold: 2 when any layer uses batch
+    // normalization, which has no statistics for a single sample.
+    pub fn min_batch_size(&self) -> usize {
+        self.layers().map(|l| l.min_batch_size()).max().unwrap_or(1)
+    }
+
+    fn check_training_batch(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<()> {
+        self.check_batch(inputs, targets)?;
+        model::check_batch_size(inputs.len(), self.min_batch_size())
+    }
+
+    pub fn try_forward(&self, input: &[f32]) -> Result<Vec<f32>> {
+        Self::check_vector(input, self.dag.input_size())?;
+        self.dag.check_inputs(&[input.to_vec()])?;
+        Ok(self.forward(input))
+    }
+
+    // All outputs of one sample, concatenated.
+    pub fn forward(&self, input: &[f32]) -> Vec<f32> {
+        let inputs = self.dag.split_inputs(&[input.to_vec()]);
+        self.predict(&inputs)
+            .into_iter()
+            .flat_map(|m| m.data)
+            .collect()
+    }
+
+    // Inference on one batch matrix per input, one sample per column;
+    // returns one matrix per output.
+    pub fn predict(&self, inputs: &[Matrix]) -> Vec<Matrix> {
+        let (values, _) = self.dag.run(inputs);
+        self.dag
+            .outputs
+            .iter()
+            .map(|&(node, _)| values[node.0].clone())
+            .collect()
+    }
+
+    pub fn evaluate(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> f32 {
+        self.try_evaluate(inputs, targets).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Mean loss over the samples, summed over the outputs, plus the
+    // regularization penalty; an empty set scores 0.
+    pub fn try_evaluate(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<f32> {
+        if inputs.is_empty() && targets.is_empty() {
+            return Ok(0.0);
+        }
+        self.check_batch(inputs, targets)?;
+
+        let dag = &self.dag;
+        let (values, caches) = dag.run(&dag.split_inputs(inputs));
+        let consumed = dag.consumed();
+        let targets = dag.split_targets(targets);
+        let mut sum = 0.0;
+        for (&(node, loss), target) in dag.outputs.iter().zip(&targets) {
+            let output = &values[node.0];
+            let (activation, logits) = dag
+                .output_activation(node, &consumed, &caches)
+                .unwrap_or((Activation::Identity, output));
+            for c in 0..output.cols {
+                sum += loss.value_with_logits(
+                    activation,
+                    &output.column(c),
+                    &logits.column(c),
+                    &target.column(c),
+                );
+            }
+        }
+        Ok(sum / inputs.len() as f32 + self.regularization_loss())
+    }
+
+    pub fn try_train(&mut self, input: &[f32], target: &[f32]) -> Result<f32> {
+        self.check_training_batch(&[input.to_vec()], &[target.to_vec()])?;
+        Ok(self.train(input, target))
+    }
+
+    pub fn train(&mut self, input: &[f32], target: &[f32]) -> f32 {
+        self.train_batch_parallel(&[input.to_vec()], &[target.to_vec()], 1)
+    }
+
+    pub fn try_train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> Result<f32> {
+        self.check_training_batch(inputs, targets)?;
+        Ok(self.train_batch_parallel(inputs, targets, num_threads))
+    }
+
+    // One optimizer step on the batch-averaged gradients. The batch is split
+    // into chunks, run on up to `num_threads` threads, that visit the nodes
+    // in lockstep, so layers needing whole-batch statistics get them; partial
+    // results are combined in chunk order to keep seeded runs bit-identical
+    // for any thread count. Panics on batches smaller than `min_batch_size`.
+    pub fn train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> f32 {
+        let batch_size = inputs.len();
+        if batch_size == 0 {
+            return 0.0;
+        }
+        model::check_batch_size(batch_size, self.min_batch_size())
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let seed = self.rng.next_u64();
+        let chunk_size = model::chunk_size(batch_size);
+        let per_task = model::chunks_per_task(batch_size, num_threads);
+
+        let dag = &self.dag;
+        let mut passes: Vec<GraphPass> = inputs
+            .par_chunks(chunk_size)
+            .zip(targets.par_chunks(chunk_size))
+            .enumerate()
+            .with_min_len(per_task)
+            .map(|(index, (in_chunk, tgt_chunk))| {
+                let rng = StdRng::seed_from_u64(seed.wrapping_add(index as u64));
+                GraphPass::new(dag, in_chunk, tgt_chunk, rng)
+            })
+            .collect();
+
+        let mut forward_statistics = Vec::new();
+        for (i, node) in dag.nodes.iter().enumerate() {
+            let stats = match &node.op {
+                Op::Layer(layer) => combine(
+                    passes
+                        .par_iter()
+                        .with_min_len(per_task)
+                        .map(|p| layer.forward_statistics(&p.values[node.inputs[0].0]))
+                        .collect(),
+                ),
+                _ => None,
+            };
+            passes
+                .par_iter_mut()
+                .with_min_len(per_task)
+                .for_each(|p| p.forward(dag, i, stats.as_deref(), batch_size));
+            if let Some(stats) = stats {
+                forward_statistics.push((i, stats));
+            }
+        }
+
+        let consumed = dag.consumed();
+        passes
+            .par_iter_mut()
+            .with_min_len(per_task)
+            .for_each(|p| p.output_errors(dag, &consumed));
+
+        for (i, node) in dag.nodes.iter().enumerate().rev() {
+            let stats = match &node.op {
+                Op::Layer(layer) => combine(
+                    passes
+                        .par_iter()
+                        .with_min_len(per_task)
+                        .map(|p| {
+                            let error = p.errors[i].as_ref()?;
+                            layer.backward_statistics(error, &p.caches[i])
+                        })
+                        .collect(),
+                ),
+                _ => None,
+            };
+            passes
+                .par_iter_mut()
+                .with_min_len(per_task)
+                .for_each(|p| p.backward(dag, i, stats.as_deref(), batch_size));
+        }
+
+        let mut passes = passes.into_iter();
+        let mut total = passes.next().unwrap();
+        for pass in passes {
+            for (a, b) in total.grads.iter_mut().zip(&pass.grads) {
+                for (x, y) in a.iter_mut().zip(b) {
+                    add_to(x, y);
+                }
+            }
+            for (a, b) in total.rows.iter_mut().zip(&pass.rows) {
+                for (x, y) in a.iter_mut().zip(b) {
+                    x.add(y);
+                }
+            }
+            total.loss_sum += pass.loss_sum;
+        }
+
+        let scale = 1.0 / (batch_size as f32);
+        let (mut grads, mut rows) = (total.grads, total.rows);
+        for ((node, node_grads), node_rows) in self.dag.nodes.iter().zip(&mut grads).zip(&mut rows) {
+            let values = node_rows.iter_mut().map(|r| &mut r.values);
+            for g in node_grads.iter_mut().chain(values) {
+                for x in &mut g.data {
+                    *x *= scale;
+                }
+            }
+            if let Op::Layer(layer) = &node.op {
+                layer.add_regularization_gradients(node_grads);
+            }
+        }
+        let penalty = self.regularization_loss();
+
+        // Like their parameters, layers no output depends on keep their
+        // running statistics.
+        let trained = self.dag.trained();
+        for (i, stats) in forward_statistics {
+            if let (Op::Layer(layer), true) = (&mut self.dag.nodes[i].op, trained[i]) {
+                layer.update_statistics(&stats, batch_size);
+            }
+        }
+        self.apply_gradients(grads, rows, &trained);
+
+        total.loss_sum * scale + penalty
+    }
+
+    // Steps the parameters of the `trained` nodes with their gradients, dense
+    // or row-sparse, keeping every parameter's optimizer slot. Returns the
+    // global norm of those gradients before any clipping.
+    fn apply_gradients(
+        &mut self,
+        grads: Vec<Vec<Matrix>>,
+        rows: Vec<Vec<RowGradient>>,
+        trained: &[bool],
+    ) -> f32 {
+        let mut params = Vec::new();
+        let mut kept = Vec::new();
+        let mut slot = 0;
+        let nodes = self.dag.nodes.iter_mut().zip(grads).zip(rows).zip(trained);
+        for (((node, node_grads), node_rows), &trained) in nodes {
+            let Op::Layer(layer) = &mut node.op else {
+                continue;
+            };
+            let sparse = layer.row_sparse();
+            let mut node_rows = node_rows.into_iter();
+            let node_grads = node_grads.into_iter().zip(sparse).map(|(grad, sparse)| {
+                if sparse {
+                    Gradient::Rows(node_rows.next().unwrap())
+                } else {
+                    Gradient::Dense(grad)
+                }
+            });
+            for (param, grad) in layer.parameters_mut().into_iter().zip(node_grads) {
+                if trained {
+                    params.push((slot, param));
+                    kept.push(grad);
+                }
+                slot += 1;
+            }
+        }
+        self.training.step_slots(params, &mut kept)
+    }
+}
+
+impl Model for Graph {
+    fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    fn check_batch(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<()> {
+        Graph::check_batch(self, inputs, targets)
+    }
+
+    fn min_batch_size(&self) -> usize {
+        Graph::min_batch_size(self)
+    }
+
+    fn current_learning_rate(&self) -> f32 {
+        Graph::current_learning_rate(self)
+    }
+
+    fn train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> f32 {
+        Graph::train_batch_parallel(self, inputs, targets, num_threads)
+    }
+
+    fn evaluate(&mut self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> f32 {
+        Graph::evaluate(self, inputs, targets)
+    }
+
+    fn end_epoch(&mut self, metric: f32) {
+        Graph::end_epoch(self, metric);
+    }
+
+    fn snapshot(&self) -> Vec<Matrix> {
+        self.tensors().into_iter().cloned().collect()
+    }
+
+    fn restore(&mut self, mut snapshot: Vec<Matrix>) -> Result<()> {
+        model::check_shapes(&self.tensors(), &snapshot)?;
+        let state = snapshot.split_off(self.parameters().len());
+        self.set_parameters(snapshot)?;
+        let targets = self.layers_mut().flat_map(|l| l.state_mut()).collect();
+        model::assign(targets, state)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        Graph::save(self, path)
+    }
+
+    fn save_json(&self, path: &Path) -> Result<()> {
+        Graph::save_json(self, path)
+    }
+}
+
+// Sums per-chunk statistics in chunk order; `None` when the layer has none.
+fn combine(partials: Vec<Option<Vec<f64>>>) -> Option<Vec<f64>> {
+    let mut partials = partials.into_iter().flatten();
+    let mut total = partials.next()?;
+    for partial in partials {
+        for (t, p) in total.iter_mut().zip(&partial) {
+            *t += p;
+        }
+    }
+    Some(total)
+}
+
+impl Dag {
+    fn size(&self, node: Node) -> usize {
+        self.nodes[node.0].shape.iter().product()
+    }
+
+    fn input_size(&self) -> usize {
+        self.inputs.iter().map(|&n| self.size(n)).sum()
+    }
+
+    fn output_size(&self) -> usize {
+        self.outputs.iter().map(|&(n, _)| self.size(n)).sum()
+    }
+
+    // Runs `Layer::check_input` on every layer fed straight from a model
+    // input. Layers deeper in the graph see computed values and are left to
+    // panic in `forward`.
+    fn check_inputs(&self, inputs: &[Vec<f32>]) -> Result<()> {
+        let mut split = None;
+        for node in &self.nodes {
+            let Op::Layer(layer) = &node.op else {
+                continue;
+            };
+            if let Op::Input(k) = self.nodes[node.inputs[0].0].op {
+                let split = split.get_or_insert_with(|| self.split_inputs(inputs));
+                layer.check_input(&split[k])?;
+            }
+        }
+        Ok(())
+    }
+
+    // Splits concatenated samples into one batch matrix per node, one
+    // sample per column.
+    fn split(&self, samples: &[Vec<f32>], nodes: impl Iterator<Item = Node>) -> Vec<Matrix> {
+        let mut offset = 0;
+        nodes
+            .map(|node| {
+                let size = self.size(node);
+                let columns: Vec<Vec<f32>> = samples
+                    .iter()
+                    .map(|s| s[offset..offset + size].to_vec())
+                    .collect();
+                offset += size;
+                Matrix::from_columns(&columns)
+            })
+            .collect()
+    }
+
+    fn split_inputs(&self, inputs: &[Vec<f32>]) -> Vec<Matrix> {
+        self.split(inputs, self.inputs.iter().copied())
+    }
+
+    fn split_targets(&self, targets: &[Vec<f32>]) -> Vec<Matrix> {
+        self.split(targets, self.outputs.iter().map(|&(n, _)| n))
+    }
+
+    // Forward pass in inference mode, keeping every node's value and cache.
+    fn run(&self, inputs: &[Matrix]) -> (Vec<Matrix>, Vec<Cache>) {
+        let batch_size = inputs.first().map_or(0, |m| m.cols);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut values = Vec::with_capacity(self.nodes.len());
+        let mut caches = Vec::with_capacity(self.nodes.len());
+        for i in 0..self.nodes.len() {
+            let mut pass = Pass {
+                training: false,
+                rng: &mut rng,
+                statistics: None,
+                batch_size,
+            };
+            let mut cache = Cache::new();
+            values.push(self.forward_node(i, inputs, &values, &mut cache, &mut pass));
+            caches.push(cache);
+        }
+        (values, caches)
+    }
+
+    fn forward_node(
+        &self,
+        i: usize,
+        inputs: &[Matrix],
+        values: &[Matrix],
+        cache: &mut Cache,
+        pass: &mut Pass,
+    ) -> Matrix {
+        let node = &self.nodes[i];
+        match &node.op {
+            Op::Input(k) => inputs[*k].clone(),
+            Op::Layer(layer) => layer.forward(&values[node.inputs[0].0], cache, pass),
+            Op::Add => {
+                let mut sum = values[node.inputs[0].0].clone();
+                for input in &node.inputs[1..] {
+                    add_to(&mut sum, &values[input.0]);
+                }
+                sum
+            }
+            // Samples are columns, so stacking along the first axis is
+            // stacking the row blocks.
+            Op::Concat => {
+                let cols = values[node.inputs[0].0].cols;
+                let mut data = Vec::with_capacity(self.size(Node(i)) * cols);
+                for input in &node.inputs {
+                    data.extend_from_slice(&values[input.0].data);
+                }
+                Matrix {
+                    rows: data.len() / cols,
+                    cols,
+                    data,
+                }
+            }
+        }
+    }
+
+    // Turns the error at a node into the errors at its inputs, adding layer
+    // gradients to `grads`.
+    fn backward_node(
+        &self,
+        i: usize,
+        error: &Matrix,
+        cache: &Cache,
+        grads: &mut [Matrix],
+        pass: &mut Pass,
+        from_logits: bool,
+    ) -> Vec<Matrix> {
+        let node = &self.nodes[i];
+        match &node.op {
+            Op::Input(_) => Vec::new(),
+            Op::Layer(layer) if from_logits => {
+                vec![layer.backward_from_logits(error, cache, grads, pass)]
+            }
+            Op::Layer(layer) => vec![layer.backward(error, cache, grads, pass)],
+            Op::Add => vec![error.clone(); node.inputs.len()],
+            Op::Concat => {
+                let mut offset = 0;
+                node.inputs
+                    .iter()
+                    .map(|&input| {
+                        let rows = self.size(input);
+                        let data = error.data[offset..offset + rows * error.cols].to_vec();
+                        offset += data.len();
+                        Matrix {
+                            rows,
+                            cols: error.cols,
+                            data,
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // Whether any output depends on each node. Only those nodes get an
+    // error in the backward pass, so only their layers are trained.
+    fn trained(&self) -> Vec<bool> {
+        let mut trained = vec![false; self.nodes.len()];
+        for &(node, _) in &self.outputs {
+            trained[node.0] = true;
+        }
+        for i in (0..self.nodes.len()).rev() {
+            if trained[i] {
+                for input in &self.nodes[i].inputs {
+                    trained[input.0] = true;
+                }
+            }
+        }
+        trained
+    }
+
+    // Whether any node reads each node's value.
+    fn consumed(&self) -> Vec<bool> {
+        let mut consumed = vec![false; self.nodes.len()];
+        for node in &self.nodes {
+            for input in &node.inputs {
+                consumed[input.0] = true;
+            }
+        }
+        consumed
+    }
+
+    // The output activation to fuse with the loss and its input. Only an
+    // output no other node reads can take its error at the logits.
+    fn output_activation<'c>(
+        &self,
+        node: Node,
+        consumed: &[bool],
+        caches: &'c [Cache],
+    ) -> Option<(Activation, &'c Matrix)> {
+        match &self.nodes[node.0].op {
+            Op::Layer(layer) if !consumed[node.0] => layer.output_activation(&caches[node.0]),
+            _ => None,
+        }
+    }
+}
+
+// One rayon chunk's share of a training step: every node's value, then the
+// error at every node, summed over the nodes reading it. Nodes no output
+// depends on never get an error.
+struct GraphPass {
+    inputs: Vec<Matrix>,
+    targets: Vec<Matrix>,
+    rng: StdRng,
+    values: Vec<Matrix>,
+    errors: Vec<Option<Matrix>>,
+    // Outputs whose error was taken at the logits.
+    from_logits: Vec<bool>,
+    caches: Vec<Cache>,
+    grads: Vec<Vec<Matrix>>,
+    // Gradients of each layer's row-sparse parameters.
+    rows: Vec<Vec<RowGradient>>,
+    loss_sum: f32,
+}
+
+impl GraphPass {
+    fn new(dag: &Dag, inputs: &[Vec<f32>], targets: &[Vec<f32>], rng: StdRng) -> Self {
+        let count = dag.nodes.len();
+        GraphPass {
+            inputs: dag.split_inputs(inputs),
+            targets: dag.split_targets(targets),
+            rng,
+            values: Vec::with_capacity(count),
+            errors: vec![None; count],
+            from_logits: vec![false; count],
+            caches: vec![Cache::new(); count],
+            grads: dag
+                .nodes
+                .iter()
+                .map(|n| match &n.op {
+                    Op::Layer(layer) => layer.gradients(),
+                    _ => Vec::new(),
+                })
+                .collect(),
+            rows: dag.nodes.iter().map(row_gradients).collect(),
+            loss_sum: 0.0,
+        }
+    }
+
+    fn forward(&mut self, dag: &Dag, i: usize, statistics: Option<&[f64]>, batch_size: usize) {
+        let mut pass = Pass {
+            training: true,
+            rng: &mut self.rng,
+            statistics,
+            batch_size,
+        };
+        let value = dag.forward_node(
+            i,
+            &self.inputs,
+            &self.values,
+            &mut self.caches[i],
+            &mut pass,
+        );
+        self.values.push(value);
+    }
+
+    fn output_errors(&mut self, dag: &Dag, consumed: &[bool]) {
+        for (&(node, loss), target) in dag.outputs.iter().zip(&self.targets) {
+            let output = &self.values[node.0];
+            let fused = dag.output_activation(node, consumed, &self.caches);
+            self.from_logits[node.0] = fused.is_some();
+            let (activation, logits) = fused.unwrap_or((Activation::Identity, output));
+            for c in 0..output.cols {
+                self.loss_sum += loss.value_with_logits(
+                    activation,
+                    &output.column(c),
+                    &logits.column(c),
+                    &target.column(c),
+                );
+            }
+
+            let mut error = Matrix::new(output.rows, output.cols);
+            loss.output_error(
+                activation,
+                &output.data,
+                &logits.data,
+                &target.data,
+                output.cols,
+                &mut error.data,
+            );
+            accumulate(&mut self.errors[node.0], error);
+        }
+    }
+
+    fn backward(&mut self, dag: &Dag, i: usize, statistics: Option<&[f64]>, batch_size: usize) {
+        let Some(error) = self.errors[i].take() else {
+            return;
+        };
+        let mut pass = Pass {
+            training: true,
+            rng: &mut self.rng,
+            statistics,
+            batch_size,
+        };
+        let input_errors = dag.backward_node(
+            i,
+            &error,
+            &self.caches[i],
+            &mut self.grads[i],
+            &mut pass,
+            self.from_logits[i],
+        );
+        if let Op::Layer(layer) = &dag.nodes[i].op
+            && !self.rows[i].is_empty()
+        {
+            layer.backward_rows(&error, &self.caches[i], &mut self.rows[i]);
+        }
+        for (input, e) in dag.nodes[i].inputs.iter().zip(input_errors) {
+            accumulate(&mut self.errors[input.0], e);
+        }
+    }
+}
+
+// Empty row gradients for a node's row-sparse parameters.
+fn row_gradients(node: &GraphNode) -> Vec<RowGradient> {
+    let Op::Layer(layer) = &node.op else {
+        return Vec::new();
+    };
+    layer
+        .parameters()
+        .into_iter()
+        .zip(layer.row_sparse())
+        .filter(|(_, sparse)| *sparse)
+        .map(|(param, _)| RowGradient::new(param.cols))
+        .collect()
+}
+
+fn accumulate(slot: &mut Option<Matrix>, error: Matrix) {
+    match slot {
+        Some(total) => add_to(total, &error),
+        None => *slot = Some(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::check_model;
+    use crate::layer::{ActivationLayer, Dense, NormalizationLayer};
+    use crate::optimizer::Sgd;
+
+    // Two branches merged both ways, with batch normalization to exercise
+    // statistics across chunks, two outputs and a dangling layer, which is
+    // returned too.
+    fn merged_with_dangling(learning_rate: f32) -> (Graph, Node) {
+        let mut graph = Graph::new(learning_rate);
+        let x = graph.input(vec![3]);
+        let a = graph.layer(Dense::new(4).with_activation(Activation::Tanh), x);
+        let b = graph.layer(Dense::new(4), x);
+        let b = graph.layer(NormalizationLayer::batch(), b);
+        let b = graph.layer(ActivationLayer::new(Activation::Sigmoid), b);
+        let joined = graph.concat(&[a, b]);
+        let summed = graph.add(&[a, b]);
+        let dangling = graph.layer(Dense::new(2), summed);
+        let y = graph.layer(Dense::new(2), joined);
+        graph.output(y, Loss::MeanSquaredError);
+        let z = graph.layer(Dense::new(3).with_activation(Activation::Softmax), summed);
+        graph.output(z, Loss::CategoricalCrossEntropy);
+        (graph.with_seed(3).with_optimizer(Sgd::new()), dangling)
+    }
+
+    fn merged(learning_rate: f32) -> Graph {
+        merged_with_dangling(learning_rate).0
+    }
+
+    fn batch() -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let inputs = vec![
+            vec![0.5, -0.2, 0.1],
+            vec![-0.7, 0.3, 0.9],
+            vec![0.2, 0.8, -0.4],
+            vec![-0.1, -0.6, 0.3],
+        ];
+        let targets = vec![
+            vec![0.3, -0.5, 1.0, 0.0, 0.0],
+            vec![-0.2, 0.4, 0.0, 1.0, 0.0],
+            vec![0.6, 0.1, 0.0, 0.0, 1.0],
+            vec![-0.4, -0.3, 0.0, 1.0, 0.0],
+        ];
+        (inputs, targets)
+    }
+
+    #[test]
+    fn concat_and_add_gradients() {
+        let (inputs, targets) = batch();
+        let parameters = merged(0.0).parameters().len();
+        check_model(merged, &inputs, &targets, |k| k < parameters);
+    }
+
+    #[test]
+    fn seeded_training_ignores_the_thread_count() {
+        let (inputs, targets) = batch();
+        let run = |threads| {
+            let mut graph = merged(0.1);
+            for _ in 0..3 {
+                graph.train_batch_parallel(&inputs, &targets, threads);
+            }
+            let bits = graph.tensors().into_iter().flat_map(|m| m.data.iter().map(|v| v.to_bits()));
+            bits.collect::<Vec<u32>>()
+        };
+        let one = run(1);
+        assert_eq!(one, run(2));
+        assert_eq!(one, run(4));
+    }
+
+    #[test]
+    fn dangling_layers_are_not_trained() {
+        let (inputs, targets) = batch();
+        let (mut graph, node) = merged_with_dangling(0.1);
+        let dangling = |g: &Graph| -> Vec<Vec<f32>> {
+            let layer = g.layer_at(node).unwrap();
+            layer.parameters().iter().map(|p| p.data.clone()).collect()
+        };
+        let before = dangling(&graph);
+        graph.train_batch_parallel(&inputs, &targets, 2);
+        assert_eq!(before, dangling(&graph));
+    }
+
+    #[test]
+    fn dangling_batch_norm_keeps_its_statistics() {
+        let (inputs, targets) = batch();
+        let mut graph = Graph::new(0.1);
+        let x = graph.input(vec![3]);
+        let h = graph.layer(Dense::new(4), x);
+        let used = graph.layer(NormalizationLayer::batch(), h);
+        let dangling = graph.layer(NormalizationLayer::batch(), h);
+        let y = graph.layer(Dense::new(5), used);
+        graph.output(y, Loss::MeanSquaredError);
+        let mut graph = graph.with_seed(4);
+        let state = |g: &Graph, node: Node| -> Vec<Vec<f32>> {
+            let layer = g.layer_at(node).unwrap();
+            layer.state().iter().map(|m| m.data.clone()).collect()
+        };
+
+        let before = (state(&graph, used), state(&graph, dangling));
+        graph.train_batch_parallel(&inputs, &targets, 2);
+        assert_ne!(before.0, state(&graph, used));
+        assert_eq!(before.1, state(&graph, dangling));
+    }
+
+    #[test]
+    fn merges_check_their_inputs() {
+        let mut graph = Graph::new(0.1);
+        let x = graph.input(vec![3]);
+        let a = graph.layer(Dense::new(4), x);
+        let b = graph.layer(Dense::new(2), x);
+        assert!(matches!(
+            graph.try_add(&[a, b]),
+            Err(RustingBrainError::InvalidArchitecture(_))
+        ));
+        let joined = graph.concat(&[a, b]);
+        assert_eq!(graph.shape(joined), &[6]);
+        assert!(graph.try_concat(&[]).is_err());
+    }
+
+    #[test]
+    fn a_chain_matches_sequential() {
+        let mut graph = Graph::new(0.1);
+        let x = graph.input(vec![3]);
+        let h = graph.layer(Dense::new(4).with_activation(Activation::Tanh), x);
+        let y = graph.layer(Dense::new(2), h);
+        graph.output(y, Loss::MeanSquaredError);
+        let mut graph = graph.with_seed(5);
+        let mut sequential = crate::sequential::Sequential::new(vec![3], 0.1)
+            .with_layer(Dense::new(4).with_activation(Activation::Tanh))
+            .with_layer(Dense::new(2))
+            .with_seed(5);
+
+        let (inputs, targets) = batch();
+        let targets: Vec<Vec<f32>> = targets.iter().map(|t| t[..2].to_vec()).collect();
+        for _ in 0..3 {
+            let a = graph.train_batch_parallel(&inputs, &targets, 2);
+            let b = sequential.train_batch_parallel(&inputs, &targets, 2);
+            assert_eq!(a, b);
+        }
+        assert_eq!(graph.forward(&inputs[0]), sequential.forward(&inputs[0]));
+    }
+
+    #[test]
+    fn dangling_layers_skip_decay_and_optimizer_state() {
+        use crate::optimizer::AdamW;
+        let (inputs, targets) = batch();
+        let (graph, node) = merged_with_dangling(0.1);
+        let mut graph = graph.with_optimizer(AdamW::new(0.1));
+        let dangling = |g: &Graph| -> Vec<Vec<f32>> {
+            let layer = g.layer_at(node).unwrap();
+            layer.parameters().iter().map(|p| p.data.clone()).collect()
+        };
+        let before = dangling(&graph);
+        for _ in 0..3 {
+            graph.train_batch_parallel(&inputs, &targets, 2);
+        }
+        // Decoupled weight decay would shrink them if they were stepped.
+        assert_eq!(before, dangling(&graph));
+    }
+}
@@ -0,0 +1,118 @@
+use rand::Rng;
+
+use crate::activation::Activation;
+use crate::matrix::Matrix;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Initializer {
+    Zeros,
+    // Uniform on 0.0..1.0, what `Matrix::random` has always produced.
+    RandomUniform,
+    XavierUniform,
+    XavierNormal,
+    HeUniform,
+    HeNormal,
+    LecunUniform,
+    LecunNormal,
+    Orthogonal(f32),
+}
+
+impl Initializer {
+    // The default weight initializer for a layer feeding `activation`: He for
+    // the ReLU family, Xavier for everything else.
+    pub fn for_activation(activation: Activation) -> Self {
+        match activation {
+            Activation::Relu | Activation::LeakyRelu(_) => Initializer::HeUniform,
+            _ => Initializer::XavierUniform,
+        }
+    }
+
+    pub fn initialize<R: Rng + ?Sized>(&self, rows: usize, cols: usize, rng: &mut R) -> Matrix {
+        match *self {
+            Initializer::Zeros => Matrix::new(rows, cols),
+            Initializer::RandomUniform => Matrix::random_uniform(rows, cols, 0.0, 1.0, rng),
+            Initializer::XavierUniform => Matrix::xavier_uniform(rows, cols, rng),
+            Initializer::XavierNormal => Matrix::xavier_normal(rows, cols, rng),
+            Initializer::HeUniform => Matrix::he_uniform(rows, cols, rng),
+            Initializer::HeNormal => Matrix::he_normal(rows, cols, rng),
+            Initializer::LecunUniform => Matrix::lecun_uniform(rows, cols, rng),
+            Initializer::LecunNormal => Matrix::lecun_normal(rows, cols, rng),
+            Initializer::Orthogonal(gain) => Matrix::orthogonal(rows, cols, gain, rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn mean_and_variance(m: &Matrix) -> (f32, f32) {
+        let n = m.data.len() as f32;
+        let mean = m.data.iter().sum::<f32>() / n;
+        let var = m.data.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+        (mean, var)
+    }
+
+    #[test]
+    fn variances_follow_the_fan_in_and_fan_out() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let (rows, cols) = (200, 300);
+        // Uniform(-l, l) has variance l^2 / 3.
+        let cases = [
+            (Initializer::XavierUniform, 2.0 / 500.0),
+            (Initializer::XavierNormal, 2.0 / 500.0),
+            (Initializer::HeUniform, 2.0 / 300.0),
+            (Initializer::HeNormal, 2.0 / 300.0),
+            (Initializer::LecunUniform, 1.0 / 300.0),
+            (Initializer::LecunNormal, 1.0 / 300.0),
+        ];
+        for (init, expected) in cases {
+            let m = init.initialize(rows, cols, &mut rng);
+            assert_eq!((m.rows, m.cols), (rows, cols));
+            let (mean, var) = mean_and_variance(&m);
+            assert!(mean.abs() < 0.01, "{:?} mean {}", init, mean);
+            assert!((var / expected - 1.0).abs() < 0.05, "{:?} variance {}", init, var);
+        }
+    }
+
+    #[test]
+    fn uniform_bounds() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let m = Initializer::RandomUniform.initialize(50, 50, &mut rng);
+        assert!(m.data.iter().all(|&x| (0.0..1.0).contains(&x)));
+        let limit = (6.0f32 / 50.0).sqrt();
+        let m = Initializer::HeUniform.initialize(50, 50, &mut rng);
+        assert!(m.data.iter().all(|&x| x.abs() <= limit));
+        let m = Initializer::Zeros.initialize(3, 4, &mut rng);
+        assert!(m.data.iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn orthogonal_rows_or_columns_are_orthonormal() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for (rows, cols) in [(4, 7), (7, 4), (5, 5)] {
+            let m = Initializer::Orthogonal(2.0).initialize(rows, cols, &mut rng);
+            // Dot products of the shorter side's vectors.
+            let (count, len) = if rows < cols { (rows, cols) } else { (cols, rows) };
+            let at = |i: usize, k: usize| {
+                if rows < cols { m.data[i * cols + k] } else { m.data[k * cols + i] }
+            };
+            for i in 0..count {
+                for j in 0..count {
+                    let dot: f32 = (0..len).map(|k| at(i, k) * at(j, k)).sum();
+                    let expected = if i == j { 4.0 } else { 0.0 };
+                    assert!((dot - expected).abs() < 1e-4, "{}x{} ({}, {}): {}", rows, cols, i, j, dot);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_values() {
+        let a = Initializer::HeNormal.initialize(8, 8, &mut StdRng::seed_from_u64(9));
+        let b = Initializer::HeNormal.initialize(8, 8, &mut StdRng::seed_from_u64(9));
+        assert_eq!(a.data, b.data);
+    }
+}
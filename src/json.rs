@@ -0,0 +1,422 @@
+use std::fmt::Write;
+
+// Minimal JSON tree, just enough for model export. Numbers keep their source
+// text so f32 values round-trip exactly instead of passing through f64.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    // JSON has no non-finite numbers, so NaN and the infinities of a
+    // diverged model are written as the strings "NaN", "Infinity" and
+    // "-Infinity", which `as_f32` reads back.
+    pub fn from_f32(v: f32) -> Self {
+        if v.is_nan() {
+            JsonValue::String("NaN".to_string())
+        } else if v.is_infinite() {
+            JsonValue::String(if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string())
+        } else {
+            JsonValue::Number(format!("{}", v))
+        }
+    }
+
+    pub fn from_usize(v: usize) -> Self {
+        JsonValue::Number(v.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        let text = match self {
+            JsonValue::Number(text) | JsonValue::String(text) => text.as_str(),
+            _ => return None,
+        };
+        match text {
+            "NaN" => Some(f32::NAN),
+            "Infinity" => Some(f32::INFINITY),
+            "-Infinity" => Some(f32::NEG_INFINITY),
+            _ if matches!(self, JsonValue::Number(_)) => text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            JsonValue::Number(text) => text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    // Containers go one entry per line; arrays holding only scalars stay on a
+    // single line so matrix data does not explode into one line per value.
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        match self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            JsonValue::Number(text) => out.push_str(text),
+            JsonValue::String(s) => write_string(out, s),
+            JsonValue::Array(items) => {
+                let scalars = items
+                    .iter()
+                    .all(|v| !matches!(v, JsonValue::Array(_) | JsonValue::Object(_)));
+                if items.is_empty() || scalars {
+                    out.push('[');
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        item.write_pretty(out, indent);
+                    }
+                    out.push(']');
+                    return;
+                }
+
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    push_indent(out, indent + 1);
+                    item.write_pretty(out, indent + 1);
+                    if i + 1 < items.len() {
+                        out.push(',');
+                    }
+                    out.push('\n');
+                }
+                push_indent(out, indent);
+                out.push(']');
+            }
+            JsonValue::Object(fields) => {
+                if fields.is_empty() {
+                    out.push_str("{}");
+                    return;
+                }
+
+                out.push_str("{\n");
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    push_indent(out, indent + 1);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write_pretty(out, indent + 1);
+                    if i + 1 < fields.len() {
+                        out.push(',');
+                    }
+                    out.push('\n');
+                }
+                push_indent(out, indent);
+                out.push('}');
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<JsonValue, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> String {
+        format!("{} at byte {}", msg, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn eat_literal(&mut self, literal: &str) -> bool {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some(b't') if self.eat_literal("true") => Ok(JsonValue::Bool(true)),
+            Some(b'f') if self.eat_literal("false") => Ok(JsonValue::Bool(false)),
+            Some(b'n') if self.eat_literal("null") => Ok(JsonValue::Null),
+            // Python's json module, and files exported before non-finite
+            // values became strings, write them as bare words.
+            Some(b'N') if self.eat_literal("NaN") => Ok(JsonValue::Number("NaN".to_string())),
+            Some(b'I') if self.eat_literal("Infinity") => {
+                Ok(JsonValue::Number("Infinity".to_string()))
+            }
+            Some(b'-') if self.eat_literal("-Infinity") => {
+                Ok(JsonValue::Number("-Infinity".to_string()))
+            }
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(JsonValue::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            let value = self.parse_value()?;
+            fields.push((key, value));
+
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(fields));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(JsonValue::Array(items));
+        }
+
+        loop {
+            items.push(self.parse_value()?);
+
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+
+        loop {
+            let start = self.pos;
+            while let Some(&b) = self.bytes.get(self.pos) {
+                if b == b'"' || b == b'\\' {
+                    break;
+                }
+                self.pos += 1;
+            }
+            out.push_str(
+                std::str::from_utf8(&self.bytes[start..self.pos])
+                    .map_err(|_| self.error("invalid utf-8"))?,
+            );
+
+            match self.bytes.get(self.pos) {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(_) => {
+                    self.pos += 1;
+                    let escaped = *self
+                        .bytes
+                        .get(self.pos)
+                        .ok_or_else(|| self.error("unterminated escape"))?;
+                    self.pos += 1;
+                    match escaped {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => {
+                            let hex = self
+                                .bytes
+                                .get(self.pos..self.pos + 4)
+                                .and_then(|h| std::str::from_utf8(h).ok())
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .ok_or_else(|| self.error("invalid unicode escape"))?;
+                            self.pos += 4;
+                            out.push(char::from_u32(hex).unwrap_or('\u{fffd}'));
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        if text.parse::<f64>().is_err() {
+            return Err(self.error("invalid number"));
+        }
+        Ok(JsonValue::Number(text.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pretty_output_parses_back() {
+        let value = JsonValue::Object(vec![
+            ("name".to_string(), JsonValue::String("a \"b\"\n\\".to_string())),
+            ("flags".to_string(), JsonValue::Array(vec![JsonValue::Bool(true), JsonValue::Null])),
+            (
+                "rows".to_string(),
+                JsonValue::Array(vec![JsonValue::Array(vec![JsonValue::from_usize(3)])]),
+            ),
+            ("empty".to_string(), JsonValue::Object(Vec::new())),
+        ]);
+        let text = value.to_pretty_string();
+        assert!(text.contains("\"flags\": [true, null]"));
+        assert_eq!(JsonValue::parse(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn f32_values_round_trip_exactly() {
+        for v in [0.1f32, -3.4028235e38, 1.0e-45, 1.0 / 3.0, -0.0] {
+            let text = JsonValue::Array(vec![JsonValue::from_f32(v)]).to_pretty_string();
+            let parsed = JsonValue::parse(&text).unwrap();
+            assert_eq!(parsed.as_array().unwrap()[0].as_f32().unwrap().to_bits(), v.to_bits());
+        }
+    }
+
+    #[test]
+    fn parse_reports_malformed_input() {
+        for text in ["", "[1, 2", "{\"a\" 1}", "[1] x", "-", "\"open"] {
+            assert!(JsonValue::parse(text).is_err(), "{:?}", text);
+        }
+        let value = JsonValue::parse(" {\"a\": [1.5e3, \"\\u0041\"]} ").unwrap();
+        assert_eq!(value.get("a").unwrap().as_array().unwrap()[0].as_f32(), Some(1500.0));
+        assert_eq!(value.get("a").unwrap().as_array().unwrap()[1].as_str(), Some("A"));
+    }
+
+    #[test]
+    fn non_finite_values_are_strings() {
+        let values = [f32::NAN, f32::INFINITY, f32::NEG_INFINITY];
+        let array = JsonValue::Array(values.iter().map(|&v| JsonValue::from_f32(v)).collect());
+        let text = array.to_pretty_string();
+        assert_eq!(text.trim_end(), "[\"NaN\", \"Infinity\", \"-Infinity\"]");
+
+        // Bare words from older files and other writers still read back.
+        for text in [text.as_str(), "[NaN, Infinity, -Infinity]"] {
+            let parsed = JsonValue::parse(text).unwrap();
+            let read: Vec<f32> = parsed
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_f32().unwrap())
+                .collect();
+            assert!(read[0].is_nan());
+            assert_eq!(read[1..], values[1..]);
+        }
+        assert_eq!(JsonValue::String("1.5".to_string()).as_f32(), None);
+    }
+}
@@ -0,0 +1,913 @@
+use std::collections::HashMap;
+use std::ops::Index;
+
+use rand::rngs::StdRng;
+
+use crate::activation::Activation;
+use crate::dropout::Dropout;
+use crate::error::{Result, RustingBrainError};
+use crate::initializer::Initializer;
+use crate::matrix::Matrix;
+use crate::normalization::{NormKind, NormStats, Normalization};
+use crate::regularizer::Regularizer;
+
+// What a layer gets to know about the pass it is running in.
+pub struct Pass<'a> {
+    pub training: bool,
+    // The chunk's own generator, for dropout masks and the like.
+    pub rng: &'a mut StdRng,
+    // Statistics the layer asked for through `forward_statistics` or
+    // `backward_statistics`, summed over every chunk of the batch.
+    pub statistics: Option<&'a [f64]>,
+    pub batch_size: usize,
+}
+
+// What `forward` leaves for `backward`. Integer state such as argmax
+// positions goes in `indices` so it stays exact instead of round-tripping
+// through `f32`; layers built from other layers keep each one's cache whole
+// in `sublayers`.
+#[derive(Clone, Debug, Default)]
+pub struct Cache {
+    matrices: Vec<Matrix>,
+    indices: Vec<Vec<usize>>,
+    sublayers: Vec<Cache>,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, matrix: Matrix) {
+        self.matrices.push(matrix);
+    }
+
+    pub fn push_indices(&mut self, indices: Vec<usize>) {
+        self.indices.push(indices);
+    }
+
+    pub fn push_sublayer(&mut self, cache: Cache) {
+        self.sublayers.push(cache);
+    }
+
+    pub fn matrices(&self) -> &[Matrix] {
+        &self.matrices
+    }
+
+    pub fn indices(&self, i: usize) -> &[usize] {
+        &self.indices[i]
+    }
+
+    pub fn sublayer(&self, i: usize) -> &Cache {
+        &self.sublayers[i]
+    }
+
+    pub fn first(&self) -> Option<&Matrix> {
+        self.matrices.first()
+    }
+
+    pub fn clear(&mut self) {
+        self.matrices.clear();
+        self.indices.clear();
+        self.sublayers.clear();
+    }
+}
+
+impl Index<usize> for Cache {
+    type Output = Matrix;
+
+    fn index(&self, i: usize) -> &Matrix {
+        &self.matrices[i]
+    }
+}
+
+impl Extend<Matrix> for Cache {
+    fn extend<I: IntoIterator<Item = Matrix>>(&mut self, iter: I) {
+        self.matrices.extend(iter);
+    }
+}
+
+// Gradient of a table parameter that only touches some of its rows, such as
+// an embedding: row `i` of `values` belongs to row `rows[i]` of the table and
+// every other row's gradient is zero. Each row appears once.
+#[derive(Clone, Debug)]
+pub struct RowGradient {
+    pub rows: Vec<usize>,
+    pub values: Matrix,
+    positions: HashMap<usize, usize>,
+}
+
+impl RowGradient {
+    pub fn new(cols: usize) -> Self {
+        RowGradient {
+            rows: Vec::new(),
+            values: Matrix::new(0, cols),
+            positions: HashMap::new(),
+        }
+    }
+
+    // Adds `delta` to the gradient of table row `row`.
+    pub fn add_row(&mut self, row: usize, delta: &[f32]) {
+        let cols = self.values.cols;
+        let values = &mut self.values;
+        let rows = &mut self.rows;
+        let position = *self.positions.entry(row).or_insert_with(|| {
+            rows.push(row);
+            values.rows += 1;
+            values.data.resize(values.rows * cols, 0.0);
+            rows.len() - 1
+        });
+        let values = &mut self.values.data[position * cols..(position + 1) * cols];
+        for (v, d) in values.iter_mut().zip(delta) {
+            *v += d;
+        }
+    }
+
+    // Adds another chunk's rows, keeping this one's first.
+    pub fn add(&mut self, other: &RowGradient) {
+        let cols = other.values.cols.max(1);
+        for (&row, delta) in other.rows.iter().zip(other.values.data.chunks(cols)) {
+            self.add_row(row, delta);
+        }
+    }
+
+    // The same gradient as a dense `table_rows x cols` matrix.
+    pub fn to_dense(&self, table_rows: usize) -> Matrix {
+        let cols = self.values.cols;
+        let mut dense = Matrix::new(table_rows, cols);
+        for (i, &row) in self.rows.iter().enumerate() {
+            dense.data[row * cols..(row + 1) * cols]
+                .copy_from_slice(&self.values.data[i * cols..(i + 1) * cols]);
+        }
+        dense
+    }
+}
+
+// A building block of a `Sequential` model. Layers work on batch matrices
+// with one sample per column; a sample's features are laid out row-major
+// according to the layer's input shape. Whatever `backward` needs is left by
+// `forward` in `cache`, so the same parameters can serve many chunks of a
+// batch in parallel.
+pub trait Layer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Sizes the layer for `input_shape`, draws fresh parameters from `rng`
+    // and returns the output shape. Runs again whenever the model is
+    // reseeded.
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>>;
+
+    fn output_shape(&self) -> &[usize];
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        Vec::new()
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        Vec::new()
+    }
+
+    // Tensors that are part of the model without being trained, like the
+    // running statistics of batch normalization. Snapshots and saved files
+    // carry them along with the parameters.
+    fn state(&self) -> Vec<&Matrix> {
+        Vec::new()
+    }
+
+    fn state_mut(&mut self) -> Vec<&mut Matrix> {
+        Vec::new()
+    }
+
+    // Zeroed gradients, one per parameter and in the same order. Entries for
+    // row-sparse parameters are left empty.
+    fn gradients(&self) -> Vec<Matrix> {
+        self.parameters()
+            .iter()
+            .zip(self.row_sparse())
+            .map(|(p, sparse)| {
+                if sparse {
+                    Matrix::new(0, 0)
+                } else {
+                    Matrix::new(p.rows, p.cols)
+                }
+            })
+            .collect()
+    }
+
+    // Which parameters get row-sparse gradients, such as an embedding table
+    // of which a batch only touches a few rows. `backward` leaves their
+    // entries in `gradients` alone; `backward_rows` reports them instead.
+    fn row_sparse(&self) -> Vec<bool> {
+        vec![false; self.parameters().len()]
+    }
+
+    // Adds the gradients of the row-sparse parameters to `rows`, one per
+    // parameter flagged by `row_sparse` and in the same order. Runs after
+    // `backward` on the same error and cache.
+    fn backward_rows(&self, _error: &Matrix, _cache: &Cache, _rows: &mut [RowGradient]) {}
+
+    // Checks a batch meant for `forward` for values the layer cannot take,
+    // such as token indices out of range. Models run it on layers fed
+    // straight from a model input, so their `try_` methods fail cleanly
+    // where `forward` would panic.
+    fn check_input(&self, _input: &Matrix) -> Result<()> {
+        Ok(())
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, pass: &mut Pass) -> Matrix;
+
+    // Turns the error at the output into the error at the input, adding the
+    // parameter gradients summed over the columns to `gradients`. Errors and
+    // gradients hold the descent direction, like `Gradients`.
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix;
+
+    // The activation the layer ends with and its input, so the model can
+    // take the error at the logits (fused with the loss where it can) and
+    // continue with `backward_from_logits`.
+    fn output_activation<'c>(&self, _cache: &'c Cache) -> Option<(Activation, &'c Matrix)> {
+        None
+    }
+
+    // Only called when `output_activation` returned an activation.
+    fn backward_from_logits(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        self.backward(error, cache, gradients, pass)
+    }
+
+    // This chunk's share of statistics that have to cover the whole batch
+    // before `forward` may run in training, such as batch normalization's
+    // moments. Shares are summed in chunk order into `Pass::statistics`.
+    fn forward_statistics(&self, _input: &Matrix) -> Option<Vec<f64>> {
+        None
+    }
+
+    // Same as `forward_statistics`, for `backward`.
+    fn backward_statistics(&self, _error: &Matrix, _cache: &Cache) -> Option<Vec<f64>> {
+        None
+    }
+
+    // Receives the batch's forward statistics after a training step, e.g.
+    // to update running averages.
+    fn update_statistics(&mut self, _statistics: &[f64], _batch_size: usize) {}
+
+    // Fewest samples a training batch needs for this layer to learn.
+    fn min_batch_size(&self) -> usize {
+        1
+    }
+
+    fn regularization_loss(&self) -> f32 {
+        0.0
+    }
+
+    // Adds the penalty gradients to batch-averaged `gradients`.
+    fn add_regularization_gradients(&self, _gradients: &mut [Matrix]) {}
+}
+
+fn invalid<T>(msg: String) -> Result<T> {
+    Err(RustingBrainError::InvalidArchitecture(msg))
+}
+
+// Takes the error at an activation's output back to its input `z`.
+pub(crate) fn activation_backward(activation: Activation, z: &Matrix, error: &Matrix) -> Matrix {
+    let mut out = error.clone();
+    if activation != Activation::Softmax {
+        for (e, &x) in out.data.iter_mut().zip(&z.data) {
+            *e *= activation.derivative(x);
+        }
+        return out;
+    }
+
+    // Softmax Jacobian-vector product, one column at a time.
+    let mut s = Matrix::new(z.rows, z.cols);
+    activation.apply(z, &mut s);
+    let (rows, cols) = (z.rows, z.cols);
+    for c in 0..cols {
+        let dot: f32 = (0..rows)
+            .map(|r| error.data[r * cols + c] * s.data[r * cols + c])
+            .sum();
+        for r in 0..rows {
+            let idx = r * cols + c;
+            out.data[idx] = s.data[idx] * (error.data[idx] - dot);
+        }
+    }
+    out
+}
+
+pub(crate) fn add_to(target: &mut Matrix, source: &Matrix) {
+    for (t, s) in target.data.iter_mut().zip(&source.data) {
+        *t += s;
+    }
+}
+
+// Fully connected layer `activation(W x + b)`, what every layer of `Network`
+// is. Expects a flat input.
+pub struct Dense {
+    units: usize,
+    activation: Activation,
+    weight_init: Option<Initializer>,
+    bias_init: Initializer,
+    regularizer: Option<Regularizer>,
+    weights: Matrix,
+    biases: Matrix,
+    output_shape: Vec<usize>,
+}
+
+impl Dense {
+    // Starts linear and with `Network`'s default initializers.
+    pub fn new(units: usize) -> Self {
+        Dense {
+            units,
+            activation: Activation::Identity,
+            weight_init: None,
+            bias_init: Initializer::Zeros,
+            regularizer: None,
+            weights: Matrix::new(0, 0),
+            biases: Matrix::new(0, 0),
+            output_shape: vec![units],
+        }
+    }
+
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    pub fn with_initializer(mut self, weight_init: Initializer, bias_init: Initializer) -> Self {
+        self.weight_init = Some(weight_init);
+        self.bias_init = bias_init;
+        self
+    }
+
+    pub fn with_regularizer(mut self, regularizer: Regularizer) -> Self {
+        self.regularizer = Some(regularizer);
+        self
+    }
+
+    pub fn activation(&self) -> Activation {
+        self.activation
+    }
+
+    pub fn weights(&self) -> &Matrix {
+        &self.weights
+    }
+
+    pub fn biases(&self) -> &Matrix {
+        &self.biases
+    }
+}
+
+impl Layer for Dense {
+    fn name(&self) -> &'static str {
+        "dense"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let [inputs] = *input_shape else {
+            return invalid(format!(
+                "dense expects a flat input, got shape {:?}; add a Flatten layer first",
+                input_shape
+            ));
+        };
+        if self.units == 0 || inputs == 0 {
+            return invalid("dense layer with zero neurons".to_string());
+        }
+        let weight_init = self
+            .weight_init
+            .unwrap_or(Initializer::for_activation(self.activation));
+        self.weights = weight_init.initialize(self.units, inputs, rng);
+        self.biases = self.bias_init.initialize(self.units, 1, rng);
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        vec![&self.weights, &self.biases]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        vec![&mut self.weights, &mut self.biases]
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let cols = input.cols;
+        let mut z = Matrix::new(self.units, cols);
+        self.weights.dot(input, &mut z);
+        for r in 0..self.units {
+            let b = self.biases.data[r];
+            for v in &mut z.data[r * cols..(r + 1) * cols] {
+                *v += b;
+            }
+        }
+
+        let mut a = Matrix::new(self.units, cols);
+        self.activation.apply(&z, &mut a);
+        cache.push(input.clone());
+        cache.push(z);
+        a
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        let error = activation_backward(self.activation, &cache[1], error);
+        self.backward_from_logits(&error, cache, gradients, pass)
+    }
+
+    fn output_activation<'c>(&self, cache: &'c Cache) -> Option<(Activation, &'c Matrix)> {
+        Some((self.activation, &cache[1]))
+    }
+
+    fn backward_from_logits(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        let input = &cache[0];
+        let cols = error.cols;
+
+        let mut d_weights = Matrix::new(self.weights.rows, self.weights.cols);
+        error.dot_rhs_transposed(input, &mut d_weights);
+        add_to(&mut gradients[0], &d_weights);
+        for r in 0..self.units {
+            gradients[1].data[r] += error.data[r * cols..(r + 1) * cols].iter().sum::<f32>();
+        }
+
+        let mut input_error = Matrix::new(input.rows, cols);
+        self.weights.dot_self_transposed(error, &mut input_error);
+        input_error
+    }
+
+    fn regularization_loss(&self) -> f32 {
+        match &self.regularizer {
+            Some(reg) if reg.include_biases => {
+                reg.penalty(&self.weights) + reg.penalty(&self.biases)
+            }
+            Some(reg) => reg.penalty(&self.weights),
+            None => 0.0,
+        }
+    }
+
+    fn add_regularization_gradients(&self, gradients: &mut [Matrix]) {
+        if let Some(reg) = &self.regularizer {
+            reg.add_gradient(&self.weights, &mut gradients[0]);
+            if reg.include_biases {
+                reg.add_gradient(&self.biases, &mut gradients[1]);
+            }
+        }
+    }
+}
+
+// Applies an activation on its own, e.g. after a normalization layer.
+pub struct ActivationLayer {
+    activation: Activation,
+    output_shape: Vec<usize>,
+}
+
+impl ActivationLayer {
+    pub fn new(activation: Activation) -> Self {
+        ActivationLayer {
+            activation,
+            output_shape: Vec::new(),
+        }
+    }
+}
+
+impl Layer for ActivationLayer {
+    fn name(&self) -> &'static str {
+        "activation"
+    }
+
+    fn build(&mut self, input_shape: &[usize], _rng: &mut StdRng) -> Result<Vec<usize>> {
+        self.output_shape = input_shape.to_vec();
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let mut a = Matrix::new(input.rows, input.cols);
+        self.activation.apply(input, &mut a);
+        cache.push(input.clone());
+        a
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        activation_backward(self.activation, &cache[0], error)
+    }
+
+    fn output_activation<'c>(&self, cache: &'c Cache) -> Option<(Activation, &'c Matrix)> {
+        Some((self.activation, &cache[0]))
+    }
+
+    fn backward_from_logits(
+        &self,
+        error: &Matrix,
+        _cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        error.clone()
+    }
+}
+
+// Reshapes any input to a flat vector. Samples are stored flat already, so
+// only the shape changes.
+pub struct Flatten {
+    output_shape: Vec<usize>,
+}
+
+impl Flatten {
+    pub fn new() -> Self {
+        Flatten {
+            output_shape: Vec::new(),
+        }
+    }
+}
+
+impl Default for Flatten {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Layer for Flatten {
+    fn name(&self) -> &'static str {
+        "flatten"
+    }
+
+    fn build(&mut self, input_shape: &[usize], _rng: &mut StdRng) -> Result<Vec<usize>> {
+        self.output_shape = vec![input_shape.iter().product()];
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn forward(&self, input: &Matrix, _cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        input.clone()
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        _cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        error.clone()
+    }
+}
+
+// Dropout between layers; only active while training.
+pub struct DropoutLayer {
+    dropout: Dropout,
+    output_shape: Vec<usize>,
+}
+
+impl DropoutLayer {
+    pub fn new(dropout: Dropout) -> Self {
+        DropoutLayer {
+            dropout,
+            output_shape: Vec::new(),
+        }
+    }
+}
+
+impl Layer for DropoutLayer {
+    fn name(&self) -> &'static str {
+        "dropout"
+    }
+
+    fn build(&mut self, input_shape: &[usize], _rng: &mut StdRng) -> Result<Vec<usize>> {
+        if !(0.0..1.0).contains(&self.dropout.rate()) {
+            return invalid(format!(
+                "dropout rate must be in [0, 1), got {}",
+                self.dropout.rate()
+            ));
+        }
+        self.output_shape = input_shape.to_vec();
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, pass: &mut Pass) -> Matrix {
+        let mut a = input.clone();
+        if pass.training {
+            let mut mask = Matrix::new(input.rows, input.cols);
+            self.dropout.apply(&mut a.data, &mut mask.data, pass.rng);
+            cache.push(mask);
+        }
+        a
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        let mut out = error.clone();
+        if let Some(mask) = cache.first() {
+            for (e, m) in out.data.iter_mut().zip(&mask.data) {
+                *e *= m;
+            }
+        }
+        out
+    }
+}
+
+// Batch or layer normalization over all of a sample's features, with a
+// learned scale and shift. Batch statistics cover the whole batch even when
+// it is split across threads; a single sample has none, so batch
+// normalization refuses to train on batches of one.
+pub struct NormalizationLayer {
+    norm: Normalization,
+    output_shape: Vec<usize>,
+}
+
+impl NormalizationLayer {
+    pub fn batch() -> Self {
+        Self::new(Normalization::batch(0))
+    }
+
+    pub fn layer() -> Self {
+        Self::new(Normalization::layer(0))
+    }
+
+    fn new(norm: Normalization) -> Self {
+        NormalizationLayer {
+            norm,
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f32) -> Self {
+        self.norm = self.norm.with_epsilon(epsilon);
+        self
+    }
+
+    pub fn with_momentum(mut self, momentum: f32) -> Self {
+        self.norm = self.norm.with_momentum(momentum);
+        self
+    }
+
+    pub fn normalization(&self) -> &Normalization {
+        &self.norm
+    }
+
+    fn uses_batch_statistics(&self, pass: &Pass) -> bool {
+        self.norm.is_batch() && pass.training
+    }
+}
+
+impl Layer for NormalizationLayer {
+    fn name(&self) -> &'static str {
+        "normalization"
+    }
+
+    fn build(&mut self, input_shape: &[usize], _rng: &mut StdRng) -> Result<Vec<usize>> {
+        let features = input_shape.iter().product();
+        let fresh = match self.norm.kind {
+            NormKind::Batch { momentum } => Normalization::batch(features).with_momentum(momentum),
+            NormKind::Layer => Normalization::layer(features),
+        };
+        self.norm = fresh.with_epsilon(self.norm.epsilon);
+        self.output_shape = input_shape.to_vec();
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        vec![&self.norm.gamma, &self.norm.beta]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        vec![&mut self.norm.gamma, &mut self.norm.beta]
+    }
+
+    fn state(&self) -> Vec<&Matrix> {
+        if !self.norm.is_batch() {
+            return Vec::new();
+        }
+        vec![&self.norm.running_mean, &self.norm.running_var]
+    }
+
+    fn state_mut(&mut self) -> Vec<&mut Matrix> {
+        if !self.norm.is_batch() {
+            return Vec::new();
+        }
+        vec![&mut self.norm.running_mean, &mut self.norm.running_var]
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, pass: &mut Pass) -> Matrix {
+        let stats = match pass.statistics {
+            Some(moments) if self.uses_batch_statistics(pass) => {
+                let (sums, squares) = moments.split_at(moments.len() / 2);
+                self.norm.batch_stats(sums, squares, pass.batch_size).0
+            }
+            _ if self.norm.is_batch() => self.norm.inference_stats(input),
+            _ => self.norm.sample_stats(input),
+        };
+
+        let mut out = input.clone();
+        let x_hat = self.norm.normalize(&mut out, &stats);
+        cache.push(x_hat);
+        cache.push(Matrix {
+            rows: stats.mean.len(),
+            cols: 1,
+            data: stats.mean,
+        });
+        cache.push(Matrix {
+            rows: stats.inv_std.len(),
+            cols: 1,
+            data: stats.inv_std,
+        });
+        out
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        let x_hat = &cache[0];
+        let stats = NormStats {
+            mean: cache[1].data.clone(),
+            inv_std: cache[2].data.clone(),
+        };
+
+        let (d_gamma, d_beta) = Normalization::parameter_gradients(error, x_hat);
+        add_to(&mut gradients[0], &d_gamma);
+        add_to(&mut gradients[1], &d_beta);
+
+        let totals = match pass.statistics {
+            Some(totals) if self.uses_batch_statistics(pass) => {
+                let (g, b) = totals.split_at(totals.len() / 2);
+                let column = |v: &[f64]| Matrix {
+                    rows: v.len(),
+                    cols: 1,
+                    data: v.iter().map(|&x| x as f32).collect(),
+                };
+                Some((column(g), column(b)))
+            }
+            _ => None,
+        };
+
+        let mut out = error.clone();
+        let totals = totals.as_ref().map(|(g, b)| (g, b, pass.batch_size));
+        self.norm.backward(&mut out, x_hat, &stats, totals);
+        out
+    }
+
+    fn forward_statistics(&self, input: &Matrix) -> Option<Vec<f64>> {
+        if !self.norm.is_batch() {
+            return None;
+        }
+        let (mut sums, squares) = Normalization::partial_moments(input);
+        sums.extend(squares);
+        Some(sums)
+    }
+
+    fn backward_statistics(&self, error: &Matrix, cache: &Cache) -> Option<Vec<f64>> {
+        if !self.norm.is_batch() {
+            return None;
+        }
+        let (d_gamma, d_beta) = Normalization::parameter_gradients(error, &cache[0]);
+        Some(
+            d_gamma
+                .data
+                .iter()
+                .chain(&d_beta.data)
+                .map(|&x| x as f64)
+                .collect(),
+        )
+    }
+
+    fn update_statistics(&mut self, statistics: &[f64], batch_size: usize) {
+        let (sums, squares) = statistics.split_at(statistics.len() / 2);
+        let (stats, var) = self.norm.batch_stats(sums, squares, batch_size);
+        self.norm.update_running(&stats.mean, &var);
+    }
+
+    fn min_batch_size(&self) -> usize {
+        if self.norm.is_batch() { 2 } else { 1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::check_layer;
+
+    #[test]
+    fn dense_gradients() {
+        for activation in [
+            Activation::Identity,
+            Activation::Sigmoid,
+            Activation::Tanh,
+            Activation::Gelu,
+            Activation::Silu,
+            Activation::Softplus,
+            Activation::Softmax,
+        ] {
+            check_layer(Dense::new(3).with_activation(activation), &[4], 3);
+        }
+    }
+
+    #[test]
+    fn dense_initializers_follow_the_activation() {
+        use rand::SeedableRng;
+        let mut rng = StdRng::seed_from_u64(4);
+        // Set after `new`, the activation still decides the weights.
+        let mut relu = Dense::new(40).with_activation(Activation::Relu);
+        relu.build(&[50], &mut rng).unwrap();
+        let mut tanh = Dense::new(40).with_activation(Activation::Tanh);
+        tanh.build(&[50], &mut rng).unwrap();
+
+        let largest = |d: &Dense| d.weights().data.iter().fold(0.0f32, |m, w| m.max(w.abs()));
+        assert!(largest(&relu) > (6.0f32 / 90.0).sqrt());
+        assert!(largest(&tanh) <= (6.0f32 / 90.0).sqrt());
+        assert!(relu.biases().data.iter().chain(&tanh.biases().data).all(|&b| b == 0.0));
+    }
+
+    #[test]
+    fn activation_layer_gradients() {
+        for activation in [Activation::Elu(1.0), Activation::Selu, Activation::Softmax] {
+            check_layer(ActivationLayer::new(activation), &[5], 2);
+        }
+    }
+
+    #[test]
+    fn flatten_gradients() {
+        check_layer(Flatten::new(), &[2, 3, 2], 2);
+    }
+
+    #[test]
+    fn dropout_gradients() {
+        check_layer(DropoutLayer::new(Dropout::Standard(0.3)), &[6], 3);
+        check_layer(DropoutLayer::new(Dropout::Alpha(0.3)), &[6], 3);
+    }
+
+    #[test]
+    fn batch_normalization_gradients() {
+        check_layer(NormalizationLayer::batch(), &[3], 4);
+    }
+
+    #[test]
+    fn layer_normalization_gradients() {
+        check_layer(NormalizationLayer::layer(), &[5], 3);
+    }
+
+    #[test]
+    fn row_gradients_merge_repeated_rows() {
+        let mut a = RowGradient::new(2);
+        a.add_row(3, &[1.0, 2.0]);
+        a.add_row(0, &[0.5, 0.5]);
+        let mut b = RowGradient::new(2);
+        b.add_row(3, &[1.0, -1.0]);
+        b.add_row(1, &[2.0, 0.0]);
+        a.add(&b);
+        assert_eq!(a.rows, vec![3, 0, 1]);
+        assert_eq!(
+            a.to_dense(4).data,
+            vec![0.5, 0.5, 2.0, 0.0, 0.0, 0.0, 2.0, 1.0]
+        );
+    }
+}
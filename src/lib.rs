@@ -0,0 +1,58 @@
+pub mod activation;
+pub mod attention;
+pub mod autograd;
+pub mod callback;
+pub mod clipping;
+pub mod conv;
+pub mod dropout;
+pub mod embedding;
+pub mod error;
+#[cfg(test)]
+mod gradcheck;
+pub mod graph;
+pub mod initializer;
+pub mod json;
+pub mod layer;
+pub mod loss;
+pub mod matrix;
+pub mod model;
+pub mod network;
+pub mod normalization;
+pub mod optimizer;
+pub mod persistence;
+pub mod pooling;
+pub mod recurrent;
+pub mod regularizer;
+pub mod scheduler;
+pub mod sequential;
+pub mod transformer;
+pub mod trainer;
+
+pub use activation::Activation;
+pub use attention::{MultiHeadAttention, PositionalEncoding, scaled_dot_product_attention};
+pub use autograd::{Tape, TapeGradients, Variable};
+pub use callback::{Callback, CsvLogger, EarlyStopping, ModelCheckpoint, Monitor};
+pub use clipping::GradientClipping;
+pub use conv::{Conv1D, Conv2D, Padding};
+pub use dropout::Dropout;
+pub use embedding::Embedding;
+pub use error::{Result, RustingBrainError};
+pub use graph::{Graph, Node};
+pub use initializer::Initializer;
+pub use layer::{ActivationLayer, Dense, DropoutLayer, Flatten, Layer, NormalizationLayer, Pass};
+pub use loss::Loss;
+pub use matrix::Matrix;
+pub use model::Model;
+pub use network::{Gradients, Network};
+pub use normalization::{NormKind, Normalization};
+pub use optimizer::{Adagrad, Adam, AdamW, Optimizer, RmsProp, Sgd};
+pub use pooling::{AvgPool2D, GlobalAveragePooling, MaxPool1D, MaxPool2D};
+pub use recurrent::{Cell, Recurrent};
+pub use regularizer::Regularizer;
+pub use scheduler::{
+    CosineAnnealingWarmRestarts, ExponentialDecay, Interval, LinearWarmup, LrScheduler, OneCycle,
+    ReduceOnPlateau, StepDecay,
+};
+pub use sequential::Sequential;
+pub use trainer::{EpochStats, History, Trainer};
+pub use transformer::TransformerEncoder;
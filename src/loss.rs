@@ -0,0 +1,316 @@
+use crate::activation::{softmax_cross_entropy, Activation};
+
+const EPSILON: f32 = 1e-7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Loss {
+    MeanSquaredError,
+    MeanAbsoluteError,
+    Huber(f32),
+    BinaryCrossEntropy,
+    CategoricalCrossEntropy,
+    Hinge,
+    KlDivergence,
+}
+
+impl Loss {
+    // Loss of a single sample, averaged over its outputs for the element-wise
+    // losses and summed over classes for the distribution losses.
+    pub fn value(&self, output: &[f32], target: &[f32]) -> f32 {
+        let n = output.len() as f32;
+        let pairs = output.iter().zip(target);
+
+        match *self {
+            Loss::MeanSquaredError => pairs.map(|(y, t)| (y - t) * (y - t)).sum::<f32>() / n,
+            Loss::MeanAbsoluteError => pairs.map(|(y, t)| (y - t).abs()).sum::<f32>() / n,
+            Loss::Huber(delta) => {
+                pairs
+                    .map(|(y, t)| {
+                        let d = (y - t).abs();
+                        if d <= delta {
+                            0.5 * d * d
+                        } else {
+                            delta * (d - 0.5 * delta)
+                        }
+                    })
+                    .sum::<f32>()
+                    / n
+            }
+            Loss::BinaryCrossEntropy => {
+                pairs
+                    .map(|(&y, &t)| {
+                        let y = y.clamp(EPSILON, 1.0 - EPSILON);
+                        -(t * y.ln() + (1.0 - t) * (1.0 - y).ln())
+                    })
+                    .sum::<f32>()
+                    / n
+            }
+            Loss::CategoricalCrossEntropy => {
+                pairs.map(|(&y, &t)| -t * y.max(EPSILON).ln()).sum()
+            }
+            Loss::Hinge => pairs.map(|(y, t)| (1.0 - t * y).max(0.0)).sum::<f32>() / n,
+            Loss::KlDivergence => pairs
+                .filter(|(_, t)| **t > 0.0)
+                .map(|(&y, &t)| t * (t / y.max(EPSILON)).ln())
+                .sum(),
+        }
+    }
+
+    // Same as `value`, but for a softmax head the distribution losses are
+    // computed straight from the logits through log-sum-exp.
+    pub fn value_with_logits(
+        &self,
+        activation: Activation,
+        output: &[f32],
+        logits: &[f32],
+        target: &[f32],
+    ) -> f32 {
+        if activation != Activation::Softmax {
+            return self.value(output, target);
+        }
+
+        match *self {
+            Loss::CategoricalCrossEntropy => softmax_cross_entropy(logits, target),
+            Loss::KlDivergence => {
+                let entropy: f32 = target
+                    .iter()
+                    .filter(|t| **t > 0.0)
+                    .map(|t| t * t.ln())
+                    .sum();
+                softmax_cross_entropy(logits, target) + entropy
+            }
+            _ => self.value(output, target),
+        }
+    }
+
+    // d(value)/d(output) for one of a sample's `outputs` outputs, so the
+    // averaged losses carry the 1/outputs of `value` and MSE its factor 2.
+    #[inline(always)]
+    pub fn gradient(&self, output: f32, target: f32, outputs: usize) -> f32 {
+        let d = output - target;
+        let n = outputs as f32;
+
+        match *self {
+            Loss::MeanSquaredError => 2.0 * d / n,
+            Loss::MeanAbsoluteError => sign(d) / n,
+            Loss::Huber(delta) => d.clamp(-delta, delta) / n,
+            Loss::BinaryCrossEntropy => {
+                let y = output.clamp(EPSILON, 1.0 - EPSILON);
+                d / (y * (1.0 - y)) / n
+            }
+            Loss::CategoricalCrossEntropy | Loss::KlDivergence => -target / output.max(EPSILON),
+            Loss::Hinge => {
+                if target * output < 1.0 { -target / n } else { 0.0 }
+            }
+        }
+    }
+
+    // Whether the error at the pre-activation for this output activation
+    // has a closed form, `target - output` (divided by the output count for
+    // binary cross-entropy, which averages), so the activation derivative
+    // (or softmax Jacobian) can be skipped.
+    pub fn is_fused_with(&self, activation: Activation) -> bool {
+        matches!(
+            (self, activation),
+            (Loss::CategoricalCrossEntropy | Loss::KlDivergence, Activation::Softmax)
+                | (Loss::BinaryCrossEntropy, Activation::Sigmoid)
+        )
+    }
+
+    // Fills `error` with the negative loss gradient with respect to the
+    // output layer's weighted sums. Matrices are laid out one sample per
+    // column, `cols` wide, matching the batched training path.
+    pub fn output_error(
+        &self,
+        activation: Activation,
+        output: &[f32],
+        z: &[f32],
+        target: &[f32],
+        cols: usize,
+        error: &mut [f32],
+    ) {
+        let rows = error.len() / cols;
+        if self.is_fused_with(activation) {
+            let scale = if *self == Loss::BinaryCrossEntropy {
+                1.0 / rows as f32
+            } else {
+                1.0
+            };
+            for i in 0..error.len() {
+                error[i] = (target[i] - output[i]) * scale;
+            }
+            return;
+        }
+
+        if activation != Activation::Softmax {
+            for i in 0..error.len() {
+                error[i] =
+                    -self.gradient(output[i], target[i], rows) * activation.derivative(z[i]);
+            }
+            return;
+        }
+
+        // Softmax Jacobian-vector product: dz_i = s_i * (g_i - sum_j g_j * s_j).
+        for c in 0..cols {
+            let mut dot = 0.0;
+            for r in 0..rows {
+                let idx = r * cols + c;
+                let g = self.gradient(output[idx], target[idx], rows);
+                error[idx] = g;
+                dot += g * output[idx];
+            }
+            for r in 0..rows {
+                let idx = r * cols + c;
+                error[idx] = -output[idx] * (error[idx] - dot);
+            }
+        }
+    }
+}
+
+#[inline(always)]
+pub(crate) fn sign(x: f32) -> f32 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn values_average_or_sum_as_documented() {
+        let (y, t) = ([0.5, -1.0], [1.0, 1.0]);
+        assert!(close(Loss::MeanSquaredError.value(&y, &t), (0.25 + 4.0) / 2.0));
+        assert!(close(Loss::MeanAbsoluteError.value(&y, &t), (0.5 + 2.0) / 2.0));
+        // Quadratic within delta, linear beyond it.
+        assert!(close(Loss::Huber(1.0).value(&y, &t), (0.125 + 1.5) / 2.0));
+        assert!(close(Loss::Hinge.value(&y, &t), (0.5 + 2.0) / 2.0));
+
+        let (p, t) = ([0.8, 0.4], [1.0, 0.0]);
+        let bce = -(0.8f32.ln() + 0.6f32.ln()) / 2.0;
+        assert!(close(Loss::BinaryCrossEntropy.value(&p, &t), bce));
+
+        let (p, t) = ([0.7, 0.2, 0.1], [0.5, 0.5, 0.0]);
+        let cce = -(0.5 * 0.7f32.ln() + 0.5 * 0.2f32.ln());
+        assert!(close(Loss::CategoricalCrossEntropy.value(&p, &t), cce));
+        let kl = 0.5 * (0.5f32 / 0.7).ln() + 0.5 * (0.5f32 / 0.2).ln();
+        assert!(close(Loss::KlDivergence.value(&p, &t), kl));
+    }
+
+    #[test]
+    fn cross_entropy_is_finite_at_saturated_outputs() {
+        assert!(Loss::BinaryCrossEntropy.value(&[1.0, 0.0], &[0.0, 1.0]).is_finite());
+        assert!(Loss::CategoricalCrossEntropy.value(&[0.0, 1.0], &[1.0, 0.0]).is_finite());
+    }
+
+    #[test]
+    fn softmax_values_come_from_the_logits() {
+        let logits = [2.0, -1.0, 0.5];
+        let z = crate::matrix::Matrix {
+            rows: 3,
+            cols: 1,
+            data: logits.to_vec(),
+        };
+        let mut output = crate::matrix::Matrix::new(3, 1);
+        Activation::Softmax.apply(&z, &mut output);
+        let target = [0.2, 0.3, 0.5];
+        for loss in [Loss::CategoricalCrossEntropy, Loss::KlDivergence] {
+            let direct = loss.value(&output.data, &target);
+            let fused = loss.value_with_logits(Activation::Softmax, &output.data, &logits, &target);
+            assert!(close(direct, fused), "{:?}", loss);
+        }
+    }
+
+    #[test]
+    fn fused_softmax_error_is_the_negative_logit_gradient() {
+        let logits = [2.0f32, -1.0, 0.5];
+        let target = [0.0, 1.0, 0.0];
+        let value = |z: &[f32]| softmax_cross_entropy(z, &target);
+        let mut output = crate::matrix::Matrix::new(3, 1);
+        let z = crate::matrix::Matrix {
+            rows: 3,
+            cols: 1,
+            data: logits.to_vec(),
+        };
+        Activation::Softmax.apply(&z, &mut output);
+
+        let mut error = [0.0; 3];
+        let loss = Loss::CategoricalCrossEntropy;
+        assert!(loss.is_fused_with(Activation::Softmax));
+        loss.output_error(Activation::Softmax, &output.data, &logits, &target, 1, &mut error);
+        for i in 0..3 {
+            let (mut plus, mut minus) = (logits, logits);
+            plus[i] += 1e-3;
+            minus[i] -= 1e-3;
+            let numeric = (value(&plus) - value(&minus)) / 2e-3;
+            assert!((error[i] + numeric).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn gradients_are_the_derivative_of_the_value() {
+        let output = [0.3f32, -0.8, 0.6, 0.9];
+        let target = [1.0f32, -1.0, 0.0, 1.0];
+        let probabilities = [0.3f32, 0.15, 0.6, 0.9];
+        for loss in [
+            Loss::MeanSquaredError,
+            Loss::MeanAbsoluteError,
+            Loss::Huber(0.5),
+            Loss::BinaryCrossEntropy,
+            Loss::CategoricalCrossEntropy,
+            Loss::Hinge,
+            Loss::KlDivergence,
+        ] {
+            let output = match loss {
+                Loss::BinaryCrossEntropy | Loss::CategoricalCrossEntropy | Loss::KlDivergence => {
+                    probabilities
+                }
+                _ => output,
+            };
+            let target = match loss {
+                Loss::BinaryCrossEntropy => [1.0, 0.0, 0.0, 1.0],
+                Loss::CategoricalCrossEntropy | Loss::KlDivergence => [0.1, 0.2, 0.3, 0.4],
+                _ => target,
+            };
+            for i in 0..4 {
+                let (mut plus, mut minus) = (output, output);
+                plus[i] += 1e-3;
+                minus[i] -= 1e-3;
+                let numeric = (loss.value(&plus, &target) - loss.value(&minus, &target)) / 2e-3;
+                let analytic = loss.gradient(output[i], target[i], 4);
+                assert!((analytic - numeric).abs() < 2e-3, "{:?} output {}", loss, i);
+            }
+        }
+    }
+
+    #[test]
+    fn fused_sigmoid_error_averages_over_the_outputs() {
+        let z = [0.4f32, -1.2, 2.0];
+        let target = [1.0, 0.0, 1.0];
+        let sigmoid = |z: &[f32]| -> Vec<f32> {
+            z.iter().map(|&v| Activation::Sigmoid.activate(v)).collect()
+        };
+        let value = |z: &[f32]| Loss::BinaryCrossEntropy.value(&sigmoid(z), &target);
+
+        let mut error = [0.0; 3];
+        let output = sigmoid(&z);
+        let loss = Loss::BinaryCrossEntropy;
+        loss.output_error(Activation::Sigmoid, &output, &z, &target, 1, &mut error);
+        for i in 0..3 {
+            let (mut plus, mut minus) = (z, z);
+            plus[i] += 1e-3;
+            minus[i] -= 1e-3;
+            let numeric = (value(&plus) - value(&minus)) / 2e-3;
+            assert!((error[i] + numeric).abs() < 1e-3);
+        }
+    }
+}
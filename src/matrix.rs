@@ -17,19 +17,122 @@ impl Matrix {
     }
 
     pub fn random(rows: usize, cols: usize) -> Self {
-        let mut rng = rand::thread_rng();
-        let data: Vec<f32> = (0..rows * cols).map(|_| rng.gen_range(0.0..1.0)).collect();
+        Self::random_uniform(rows, cols, 0.0, 1.0, &mut rand::thread_rng())
+    }
+
+    pub fn random_uniform<R: Rng + ?Sized>(
+        rows: usize,
+        cols: usize,
+        low: f32,
+        high: f32,
+        rng: &mut R,
+    ) -> Self {
+        let data: Vec<f32> = (0..rows * cols).map(|_| rng.gen_range(low..high)).collect();
+        Self { rows, cols, data }
+    }
+
+    pub fn random_normal<R: Rng + ?Sized>(
+        rows: usize,
+        cols: usize,
+        mean: f32,
+        std_dev: f32,
+        rng: &mut R,
+    ) -> Self {
+        let data: Vec<f32> = (0..rows * cols)
+            .map(|_| mean + std_dev * sample_standard_normal(rng))
+            .collect();
         Self { rows, cols, data }
     }
 
+    // Fan-in is the number of inputs (`cols`) and fan-out the number of
+    // outputs (`rows`), matching how `Network` lays out its weights.
+    pub fn xavier_uniform<R: Rng + ?Sized>(rows: usize, cols: usize, rng: &mut R) -> Self {
+        let limit = (6.0 / (rows + cols) as f32).sqrt();
+        Self::random_uniform(rows, cols, -limit, limit, rng)
+    }
+
+    pub fn xavier_normal<R: Rng + ?Sized>(rows: usize, cols: usize, rng: &mut R) -> Self {
+        let std_dev = (2.0 / (rows + cols) as f32).sqrt();
+        Self::random_normal(rows, cols, 0.0, std_dev, rng)
+    }
+
+    pub fn he_uniform<R: Rng + ?Sized>(rows: usize, cols: usize, rng: &mut R) -> Self {
+        let limit = (6.0 / cols as f32).sqrt();
+        Self::random_uniform(rows, cols, -limit, limit, rng)
+    }
+
+    pub fn he_normal<R: Rng + ?Sized>(rows: usize, cols: usize, rng: &mut R) -> Self {
+        let std_dev = (2.0 / cols as f32).sqrt();
+        Self::random_normal(rows, cols, 0.0, std_dev, rng)
+    }
+
+    pub fn lecun_uniform<R: Rng + ?Sized>(rows: usize, cols: usize, rng: &mut R) -> Self {
+        let limit = (3.0 / cols as f32).sqrt();
+        Self::random_uniform(rows, cols, -limit, limit, rng)
+    }
+
+    pub fn lecun_normal<R: Rng + ?Sized>(rows: usize, cols: usize, rng: &mut R) -> Self {
+        let std_dev = (1.0 / cols as f32).sqrt();
+        Self::random_normal(rows, cols, 0.0, std_dev, rng)
+    }
+
+    // Orthonormal rows (or columns, whichever there are fewer of) scaled by
+    // `gain`, built by Gram-Schmidt over a Gaussian matrix.
+    pub fn orthogonal<R: Rng + ?Sized>(rows: usize, cols: usize, gain: f32, rng: &mut R) -> Self {
+        let (count, len) = if rows < cols { (rows, cols) } else { (cols, rows) };
+        let mut vectors = Self::random_normal(count, len, 0.0, 1.0, rng);
+
+        for i in 0..count {
+            let (done, rest) = vectors.data.split_at_mut(i * len);
+            let v = &mut rest[..len];
+
+            for j in 0..i {
+                let u = &done[j * len..(j + 1) * len];
+                let proj: f32 = u.iter().zip(v.iter()).map(|(a, b)| a * b).sum();
+                for (x, &y) in v.iter_mut().zip(u) {
+                    *x -= proj * y;
+                }
+            }
+
+            let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt().max(f32::EPSILON);
+            for x in v.iter_mut() {
+                *x /= norm;
+            }
+        }
+
+        let mut m = Self::new(rows, cols);
+        for i in 0..count {
+            for k in 0..len {
+                let v = gain * vectors.data[i * len + k];
+                if rows < cols {
+                    m.data[i * cols + k] = v;
+                } else {
+                    m.data[k * cols + i] = v;
+                }
+            }
+        }
+        m
+    }
+
+    // The sgemm wrappers below hand raw pointers and strides to
+    // matrixmultiply, so shapes are checked in release builds too: a
+    // mismatch would otherwise read or write out of bounds.
+    #[inline(always)]
+    fn assert_consistent(&self) {
+        assert_eq!(self.data.len(), self.rows * self.cols, "matrix data does not match its shape");
+    }
+
     pub fn zeros(&mut self) {
         self.data.fill(0.0);
     }
 
     pub fn dot(&self, other: &Matrix, target: &mut Matrix) {
-        debug_assert_eq!(self.cols, other.rows);
-        debug_assert_eq!(target.rows, self.rows);
-        debug_assert_eq!(target.cols, other.cols);
+        self.assert_consistent();
+        other.assert_consistent();
+        target.assert_consistent();
+        assert_eq!(self.cols, other.rows);
+        assert_eq!(target.rows, self.rows);
+        assert_eq!(target.cols, other.cols);
 
         unsafe {
             matrixmultiply::sgemm(
@@ -52,9 +155,12 @@ impl Matrix {
     }
 
     pub fn dot_rhs_transposed(&self, other: &Matrix, target: &mut Matrix) {
-        debug_assert_eq!(self.cols, other.cols);
-        debug_assert_eq!(target.rows, self.rows);
-        debug_assert_eq!(target.cols, other.rows);
+        self.assert_consistent();
+        other.assert_consistent();
+        target.assert_consistent();
+        assert_eq!(self.cols, other.cols);
+        assert_eq!(target.rows, self.rows);
+        assert_eq!(target.cols, other.rows);
 
         unsafe {
             matrixmultiply::sgemm(
@@ -77,9 +183,12 @@ impl Matrix {
     }
 
     pub fn dot_self_transposed(&self, other: &Matrix, target: &mut Matrix) {
-        debug_assert_eq!(self.rows, other.rows);
-        debug_assert_eq!(target.rows, self.cols);
-        debug_assert_eq!(target.cols, other.cols);
+        self.assert_consistent();
+        other.assert_consistent();
+        target.assert_consistent();
+        assert_eq!(self.rows, other.rows);
+        assert_eq!(target.rows, self.cols);
+        assert_eq!(target.cols, other.cols);
 
         unsafe {
             matrixmultiply::sgemm(
@@ -102,9 +211,12 @@ impl Matrix {
     }
 
     pub fn outer_product(&self, input: &Matrix, target: &mut Matrix) {
-        debug_assert_eq!(input.cols, 1);
-        debug_assert_eq!(target.rows, self.rows);
-        debug_assert_eq!(target.cols, input.rows);
+        self.assert_consistent();
+        input.assert_consistent();
+        target.assert_consistent();
+        assert_eq!(input.cols, 1);
+        assert_eq!(target.rows, self.rows);
+        assert_eq!(target.cols, input.rows);
 
         unsafe {
             matrixmultiply::sgemm(
@@ -127,9 +239,12 @@ impl Matrix {
     }
 
     pub fn dot_transpose_self(&self, error: &Matrix, target: &mut Matrix) {
-        debug_assert_eq!(self.rows, error.rows);
-        debug_assert_eq!(target.rows, self.cols);
-        debug_assert_eq!(target.cols, 1);
+        self.assert_consistent();
+        error.assert_consistent();
+        target.assert_consistent();
+        assert_eq!(self.rows, error.rows);
+        assert_eq!(target.rows, self.cols);
+        assert_eq!(target.cols, 1);
 
         unsafe {
             matrixmultiply::sgemm(
@@ -154,4 +269,71 @@ impl Matrix {
     pub fn copy_from_slice(&mut self, source: &[f32]) {
         self.data.copy_from_slice(source);
     }
-}   
+
+    // Batch matrix with one sample per column, the layout every batched
+    // forward and backward pass works in.
+    pub fn from_columns(samples: &[Vec<f32>]) -> Self {
+        let rows = samples.first().map_or(0, Vec::len);
+        let cols = samples.len();
+        let mut m = Matrix::new(rows, cols);
+        for (c, sample) in samples.iter().enumerate() {
+            for (r, &v) in sample.iter().enumerate() {
+                m.data[r * cols + c] = v;
+            }
+        }
+        m
+    }
+
+    pub fn column(&self, c: usize) -> Vec<f32> {
+        (0..self.rows).map(|r| self.data[r * self.cols + c]).collect()
+    }
+}
+
+// Box-Muller transform; `rand` alone does not ship a normal distribution.
+pub(crate) fn sample_standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f32 {
+    let u1: f32 = 1.0 - rng.gen_range(0.0..1.0);
+    let u2: f32 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_multiplies() {
+        let a = Matrix {
+            rows: 2,
+            cols: 3,
+            data: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        };
+        let b = Matrix {
+            rows: 3,
+            cols: 1,
+            data: vec![1.0, 0.0, -1.0],
+        };
+        let mut c = Matrix::new(2, 1);
+        a.dot(&b, &mut c);
+        assert_eq!(c.data, vec![-2.0, -2.0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn dot_checks_shapes_in_release_builds_too() {
+        let a = Matrix::new(2, 3);
+        let mut c = Matrix::new(2, 1);
+        a.dot(&Matrix::new(2, 1), &mut c);
+    }
+
+    #[test]
+    #[should_panic(expected = "matrix data does not match its shape")]
+    fn dot_checks_the_data_length() {
+        let a = Matrix {
+            rows: 2,
+            cols: 2,
+            data: vec![1.0; 3],
+        };
+        let mut c = Matrix::new(2, 1);
+        a.dot(&Matrix::new(2, 1), &mut c);
+    }
+}
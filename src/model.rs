@@ -0,0 +1,223 @@
+use std::path::Path;
+
+use rand::rngs::StdRng;
+
+use crate::clipping::{self, GradientClipping};
+use crate::error::{Result, RustingBrainError};
+use crate::layer::RowGradient;
+use crate::matrix::Matrix;
+use crate::optimizer::{Optimizer, Sgd};
+use crate::scheduler::{Interval, LrScheduler};
+
+// What `Trainer` and its callbacks need from a model. `Network`,
+// `Sequential` and `Graph` all implement it, so any of them can be fitted,
+// stopped early and checkpointed.
+pub trait Model {
+    // The model's random source; `Trainer` shuffles with it.
+    fn rng(&mut self) -> &mut StdRng;
+
+    fn check_batch(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<()>;
+
+    // Fewest samples a training batch may hold, 2 with batch normalization.
+    fn min_batch_size(&self) -> usize;
+
+    // Rate the next training step will use.
+    fn current_learning_rate(&self) -> f32;
+
+    // One optimizer step on the batch-averaged gradients; returns the loss.
+    fn train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> f32;
+
+    fn evaluate(&mut self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> f32;
+
+    // Advances per-epoch schedules and feeds them the monitored loss.
+    fn end_epoch(&mut self, metric: f32);
+
+    // Copies of every trainable tensor followed by every state tensor, such
+    // as running statistics: enough to roll the model back with `restore`.
+    fn snapshot(&self) -> Vec<Matrix>;
+
+    // Puts back tensors taken by `snapshot`. Optimizer state is left
+    // untouched.
+    fn restore(&mut self, snapshot: Vec<Matrix>) -> Result<()>;
+
+    fn save(&self, path: &Path) -> Result<()>;
+
+    fn save_json(&self, path: &Path) -> Result<()>;
+}
+
+// Most chunks a training batch is split into. The split depends on the
+// batch size alone, so chunk seeds and the order partial results are summed
+// in are the same for any thread count and seeded runs stay bit-identical.
+const MAX_CHUNKS: usize = 16;
+
+pub(crate) fn chunk_size(batch_size: usize) -> usize {
+    batch_size.div_ceil(MAX_CHUNKS).max(1)
+}
+
+// Chunks each parallel task works through, so that no more than
+// `num_threads` tasks run at once.
+pub(crate) fn chunks_per_task(batch_size: usize, num_threads: usize) -> usize {
+    batch_size
+        .div_ceil(chunk_size(batch_size))
+        .div_ceil(num_threads.max(1))
+        .max(1)
+}
+
+// Batch normalization has no statistics for a single sample, so models
+// using it reject training batches of one instead of silently training on
+// the running estimates.
+pub(crate) fn check_batch_size(batch_size: usize, minimum: usize) -> Result<()> {
+    if batch_size < minimum {
+        return Err(RustingBrainError::BatchTooSmall {
+            found: batch_size,
+            minimum,
+        });
+    }
+    Ok(())
+}
+
+// Checks that `values` line up one to one with `expected`.
+pub(crate) fn check_shapes(expected: &[&Matrix], values: &[Matrix]) -> Result<()> {
+    if expected.len() != values.len() {
+        return Err(RustingBrainError::InvalidArchitecture(format!(
+            "expected {} matrices, found {}",
+            expected.len(),
+            values.len()
+        )));
+    }
+    for (old, new) in expected.iter().zip(values) {
+        if (old.rows, old.cols) != (new.rows, new.cols) {
+            return Err(RustingBrainError::ShapeMismatch {
+                expected: (old.rows, old.cols),
+                found: (new.rows, new.cols),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Moves `values` into `targets` after checking they line up.
+pub(crate) fn assign(targets: Vec<&mut Matrix>, values: Vec<Matrix>) -> Result<()> {
+    let expected: Vec<&Matrix> = targets.iter().map(|m| &**m).collect();
+    check_shapes(&expected, &values)?;
+    for (target, value) in targets.into_iter().zip(values) {
+        *target = value;
+    }
+    Ok(())
+}
+
+// One parameter's gradient on its way to the optimizer.
+pub(crate) enum Gradient {
+    Dense(Matrix),
+    Rows(RowGradient),
+}
+
+impl Gradient {
+    // The gradient's own numbers, which is all clipping needs to see: a
+    // row-sparse gradient's rows are distinct, so they have the norm of
+    // its dense equivalent.
+    fn values_mut(&mut self) -> &mut Matrix {
+        match self {
+            Gradient::Dense(m) => m,
+            Gradient::Rows(r) => &mut r.values,
+        }
+    }
+}
+
+// Everything about taking an optimizer step that does not depend on how a
+// model lays out its parameters: the rate and its schedule, the optimizer
+// and gradient clipping.
+pub(crate) struct TrainingState {
+    pub learning_rate: f32,
+    pub optimizer: Box<dyn Optimizer>,
+    pub scheduler: Option<Box<dyn LrScheduler>>,
+    pub steps_taken: usize,
+    pub epochs_taken: usize,
+    pub gradient_clipping: Option<GradientClipping>,
+    pub last_gradient_norm: f32,
+}
+
+impl TrainingState {
+    pub fn new(learning_rate: f32) -> Self {
+        TrainingState {
+            learning_rate,
+            optimizer: Box::new(Sgd::new()),
+            scheduler: None,
+            steps_taken: 0,
+            epochs_taken: 0,
+            gradient_clipping: None,
+            last_gradient_norm: 0.0,
+        }
+    }
+
+    // The scheduler's clock starts from zero whenever one is attached.
+    pub fn set_scheduler(&mut self, scheduler: Box<dyn LrScheduler>) {
+        self.scheduler = Some(scheduler);
+        self.steps_taken = 0;
+        self.epochs_taken = 0;
+    }
+
+    pub fn current_learning_rate(&self) -> f32 {
+        match &self.scheduler {
+            Some(scheduler) => {
+                let t = match scheduler.interval() {
+                    Interval::Step => self.steps_taken,
+                    Interval::Epoch => self.epochs_taken,
+                };
+                scheduler.rate(self.learning_rate, t)
+            }
+            None => self.learning_rate,
+        }
+    }
+
+    pub fn end_epoch(&mut self, metric: f32) {
+        self.epochs_taken += 1;
+        if let Some(scheduler) = &mut self.scheduler {
+            scheduler.observe(metric);
+        }
+    }
+
+    // Clips `grads` and hands each to the optimizer with the parameter at the
+    // same position, which is also its optimizer slot. Returns the global
+    // norm of `grads` before clipping.
+    pub fn step<'a>(
+        &mut self,
+        params: impl IntoIterator<Item = &'a mut Matrix>,
+        grads: Vec<Matrix>,
+    ) -> f32 {
+        let mut grads: Vec<Gradient> = grads.into_iter().map(Gradient::Dense).collect();
+        self.step_slots(params.into_iter().enumerate(), &mut grads)
+    }
+
+    // Same as `step` for only some of the parameters, each paired with its
+    // optimizer slot; the slots left out keep their parameters and state.
+    pub fn step_slots<'a>(
+        &mut self,
+        params: impl IntoIterator<Item = (usize, &'a mut Matrix)>,
+        grads: &mut [Gradient],
+    ) -> f32 {
+        let lr = self.current_learning_rate();
+        let mut values: Vec<&mut Matrix> = grads.iter_mut().map(|g| g.values_mut()).collect();
+        let norm = match self.gradient_clipping {
+            Some(clipping) => clipping.clip(&mut values),
+            None => clipping::global_norm(values.iter().map(|m| &**m)),
+        };
+
+        self.optimizer.begin_step();
+        for ((slot, param), grad) in params.into_iter().zip(grads.iter()) {
+            match grad {
+                Gradient::Dense(grad) => self.optimizer.update(slot, param, grad, lr),
+                Gradient::Rows(grad) => self.optimizer.update_rows(slot, param, grad, lr),
+            }
+        }
+
+        self.steps_taken += 1;
+        self.last_gradient_norm = norm;
+        norm
+    }
+}
@@ -1,9 +1,31 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::activation::Activation;
+use crate::clipping::{self, GradientClipping};
+use crate::dropout::Dropout;
+use crate::initializer::Initializer;
+use crate::json::JsonValue;
+use crate::loss::Loss;
 use crate::matrix::Matrix;
+use crate::normalization::{NormStats, Normalization};
+use crate::model::{self, Model, TrainingState};
+use crate::optimizer::Optimizer;
+use crate::error::{Result, RustingBrainError};
+use crate::persistence;
+use crate::regularizer::Regularizer;
+use crate::scheduler::LrScheduler;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
 use rayon::prelude::*;
 
+#[derive(Clone, Debug)]
 pub struct Gradients {
     pub d_weights: Vec<Matrix>,
     pub d_biases: Vec<Matrix>,
+    // Gamma then beta of every normalized layer, in layer order.
+    pub d_norm: Vec<Matrix>,
 }
 
 impl Gradients {
@@ -18,25 +40,48 @@ impl Gradients {
             d_biases.push(Matrix::new(rows, 1));
         }
 
-        Gradients { d_weights, d_biases }
+        Gradients {
+            d_weights,
+            d_biases,
+            d_norm: Vec::new(),
+        }
     }
 
-    pub fn zero(&mut self) {
-        for m in &mut self.d_weights {
-            m.zeros();
+    pub(crate) fn with_normalization(mut self, normalization: &[Option<Normalization>]) -> Self {
+        for norm in normalization.iter().flatten() {
+            self.d_norm.push(Matrix::new(norm.features(), 1));
+            self.d_norm.push(Matrix::new(norm.features(), 1));
         }
-        for m in &mut self.d_biases {
+        self
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Matrix> {
+        self.d_weights.iter().chain(&self.d_biases).chain(&self.d_norm)
+    }
+
+    pub(crate) fn all_mut(&mut self) -> impl Iterator<Item = &mut Matrix> {
+        self.d_weights
+            .iter_mut()
+            .chain(&mut self.d_biases)
+            .chain(&mut self.d_norm)
+    }
+
+    // Every gradient in optimizer slot order.
+    fn into_vec(self) -> Vec<Matrix> {
+        let mut all = self.d_weights;
+        all.extend(self.d_biases);
+        all.extend(self.d_norm);
+        all
+    }
+
+    pub fn zero(&mut self) {
+        for m in self.all_mut() {
             m.zeros();
         }
     }
 
     pub fn add(&mut self, other: &Gradients) {
-        for (a, b) in self.d_weights.iter_mut().zip(&other.d_weights) {
-            for (x, y) in a.data.iter_mut().zip(&b.data) {
-                *x += y;
-            }
-        }
-        for (a, b) in self.d_biases.iter_mut().zip(&other.d_biases) {
+        for (a, b) in self.all_mut().zip(other.all()) {
             for (x, y) in a.data.iter_mut().zip(&b.data) {
                 *x += y;
             }
@@ -44,24 +89,62 @@ impl Gradients {
     }
 
     pub fn scale(&mut self, factor: f32) {
-        for m in &mut self.d_weights {
-            for x in &mut m.data {
-                *x *= factor;
-            }
-        }
-        for m in &mut self.d_biases {
+        for m in self.all_mut() {
             for x in &mut m.data {
                 *x *= factor;
             }
         }
     }
+
+    // L2 norm over every gradient taken together.
+    pub fn global_norm(&self) -> f32 {
+        clipping::global_norm(self.all())
+    }
+
+    pub fn clip_by_value(&mut self, limit: f32) {
+        clipping::clip_by_value(&mut self.all_mut().collect::<Vec<_>>(), limit);
+    }
+
+    // Rescales each gradient tensor on its own so that none has an L2 norm
+    // above `max_norm`.
+    pub fn clip_by_norm(&mut self, max_norm: f32) {
+        clipping::clip_by_norm(&mut self.all_mut().collect::<Vec<_>>(), max_norm);
+    }
+
+    // Rescales all gradients by the same factor so their global norm is at
+    // most `max_norm`, keeping the update direction. Returns the norm from
+    // before clipping.
+    pub fn clip_by_global_norm(&mut self, max_norm: f32) -> f32 {
+        clipping::clip_by_global_norm(&mut self.all_mut().collect::<Vec<_>>(), max_norm)
+    }
+}
+
+// Read-only view of a network shared by the parallel gradient workers.
+#[derive(Clone, Copy)]
+struct BatchModel<'a> {
+    layers: &'a [usize],
+    layer_activations: &'a [Activation],
+    loss: Loss,
+    weights: &'a [Matrix],
+    biases: &'a [Matrix],
+    dropout: &'a [Option<Dropout>],
+    normalization: &'a [Option<Normalization>],
 }
 
 pub struct Network {
     layers: Vec<usize>,
+    layer_activations: Vec<Activation>,
+    loss: Loss,
     weights: Vec<Matrix>,
     biases: Vec<Matrix>,
-    learning_rate: f32,
+    training: TrainingState,
+    regularizers: Vec<Option<Regularizer>>,
+    dropout: Vec<Option<Dropout>>,
+    normalization: Vec<Option<Normalization>>,
+    // `None` picks each layer's weights by its activation.
+    weight_init: Option<Initializer>,
+    bias_init: Initializer,
+    rng: StdRng,
     
     activations: Vec<Matrix>,
     weighted_sums: Vec<Matrix>,
@@ -71,6 +154,32 @@ pub struct Network {
 
 impl Network {
     pub fn new(layers: Vec<usize>, learning_rate: f32) -> Self {
+        Self::try_new(layers, learning_rate).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(layers: Vec<usize>, learning_rate: f32) -> Result<Self> {
+        let num_layers = layers.len().saturating_sub(1).max(1);
+        let mut layer_activations = vec![Activation::Relu; num_layers];
+        layer_activations[num_layers - 1] = Activation::Identity;
+        Self::try_with_activations(layers, layer_activations, learning_rate)
+    }
+
+    pub fn with_activations(
+        layers: Vec<usize>,
+        layer_activations: Vec<Activation>,
+        learning_rate: f32,
+    ) -> Self {
+        Self::try_with_activations(layers, layer_activations, learning_rate)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_with_activations(
+        layers: Vec<usize>,
+        layer_activations: Vec<Activation>,
+        learning_rate: f32,
+    ) -> Result<Self> {
+        Self::validate_architecture(&layers, &layer_activations)?;
+
         let mut weights = vec![];
         let mut biases = vec![];
         
@@ -87,8 +196,8 @@ impl Network {
             let rows = layers[i + 1];
             let cols = layers[i];
             
-            weights.push(Matrix::random(rows, cols));
-            biases.push(Matrix::random(rows, 1));
+            weights.push(Matrix::new(rows, cols));
+            biases.push(Matrix::new(rows, 1));
             
             activations.push(Matrix::new(rows, 1));
             weighted_sums.push(Matrix::new(rows, 1));
@@ -96,26 +205,601 @@ impl Network {
             gradients.push(Matrix::new(rows, cols));
         }
 
-        Network {
+        let regularizers = vec![None; layers.len() - 1];
+        let dropout = vec![None; layers.len() - 1];
+        let normalization = vec![None; layers.len() - 1];
+
+        let mut net = Network {
             layers,
+            layer_activations,
+            loss: Loss::MeanSquaredError,
             weights,
             biases,
-            learning_rate,
+            training: TrainingState::new(learning_rate),
+            regularizers,
+            dropout,
+            normalization,
+            weight_init: None,
+            bias_init: Initializer::Zeros,
+            rng: StdRng::from_entropy(),
             activations,
             weighted_sums,
             errors,
             gradients,
+        };
+        net.initialize_parameters();
+        Ok(net)
+    }
+
+    fn validate_architecture(layers: &[usize], layer_activations: &[Activation]) -> Result<()> {
+        let invalid = |msg: String| Err(RustingBrainError::InvalidArchitecture(msg));
+
+        if layers.len() < 2 {
+            return invalid(format!("expected at least 2 layers, found {}", layers.len()));
+        }
+        if layers.contains(&0) {
+            return invalid("layer with zero neurons".to_string());
+        }
+        if layer_activations.len() != layers.len() - 1 {
+            return invalid(format!(
+                "expected {} activations (one per non-input layer), found {}",
+                layers.len() - 1,
+                layer_activations.len()
+            ));
+        }
+        if layer_activations[..layer_activations.len() - 1].contains(&Activation::Softmax) {
+            return invalid("softmax is only supported on the output layer".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn with_loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    pub fn new_with_seed(layers: Vec<usize>, learning_rate: f32, seed: u64) -> Self {
+        Self::new(layers, learning_rate).with_seed(seed)
+    }
+
+    // Reseeds the network's random source and redraws every weight and bias
+    // from it, so the same seed always yields the same starting parameters.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.initialize_parameters();
+        self
+    }
+
+    // Replaces the default of He weights for ReLU layers, Xavier weights for
+    // the rest and zero biases.
+    pub fn with_initializer(mut self, weight_init: Initializer, bias_init: Initializer) -> Self {
+        self.weight_init = Some(weight_init);
+        self.bias_init = bias_init;
+        self.initialize_parameters();
+        self
+    }
+
+    fn initialize_parameters(&mut self) {
+        let layers = self.weights.iter_mut().zip(&mut self.biases);
+        for ((weight, bias), &activation) in layers.zip(&self.layer_activations) {
+            let init = self.weight_init.unwrap_or(Initializer::for_activation(activation));
+            *weight = init.initialize(weight.rows, weight.cols, &mut self.rng);
+            *bias = self.bias_init.initialize(bias.rows, bias.cols, &mut self.rng);
+        }
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    pub fn with_optimizer<O: Optimizer + 'static>(mut self, optimizer: O) -> Self {
+        self.training.optimizer = Box::new(optimizer);
+        self
+    }
+
+    // The scheduler's clock starts from zero whenever one is attached.
+    pub fn with_scheduler<S: LrScheduler + 'static>(mut self, scheduler: S) -> Self {
+        self.training.set_scheduler(Box::new(scheduler));
+        self
+    }
+
+    pub fn with_gradient_clipping(mut self, clipping: GradientClipping) -> Self {
+        self.training.gradient_clipping = Some(clipping);
+        self
+    }
+
+    // Applies the same penalty to every layer.
+    pub fn with_regularizer(mut self, regularizer: Regularizer) -> Self {
+        for slot in &mut self.regularizers {
+            *slot = Some(regularizer);
+        }
+        self
+    }
+
+    // Drops outputs of a hidden layer while training; `layer` indexes
+    // `layers()`, so valid values run from 1 to `layers().len() - 2`.
+    // `forward` and `evaluate` never apply dropout.
+    pub fn with_dropout(self, layer: usize, dropout: Dropout) -> Self {
+        self.try_with_dropout(layer, dropout)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_with_dropout(mut self, layer: usize, dropout: Dropout) -> Result<Self> {
+        self.check_hidden_layer("dropout", layer)?;
+        if !(0.0..1.0).contains(&dropout.rate()) {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "dropout rate must be in [0, 1), got {}",
+                dropout.rate()
+            )));
+        }
+        self.dropout[layer - 1] = Some(dropout);
+        Ok(self)
+    }
+
+    // Normalizes the weighted sums of a hidden layer before its activation;
+    // `layer` indexes `layers()` like `with_dropout`.
+    pub fn with_normalization(self, layer: usize, normalization: Normalization) -> Self {
+        self.try_with_normalization(layer, normalization)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_with_normalization(
+        mut self,
+        layer: usize,
+        normalization: Normalization,
+    ) -> Result<Self> {
+        self.check_hidden_layer("normalization", layer)?;
+        if normalization.features() != self.layers[layer] {
+            return Err(RustingBrainError::ShapeMismatch {
+                expected: (self.layers[layer], 1),
+                found: (normalization.features(), 1),
+            });
+        }
+        self.normalization[layer - 1] = Some(normalization);
+        Ok(self)
+    }
+
+    fn check_hidden_layer(&self, what: &str, layer: usize) -> Result<()> {
+        if layer >= 1 && layer + 1 < self.layers.len() {
+            return Ok(());
+        }
+        Err(RustingBrainError::InvalidArchitecture(format!(
+            "{} can only be applied to hidden layers, got layer {}",
+            what, layer
+        )))
+    }
+
+    pub fn with_batch_norm(self, layer: usize) -> Self {
+        self.try_with_batch_norm(layer)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_with_batch_norm(self, layer: usize) -> Result<Self> {
+        self.check_hidden_layer("normalization", layer)?;
+        let features = self.layers[layer];
+        self.try_with_normalization(layer, Normalization::batch(features))
+    }
+
+    pub fn with_layer_norm(self, layer: usize) -> Self {
+        self.try_with_layer_norm(layer)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_with_layer_norm(self, layer: usize) -> Result<Self> {
+        self.check_hidden_layer("normalization", layer)?;
+        let features = self.layers[layer];
+        self.try_with_normalization(layer, Normalization::layer(features))
+    }
+
+    // Normalization of every non-input layer, indexed like `weights()`.
+    pub fn normalization(&self) -> &[Option<Normalization>] {
+        &self.normalization
+    }
+
+    pub fn set_normalization(&mut self, normalization: Vec<Option<Normalization>>) -> Result<()> {
+        if normalization.len() != self.normalization.len() {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "expected {} normalization entries, found {}",
+                self.normalization.len(),
+                normalization.len()
+            )));
+        }
+        for (l, norm) in normalization.iter().enumerate() {
+            if let Some(norm) = norm
+                && norm.features() != self.layers[l + 1]
+            {
+                return Err(RustingBrainError::ShapeMismatch {
+                    expected: (self.layers[l + 1], 1),
+                    found: (norm.features(), 1),
+                });
+            }
+        }
+        self.normalization = normalization;
+        Ok(())
+    }
+
+    // Zeroed gradients shaped for this network, including the gamma/beta
+    // gradients of normalized layers.
+    pub fn new_gradients(&self) -> Gradients {
+        Gradients::new(&self.layers).with_normalization(&self.normalization)
+    }
+
+    // Penalises a single layer; `layer` indexes `weights()`, so 0 is the
+    // first weight matrix and the input layer, which has none, cannot be
+    // named.
+    pub fn with_layer_regularizer(self, layer: usize, regularizer: Regularizer) -> Self {
+        self.try_with_layer_regularizer(layer, regularizer)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_with_layer_regularizer(
+        mut self,
+        layer: usize,
+        regularizer: Regularizer,
+    ) -> Result<Self> {
+        if layer >= self.weights.len() {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "regularizer layer {} out of range, the network has {} weight matrices",
+                layer,
+                self.weights.len()
+            )));
+        }
+        self.regularizers[layer] = Some(regularizer);
+        Ok(self)
+    }
+
+    // Total regularization penalty of the current parameters. It is already
+    // included in the losses returned by `evaluate` and the training methods.
+    pub fn regularization_loss(&self) -> f32 {
+        let mut penalty = 0.0;
+        for (i, regularizer) in self.regularizers.iter().enumerate() {
+            if let Some(reg) = regularizer {
+                penalty += reg.penalty(&self.weights[i]);
+                if reg.include_biases {
+                    penalty += reg.penalty(&self.biases[i]);
+                }
+            }
+        }
+        penalty
+    }
+
+    // Adds the penalty gradients to batch-averaged `grads`.
+    fn add_regularization_gradients(&self, grads: &mut Gradients) {
+        for (i, regularizer) in self.regularizers.iter().enumerate() {
+            if let Some(reg) = regularizer {
+                reg.add_gradient(&self.weights[i], &mut grads.d_weights[i]);
+                if reg.include_biases {
+                    reg.add_gradient(&self.biases[i], &mut grads.d_biases[i]);
+                }
+            }
+        }
+    }
+
+    // Global norm of the last applied gradients, measured before clipping.
+    pub fn gradient_norm(&self) -> f32 {
+        self.training.last_gradient_norm
+    }
+
+    pub fn layer_activations(&self) -> &[Activation] {
+        &self.layer_activations
+    }
+
+    pub fn loss(&self) -> Loss {
+        self.loss
+    }
+
+    pub fn layers(&self) -> &[usize] {
+        &self.layers
+    }
+
+    // Base rate, before any scheduler is applied.
+    pub fn learning_rate(&self) -> f32 {
+        self.training.learning_rate
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.training.learning_rate = learning_rate;
+    }
+
+    // Rate the next call to `apply_gradients` will use.
+    pub fn current_learning_rate(&self) -> f32 {
+        self.training.current_learning_rate()
+    }
+
+    // Advances per-epoch schedules and feeds them the monitored loss. Called
+    // by `Trainer` after every epoch; call it yourself in hand-written loops.
+    pub fn end_epoch(&mut self, metric: f32) {
+        self.training.end_epoch(metric);
+    }
+
+    pub fn weights(&self) -> &[Matrix] {
+        &self.weights
+    }
+
+    pub fn biases(&self) -> &[Matrix] {
+        &self.biases
+    }
+
+    // Replaces every weight and bias, e.g. to roll back to a snapshot taken
+    // from `weights()`/`biases()`. Optimizer state is left untouched.
+    pub fn set_parameters(&mut self, weights: Vec<Matrix>, biases: Vec<Matrix>) -> Result<()> {
+        if weights.len() != self.weights.len() || biases.len() != self.biases.len() {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "expected {} weight and bias matrices, found {} and {}",
+                self.weights.len(),
+                weights.len(),
+                biases.len()
+            )));
+        }
+
+        let current = self.weights.iter().chain(&self.biases);
+        for (old, new) in current.zip(weights.iter().chain(&biases)) {
+            if (old.rows, old.cols) != (new.rows, new.cols) {
+                return Err(RustingBrainError::ShapeMismatch {
+                    expected: (old.rows, old.cols),
+                    found: (new.rows, new.cols),
+                });
+            }
         }
+
+        self.weights = weights;
+        self.biases = biases;
+        Ok(())
     }
 
-    #[inline(always)]
-    fn relu(x: f32) -> f32 {
-        if x > 0.0 { x } else { 0.0 }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
     }
 
-    #[inline(always)]
-    fn relu_derivative(x: f32) -> f32 {
-        if x > 0.0 { 1.0 } else { 0.0 }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read_from(&mut reader)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&persistence::MAGIC)?;
+        persistence::write_u32(w, persistence::VERSION)?;
+
+        persistence::write_u32(w, self.layers.len() as u32)?;
+        for &size in &self.layers {
+            persistence::write_u32(w, size as u32)?;
+        }
+        for &activation in &self.layer_activations {
+            persistence::write_activation(w, activation)?;
+        }
+        persistence::write_loss(w, self.loss)?;
+        persistence::write_f32(w, self.training.learning_rate)?;
+
+        for (weight, bias) in self.weights.iter().zip(&self.biases) {
+            persistence::write_matrix(w, weight)?;
+            persistence::write_matrix(w, bias)?;
+        }
+        for norm in &self.normalization {
+            persistence::write_normalization(w, norm.as_ref())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let version = persistence::read_header(r)?;
+
+        let num_layers = persistence::read_u32(r)? as usize;
+        if num_layers < 2 {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "expected at least 2 layers, found {}",
+                num_layers
+            )));
+        }
+
+        // `num_layers` is untrusted, so the vectors grow with what is read.
+        let mut layers = Vec::new();
+        for _ in 0..num_layers {
+            let size = persistence::read_u32(r)? as usize;
+            if size == 0 {
+                return Err(RustingBrainError::InvalidArchitecture(
+                    "layer with zero neurons".to_string(),
+                ));
+            }
+            layers.push(size);
+        }
+
+        let mut layer_activations = Vec::new();
+        for _ in 0..num_layers - 1 {
+            layer_activations.push(persistence::read_activation(r)?);
+        }
+        Self::validate_architecture(&layers, &layer_activations)?;
+
+        let loss = persistence::read_loss(r)?;
+        let learning_rate = persistence::read_f32(r)?;
+
+        let mut weights = Vec::with_capacity(num_layers - 1);
+        let mut biases = Vec::with_capacity(num_layers - 1);
+        for i in 0..num_layers - 1 {
+            weights.push(persistence::read_matrix(r, layers[i + 1], layers[i])?);
+            biases.push(persistence::read_matrix(r, layers[i + 1], 1)?);
+        }
+
+        let mut normalization = vec![None; num_layers - 1];
+        if version >= 2 {
+            for (i, norm) in normalization.iter_mut().enumerate() {
+                *norm = persistence::read_normalization(r, layers[i + 1])?;
+            }
+        }
+
+        let mut net =
+            Self::try_with_activations(layers, layer_activations, learning_rate)?.with_loss(loss);
+        net.weights = weights;
+        net.biases = biases;
+        net.normalization = normalization;
+        Ok(net)
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_json())?;
+        Ok(())
+    }
+
+    pub fn load_json<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        let field = |key: &str, value: JsonValue| (key.to_string(), value);
+
+        JsonValue::Object(vec![
+            field("format", JsonValue::String(persistence::JSON_FORMAT.to_string())),
+            field("version", JsonValue::from_usize(persistence::VERSION as usize)),
+            field(
+                "layers",
+                JsonValue::Array(self.layers.iter().map(|&n| JsonValue::from_usize(n)).collect()),
+            ),
+            field(
+                "activations",
+                JsonValue::Array(
+                    self.layer_activations
+                        .iter()
+                        .map(|&a| persistence::activation_to_json(a))
+                        .collect(),
+                ),
+            ),
+            field("loss", persistence::loss_to_json(self.loss)),
+            field("learning_rate", JsonValue::from_f32(self.training.learning_rate)),
+            field(
+                "weights",
+                JsonValue::Array(self.weights.iter().map(persistence::matrix_to_json).collect()),
+            ),
+            field(
+                "biases",
+                JsonValue::Array(self.biases.iter().map(persistence::matrix_to_json).collect()),
+            ),
+            field(
+                "normalization",
+                JsonValue::Array(
+                    self.normalization
+                        .iter()
+                        .map(|n| persistence::normalization_to_json(n.as_ref()))
+                        .collect(),
+                ),
+            ),
+        ])
+        .to_pretty_string()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let root = JsonValue::parse(text).map_err(RustingBrainError::InvalidJson)?;
+
+        if persistence::json_field(&root, "format")?.as_str() != Some(persistence::JSON_FORMAT) {
+            return Err(RustingBrainError::BadMagic);
+        }
+        let version = persistence::json_usize(&root, "version")? as u32;
+        if version == 0 || version > persistence::VERSION {
+            return Err(RustingBrainError::UnsupportedVersion(version));
+        }
+
+        let layers = persistence::json_array(&root, "layers")?
+            .iter()
+            .map(|v| v.as_usize().filter(|&n| n > 0))
+            .collect::<Option<Vec<usize>>>()
+            .ok_or_else(|| {
+                RustingBrainError::InvalidArchitecture(
+                    "layer sizes must be positive integers".to_string(),
+                )
+            })?;
+        let num_layers = layers.len();
+
+        let layer_activations = persistence::json_array(&root, "activations")?
+            .iter()
+            .map(persistence::activation_from_json)
+            .collect::<Result<Vec<_>>>()?;
+        Self::validate_architecture(&layers, &layer_activations)?;
+
+        let loss = persistence::loss_from_json(persistence::json_field(&root, "loss")?)?;
+        let learning_rate = persistence::json_f32(&root, "learning_rate")?;
+
+        let weight_values = persistence::json_array(&root, "weights")?;
+        let bias_values = persistence::json_array(&root, "biases")?;
+        if weight_values.len() != num_layers - 1 || bias_values.len() != num_layers - 1 {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "expected {} weight and bias matrices",
+                num_layers - 1
+            )));
+        }
+
+        let mut weights = Vec::with_capacity(num_layers - 1);
+        let mut biases = Vec::with_capacity(num_layers - 1);
+        for i in 0..num_layers - 1 {
+            weights.push(persistence::matrix_from_json(
+                &weight_values[i],
+                layers[i + 1],
+                layers[i],
+            )?);
+            biases.push(persistence::matrix_from_json(&bias_values[i], layers[i + 1], 1)?);
+        }
+
+        let mut normalization = vec![None; num_layers - 1];
+        if root.get("normalization").is_some() {
+            let values = persistence::json_array(&root, "normalization")?;
+            if values.len() != num_layers - 1 {
+                return Err(RustingBrainError::InvalidArchitecture(format!(
+                    "expected {} normalization entries",
+                    num_layers - 1
+                )));
+            }
+            for (i, value) in values.iter().enumerate() {
+                normalization[i] = persistence::normalization_from_json(value, layers[i + 1])?;
+            }
+        }
+
+        let mut net =
+            Self::try_with_activations(layers, layer_activations, learning_rate)?.with_loss(loss);
+        net.weights = weights;
+        net.biases = biases;
+        net.normalization = normalization;
+        Ok(net)
+    }
+
+    fn check_vector(values: &[f32], expected: usize) -> Result<()> {
+        if values.len() != expected {
+            return Err(RustingBrainError::ShapeMismatch {
+                expected: (expected, 1),
+                found: (values.len(), 1),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_batch(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<()> {
+        if inputs.is_empty() {
+            return Err(RustingBrainError::EmptyBatch);
+        }
+        if inputs.len() != targets.len() {
+            return Err(RustingBrainError::BatchSizeMismatch {
+                inputs: inputs.len(),
+                targets: targets.len(),
+            });
+        }
+
+        let output_dim = *self.layers.last().unwrap();
+        for (input, target) in inputs.iter().zip(targets) {
+            Self::check_vector(input, self.layers[0])?;
+            Self::check_vector(target, output_dim)?;
+        }
+        Ok(())
+    }
+
+    // Fewest samples a training batch may hold: 2 with batch normalization,
+    // which has no statistics for a single sample.
+    pub fn min_batch_size(&self) -> usize {
+        let batch_norm = self.normalization.iter().flatten().any(|n| n.is_batch());
+        if batch_norm { 2 } else { 1 }
+    }
+
+    pub fn try_forward(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+        Self::check_vector(input, self.layers[0])?;
+        Ok(self.forward(input))
     }
 
     pub fn forward(&mut self, input: &[f32]) -> Vec<f32> {
@@ -130,55 +814,126 @@ impl Network {
             
             self.weights[i].dot(prev_a, current_z);
             
-            let current_a = &mut self.activations[i+1];
-            let is_last = i == self.weights.len() - 1;
+            for (z, b) in current_z.data.iter_mut().zip(&self.biases[i].data) {
+                *z += b;
+            }
 
-            for j in 0..current_z.data.len() {
-                let z = current_z.data[j] + self.biases[i].data[j];
-                current_z.data[j] = z;
-                current_a.data[j] = if is_last { z } else { Self::relu(z) };
+            if let Some(norm) = &self.normalization[i] {
+                let stats = norm.inference_stats(current_z);
+                norm.normalize(current_z, &stats);
             }
+
+            let current_a = &mut self.activations[i + 1];
+            self.layer_activations[i].apply(current_z, current_a);
         }
 
         self.activations.last().unwrap().data.clone()
     }
 
+    pub fn evaluate(&mut self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> f32 {
+        self.try_evaluate(inputs, targets).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Mean loss over the samples plus the regularization penalty; an empty
+    // set scores 0.
+    pub fn try_evaluate(&mut self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<f32> {
+        if inputs.is_empty() && targets.is_empty() {
+            return Ok(0.0);
+        }
+        self.check_batch(inputs, targets)?;
+
+        let output_activation = *self.layer_activations.last().unwrap();
+        let mut sum = 0.0;
+        for (input, target) in inputs.iter().zip(targets) {
+            self.forward(input);
+            let output = &self.activations.last().unwrap().data;
+            let logits = &self.weighted_sums.last().unwrap().data;
+            sum += self
+                .loss
+                .value_with_logits(output_activation, output, logits, target);
+        }
+        Ok(sum / inputs.len() as f32 + self.regularization_loss())
+    }
+
+    // Adds one sample's gradients to `grads`. Panics on networks with batch
+    // normalization, which only trains on whole batches.
     pub fn compute_gradients_single(
         &mut self,
         input: &[f32],
         target: &[f32],
         grads: &mut Gradients,
-    ) {
+    ) -> f32 {
+        model::check_batch_size(1, self.min_batch_size()).unwrap_or_else(|e| panic!("{}", e));
         self.activations[0].copy_from_slice(input);
 
+        if grads.d_norm.is_empty() {
+            grads.d_norm = self.new_gradients().d_norm;
+        }
+
+        // Dropout masks by layer index; empty where the layer has none.
+        let mut masks: Vec<Vec<f32>> = vec![Vec::new(); self.layers.len()];
+        let mut norm_cache: Vec<Option<(Matrix, NormStats)>> = vec![None; self.layers.len()];
+
         for i in 0..self.weights.len() {
             let prev_a = &self.activations[i];
             let current_z = &mut self.weighted_sums[i + 1];
 
             self.weights[i].dot(prev_a, current_z);
 
-            let current_a = &mut self.activations[i + 1];
-            let bias = &self.biases[i];
+            for (z, b) in current_z.data.iter_mut().zip(&self.biases[i].data) {
+                *z += b;
+            }
+
+            // Only layer normalization gets here; it normalizes each sample
+            // over its own features.
+            if let Some(norm) = &self.normalization[i] {
+                let stats = norm.inference_stats(current_z);
+                let x_hat = norm.normalize(current_z, &stats);
+                norm_cache[i + 1] = Some((x_hat, stats));
+            }
 
-            let is_last = i == self.weights.len() - 1;
+            let current_a = &mut self.activations[i + 1];
+            self.layer_activations[i].apply(current_z, current_a);
 
-            for j in 0..current_z.data.len() {
-                let z = current_z.data[j] + bias.data[j];
-                current_z.data[j] = z;
-                current_a.data[j] = if is_last { z } else { Self::relu(z) };
+            if let Some(dropout) = self.dropout[i] {
+                let mask = &mut masks[i + 1];
+                mask.resize(current_a.data.len(), 0.0);
+                dropout.apply(&mut current_a.data, mask, &mut self.rng);
             }
         }
 
         let last_idx = self.layers.len() - 1;
         let output_a = &self.activations[last_idx];
+        let output_z = &self.weighted_sums[last_idx];
+        let output_activation = self.layer_activations[last_idx - 1];
         let error = &mut self.errors[last_idx];
 
-        for j in 0..error.data.len() {
-            let e = target[j] - output_a.data[j];
-            error.data[j] = e;
-        }
+        let loss_value =
+            self.loss
+                .value_with_logits(output_activation, &output_a.data, &output_z.data, target);
+        self.loss.output_error(
+            output_activation,
+            &output_a.data,
+            &output_z.data,
+            target,
+            1,
+            &mut error.data,
+        );
 
         for i in (0..self.weights.len()).rev() {
+            if let (Some(norm), Some((x_hat, stats))) = (&self.normalization[i], &norm_cache[i + 1]) {
+                let error = &mut self.errors[i + 1];
+                let (d_gamma, d_beta) = Normalization::parameter_gradients(error, x_hat);
+                let slot = norm_slot(&self.normalization, i);
+                for (g, d) in grads.d_norm[slot].data.iter_mut().zip(&d_gamma.data) {
+                    *g += d;
+                }
+                for (g, d) in grads.d_norm[slot + 1].data.iter_mut().zip(&d_beta.data) {
+                    *g += d;
+                }
+                norm.backward(error, x_hat, stats, None);
+            }
+
             let curr_error = &self.errors[i + 1];
             let prev_activation = &self.activations[i];
             let gradient = &mut self.gradients[i];
@@ -195,8 +950,13 @@ impl Network {
 
                 weight.dot_transpose_self(source_curr_error, target_prev_error);
 
+                for (e, m) in target_prev_error.data.iter_mut().zip(&masks[i]) {
+                    *e *= m;
+                }
+
+                let activation = self.layer_activations[i - 1];
                 for j in 0..target_prev_error.data.len() {
-                    target_prev_error.data[j] *= Self::relu_derivative(prev_z.data[j]);
+                    target_prev_error.data[j] *= activation.derivative(prev_z.data[j]);
                 }
             }
 
@@ -213,41 +973,316 @@ impl Network {
                 grad_b.data[j] += err_vector.data[j];
             }
         }
+
+        loss_value
     }
 
-    pub fn apply_gradients(&mut self, grads: &Gradients, scale: f32) {
-        let lr = self.learning_rate * scale;
+    // Returns the global norm of `grads * scale` before any clipping.
+    // Normalization parameters take the optimizer slots after the biases.
+    pub fn apply_gradients(&mut self, grads: &Gradients, scale: f32) -> f32 {
+        let mut grads = grads.clone();
+        if scale != 1.0 {
+            grads.scale(scale);
+        }
+        let params = self
+            .weights
+            .iter_mut()
+            .chain(&mut self.biases)
+            .chain(
+                self.normalization
+                    .iter_mut()
+                    .flatten()
+                    .flat_map(|n| [&mut n.gamma, &mut n.beta]),
+            );
+        self.training.step(params, grads.into_vec())
+    }
 
-        for (weight_matrix, grad_matrix) in self.weights.iter_mut().zip(&grads.d_weights) {
-            for (w, g) in weight_matrix.data.iter_mut().zip(&grad_matrix.data) {
-                *w += g * lr;
+    pub fn try_train(&mut self, input: &[f32], target: &[f32]) -> Result<f32> {
+        Self::check_vector(input, self.layers[0])?;
+        Self::check_vector(target, *self.layers.last().unwrap())?;
+        model::check_batch_size(1, self.min_batch_size())?;
+        Ok(self.train(input, target))
+    }
+
+    pub fn train(&mut self, input: &[f32], target: &[f32]) -> f32 {
+        let mut grads = self.new_gradients();
+        let loss = self.compute_gradients_single(input, target, &mut grads);
+        let penalty = self.regularization_loss();
+        self.add_regularization_gradients(&mut grads);
+        self.apply_gradients(&grads, 1.0);
+        loss + penalty
+    }
+
+    pub fn try_train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> Result<f32> {
+        self.check_batch(inputs, targets)?;
+        model::check_batch_size(inputs.len(), self.min_batch_size())?;
+        Ok(self.train_batch_parallel(inputs, targets, num_threads))
+    }
+
+    // Panics on batches smaller than `min_batch_size`.
+    pub fn train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> f32 {
+        let batch_size = inputs.len();
+        if batch_size == 0 {
+            return 0.0;
+        }
+        model::check_batch_size(batch_size, self.min_batch_size())
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        // Each chunk draws its dropout masks from its own generator, seeded
+        // from the network's, so results do not depend on thread scheduling.
+        // Without dropout nothing is drawn and seeded runs stay as they were.
+        let seed = if self.dropout.iter().any(Option::is_some) {
+            self.rng.next_u64()
+        } else {
+            0
+        };
+
+        let model = BatchModel {
+            layers: &self.layers,
+            layer_activations: &self.layer_activations,
+            loss: self.loss,
+            weights: &self.weights,
+            biases: &self.biases,
+            dropout: &self.dropout,
+            normalization: &self.normalization,
+        };
+        let num_layers = self.weights.len();
+
+        let chunk_size = model::chunk_size(batch_size);
+        let per_task = model::chunks_per_task(batch_size, num_threads);
+
+        let mut passes: Vec<ChunkPass> = inputs
+            .par_chunks(chunk_size)
+            .zip(targets.par_chunks(chunk_size))
+            .enumerate()
+            .with_min_len(per_task)
+            .map(|(index, (in_chunk, tgt_chunk))| {
+                let rng = StdRng::seed_from_u64(seed.wrapping_add(index as u64));
+                ChunkPass::new(&model, in_chunk, tgt_chunk, rng)
+            })
+            .collect();
+
+        // The pass runs one layer at a time across all chunks, so batch
+        // normalization can combine statistics from the whole batch before
+        // any chunk moves on. Partial results are always combined in chunk
+        // order to keep seeded runs bit-identical.
+        let mut batch_moments = Vec::new();
+        for l in 0..num_layers {
+            passes
+                .par_iter_mut()
+                .with_min_len(per_task)
+                .for_each(|p| p.linear(&model, l));
+
+            if let Some(norm) = &model.normalization[l] {
+                let stats = if norm.is_batch() {
+                    let mut sums = vec![0.0; norm.features()];
+                    let mut squares = vec![0.0; norm.features()];
+                    for pass in &passes {
+                        let (s, q) = Normalization::partial_moments(&pass.weighted_sums[l + 1]);
+                        for r in 0..sums.len() {
+                            sums[r] += s[r];
+                            squares[r] += q[r];
+                        }
+                    }
+                    let (stats, var) = norm.batch_stats(&sums, &squares, batch_size);
+                    batch_moments.push((l, stats.mean.clone(), var));
+                    Some(stats)
+                } else {
+                    None
+                };
+                passes
+                    .par_iter_mut()
+                    .with_min_len(per_task)
+                    .for_each(|p| p.normalize(&model, l, stats.as_ref()));
             }
+
+            passes
+                .par_iter_mut()
+                .with_min_len(per_task)
+                .for_each(|p| p.activate(&model, l));
         }
 
-        for (bias_matrix, grad_b) in self.biases.iter_mut().zip(&grads.d_biases) {
-            for (b, g) in bias_matrix.data.iter_mut().zip(&grad_b.data) {
-                *b += g * lr;
+        passes
+            .par_iter_mut()
+            .with_min_len(per_task)
+            .for_each(|p| p.output_error(&model));
+
+        for l in (0..num_layers).rev() {
+            if let Some(norm) = &model.normalization[l] {
+                let partials: Vec<(Matrix, Matrix)> = passes
+                    .par_iter_mut()
+                    .with_min_len(per_task)
+                    .map(|p| p.norm_parameter_gradients(&model, l))
+                    .collect();
+
+                let totals = if norm.is_batch() {
+                    let mut d_gamma = Matrix::new(norm.features(), 1);
+                    let mut d_beta = Matrix::new(norm.features(), 1);
+                    for (g, b) in &partials {
+                        for (x, y) in d_gamma.data.iter_mut().zip(&g.data) {
+                            *x += y;
+                        }
+                        for (x, y) in d_beta.data.iter_mut().zip(&b.data) {
+                            *x += y;
+                        }
+                    }
+                    Some((d_gamma, d_beta))
+                } else {
+                    None
+                };
+                let totals = totals.as_ref().map(|(g, b)| (g, b, batch_size));
+                passes
+                    .par_iter_mut()
+                    .with_min_len(per_task)
+                    .for_each(|p| p.norm_backward(&model, l, totals));
             }
+
+            passes
+                .par_iter_mut()
+                .with_min_len(per_task)
+                .for_each(|p| p.backward(&model, l));
+        }
+
+        let mut total_grads = self.new_gradients();
+        let mut loss_sum = 0.0;
+        for pass in &passes {
+            total_grads.add(&pass.grads);
+            loss_sum += pass.loss_sum;
         }
+        drop(passes);
+
+        for (l, mean, var) in batch_moments {
+            if let Some(norm) = &mut self.normalization[l] {
+                norm.update_running(&mean, &var);
+            }
+        }
+
+        let scale = 1.0 / (batch_size as f32);
+        total_grads.scale(scale);
+        let penalty = self.regularization_loss();
+        self.add_regularization_gradients(&mut total_grads);
+        self.apply_gradients(&total_grads, 1.0);
+
+        loss_sum * scale + penalty
     }
 
-    pub fn train(&mut self, input: &[f32], target: &[f32]) {
-        let mut grads = Gradients::new(&self.layers);
-        grads.zero();
-        self.compute_gradients_single(input, target, &mut grads);
-        self.apply_gradients(&grads, 1.0);
+    // Weights, biases, then gamma, beta and running statistics of every
+    // normalized layer: the tensors `Model::snapshot` copies.
+    fn tensors_mut(&mut self) -> Vec<&mut Matrix> {
+        let norms = self.normalization.iter_mut().flatten().flat_map(|n| {
+            [
+                &mut n.gamma,
+                &mut n.beta,
+                &mut n.running_mean,
+                &mut n.running_var,
+            ]
+        });
+        self.weights
+            .iter_mut()
+            .chain(&mut self.biases)
+            .chain(norms)
+            .collect()
+    }
+}
+
+impl Model for Network {
+    fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    fn check_batch(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<()> {
+        Network::check_batch(self, inputs, targets)
     }
 
-    fn compute_batch_gradients_chunk(
-        layers: &[usize],
-        weights: &[Matrix],
-        biases: &[Matrix],
+    fn min_batch_size(&self) -> usize {
+        Network::min_batch_size(self)
+    }
+
+    fn current_learning_rate(&self) -> f32 {
+        Network::current_learning_rate(self)
+    }
+
+    fn train_batch_parallel(
+        &mut self,
         inputs: &[Vec<f32>],
         targets: &[Vec<f32>],
-    ) -> Gradients {
+        num_threads: usize,
+    ) -> f32 {
+        Network::train_batch_parallel(self, inputs, targets, num_threads)
+    }
+
+    fn evaluate(&mut self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> f32 {
+        Network::evaluate(self, inputs, targets)
+    }
+
+    fn end_epoch(&mut self, metric: f32) {
+        Network::end_epoch(self, metric);
+    }
+
+    fn snapshot(&self) -> Vec<Matrix> {
+        let norms = self.normalization.iter().flatten().flat_map(|n| {
+            [&n.gamma, &n.beta, &n.running_mean, &n.running_var]
+        });
+        self.weights
+            .iter()
+            .chain(&self.biases)
+            .chain(norms)
+            .cloned()
+            .collect()
+    }
+
+    fn restore(&mut self, snapshot: Vec<Matrix>) -> Result<()> {
+        model::assign(self.tensors_mut(), snapshot)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        Network::save(self, path)
+    }
+
+    fn save_json(&self, path: &Path) -> Result<()> {
+        Network::save_json(self, path)
+    }
+}
+
+// Position of layer `l`'s gamma gradient in `Gradients::d_norm`.
+fn norm_slot(normalization: &[Option<Normalization>], l: usize) -> usize {
+    2 * normalization[..l].iter().filter(|n| n.is_some()).count()
+}
+
+// One rayon chunk's share of a batched training pass. Matrices hold one
+// sample per column and are indexed by layer like the network's buffers.
+struct ChunkPass<'a> {
+    targets: &'a [Vec<f32>],
+    rng: StdRng,
+    activations: Vec<Matrix>,
+    weighted_sums: Vec<Matrix>,
+    errors: Vec<Matrix>,
+    masks: Vec<Option<Matrix>>,
+    norm_cache: Vec<Option<(Matrix, NormStats)>>,
+    grads: Gradients,
+    loss_sum: f32,
+}
+
+impl<'a> ChunkPass<'a> {
+    fn new(
+        model: &BatchModel,
+        inputs: &[Vec<f32>],
+        targets: &'a [Vec<f32>],
+        rng: StdRng,
+    ) -> Self {
+        let layers = model.layers;
         let batch_size = inputs.len();
         let input_dim = layers[0];
-        let output_dim = *layers.last().unwrap();
 
         let mut input_batch = Matrix::new(input_dim, batch_size);
         for (b, input) in inputs.iter().enumerate() {
@@ -257,166 +1292,836 @@ impl Network {
             }
         }
 
+        let mut activations = Vec::with_capacity(layers.len());
+        let mut weighted_sums = Vec::with_capacity(layers.len());
+        let mut errors = Vec::with_capacity(layers.len());
+
+        activations.push(input_batch);
+        weighted_sums.push(Matrix::new(input_dim, batch_size));
+        errors.push(Matrix::new(input_dim, batch_size));
+
+        for &rows in &layers[1..] {
+            activations.push(Matrix::new(rows, batch_size));
+            weighted_sums.push(Matrix::new(rows, batch_size));
+            errors.push(Matrix::new(rows, batch_size));
+        }
+
+        ChunkPass {
+            targets,
+            rng,
+            activations,
+            weighted_sums,
+            errors,
+            masks: vec![None; layers.len()],
+            norm_cache: vec![None; layers.len()],
+            grads: Gradients::new(layers).with_normalization(model.normalization),
+            loss_sum: 0.0,
+        }
+    }
+
+    fn linear(&mut self, model: &BatchModel, l: usize) {
+        let z = &mut self.weighted_sums[l + 1];
+        model.weights[l].dot(&self.activations[l], z);
+
+        let cols = z.cols;
+        for (r, &b) in model.biases[l].data.iter().enumerate() {
+            for v in &mut z.data[r * cols..(r + 1) * cols] {
+                *v += b;
+            }
+        }
+    }
+
+    // `stats` are the whole-batch statistics for batch normalization; layer
+    // normalization computes its own per sample.
+    fn normalize(&mut self, model: &BatchModel, l: usize, stats: Option<&NormStats>) {
+        let norm = model.normalization[l].as_ref().unwrap();
+        let z = &mut self.weighted_sums[l + 1];
+        let stats = match stats {
+            Some(stats) => stats.clone(),
+            None => norm.sample_stats(z),
+        };
+        let x_hat = norm.normalize(z, &stats);
+        self.norm_cache[l + 1] = Some((x_hat, stats));
+    }
+
+    fn activate(&mut self, model: &BatchModel, l: usize) {
+        let z = &self.weighted_sums[l + 1];
+        let a = &mut self.activations[l + 1];
+        model.layer_activations[l].apply(z, a);
+
+        if let Some(d) = model.dropout[l] {
+            let mut mask = Matrix::new(a.rows, a.cols);
+            d.apply(&mut a.data, &mut mask.data, &mut self.rng);
+            self.masks[l + 1] = Some(mask);
+        }
+    }
+
+    fn output_error(&mut self, model: &BatchModel) {
+        let last = model.layers.len() - 1;
+        let output_dim = model.layers[last];
+        let batch_size = self.targets.len();
+        let output_a = &self.activations[last];
+        let output_z = &self.weighted_sums[last];
+        let output_activation = model.layer_activations[last - 1];
+
         let mut target_batch = Matrix::new(output_dim, batch_size);
-        for (b, target) in targets.iter().enumerate() {
+        for (b, target) in self.targets.iter().enumerate() {
             debug_assert_eq!(target.len(), output_dim);
             for (i, &v) in target.iter().enumerate() {
                 target_batch.data[i * batch_size + b] = v;
             }
         }
 
-        let num_layers = layers.len() - 1;
-        let mut activations_batch: Vec<Matrix> = Vec::with_capacity(layers.len());
-        let mut weighted_sums_batch: Vec<Matrix> = Vec::with_capacity(layers.len());
-        let mut errors_batch: Vec<Matrix> = Vec::with_capacity(layers.len());
+        let mut sample_output = vec![0.0; output_dim];
+        let mut sample_logits = vec![0.0; output_dim];
+        for (b, target) in self.targets.iter().enumerate() {
+            for r in 0..output_dim {
+                sample_output[r] = output_a.data[r * batch_size + b];
+                sample_logits[r] = output_z.data[r * batch_size + b];
+            }
+            self.loss_sum += model.loss.value_with_logits(
+                output_activation,
+                &sample_output,
+                &sample_logits,
+                target,
+            );
+        }
 
-        activations_batch.push(input_batch);
-        weighted_sums_batch.push(Matrix::new(layers[0], batch_size));
-        errors_batch.push(Matrix::new(layers[0], batch_size));
+        model.loss.output_error(
+            output_activation,
+            &output_a.data,
+            &output_z.data,
+            &target_batch.data,
+            batch_size,
+            &mut self.errors[last].data,
+        );
+    }
 
-        for l in 0..num_layers {
-            let rows = layers[l + 1];
-            let cols = layers[l];
-            let z = Matrix::new(rows, batch_size);
-            let a = Matrix::new(rows, batch_size);
-            let e = Matrix::new(rows, batch_size);
+    // Stores this chunk's gamma/beta gradients and returns them for the
+    // whole-batch totals batch normalization needs.
+    fn norm_parameter_gradients(&mut self, model: &BatchModel, l: usize) -> (Matrix, Matrix) {
+        let (x_hat, _) = self.norm_cache[l + 1].as_ref().unwrap();
+        let (d_gamma, d_beta) = Normalization::parameter_gradients(&self.errors[l + 1], x_hat);
+        let slot = norm_slot(model.normalization, l);
+        self.grads.d_norm[slot] = d_gamma.clone();
+        self.grads.d_norm[slot + 1] = d_beta.clone();
+        (d_gamma, d_beta)
+    }
 
-            weighted_sums_batch.push(z);
-            activations_batch.push(a);
-            errors_batch.push(e);
+    fn norm_backward(
+        &mut self,
+        model: &BatchModel,
+        l: usize,
+        totals: Option<(&Matrix, &Matrix, usize)>,
+    ) {
+        let norm = model.normalization[l].as_ref().unwrap();
+        let (x_hat, stats) = self.norm_cache[l + 1].as_ref().unwrap();
+        norm.backward(&mut self.errors[l + 1], x_hat, stats, totals);
+    }
 
-            debug_assert_eq!(weights[l].rows, rows);
-            debug_assert_eq!(weights[l].cols, cols);
+    // Weight and bias gradients of layer `l`, then the error pushed back to
+    // the previous layer's weighted sums.
+    fn backward(&mut self, model: &BatchModel, l: usize) {
+        let curr_error = &self.errors[l + 1];
+        let prev_activation = &self.activations[l];
+        let rows = model.layers[l + 1];
+        let cols = model.layers[l];
+        let batch_cols = curr_error.cols;
+
+        let mut grad_w = Matrix::new(rows, cols);
+        curr_error.dot_rhs_transposed(prev_activation, &mut grad_w);
+
+        let mut grad_b = Matrix::new(rows, 1);
+        for r in 0..rows {
+            grad_b.data[r] = curr_error.data[r * batch_cols..(r + 1) * batch_cols].iter().sum();
         }
 
-        for l in 0..num_layers {
-            let w = &weights[l];
-            let bias = &biases[l];
-
-            let (prev_slice, next_slice) = activations_batch.split_at_mut(l + 1);
-            let prev_a = &prev_slice[l];
-            let z = &mut weighted_sums_batch[l + 1];
-            let a = &mut next_slice[0];
-
-            w.dot(prev_a, z);
-
-            let is_last = l == num_layers - 1;
-            let rows = z.rows;
-            let cols = z.cols;
-
-            for r in 0..rows {
-                let b_val = bias.data[r];
-                let row_offset = r * cols;
-                for c in 0..cols {
-                    let idx = row_offset + c;
-                    let z_val = z.data[idx] + b_val;
-                    z.data[idx] = z_val;
-                    a.data[idx] = if is_last { z_val } else { Self::relu(z_val) };
+        self.grads.d_weights[l] = grad_w;
+        self.grads.d_biases[l] = grad_b;
+
+        if l > 0 {
+            let mut prev_error = Matrix::new(cols, batch_cols);
+            model.weights[l].dot_self_transposed(curr_error, &mut prev_error);
+
+            if let Some(mask) = &self.masks[l] {
+                for (e, m) in prev_error.data.iter_mut().zip(&mask.data) {
+                    *e *= m;
                 }
             }
+
+            let prev_z = &self.weighted_sums[l];
+            let activation = model.layer_activations[l - 1];
+            for (e, &z) in prev_error.data.iter_mut().zip(&prev_z.data) {
+                *e *= activation.derivative(z);
+            }
+
+            self.errors[l] = prev_error;
         }
+    }
+}
 
-        let mut grads = Gradients::new(layers);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::check_model;
+
+    #[test]
+    fn new_uses_relu_hidden_layers_and_a_linear_output() {
+        let net = Network::new(vec![2, 3, 3, 1], 0.1);
+        assert_eq!(
+            net.layer_activations(),
+            &[Activation::Relu, Activation::Relu, Activation::Identity]
+        );
+    }
 
-        {
-            let output_a = &activations_batch[num_layers];
-            let output_err = &mut errors_batch[num_layers];
+    #[test]
+    fn forward_applies_each_layer_activation() {
+        let mut net = Network::with_activations(
+            vec![1, 2, 1],
+            vec![Activation::Relu, Activation::Sigmoid],
+            0.1,
+        );
+        net.weights[0].data = vec![1.0, -1.0];
+        net.biases[0].data = vec![0.0, 0.0];
+        net.weights[1].data = vec![2.0, 3.0];
+        net.biases[1].data = vec![-1.0];
+        // relu(0.5) = 0.5 and relu(-0.5) = 0, then sigmoid(2 * 0.5 - 1).
+        assert_eq!(net.forward(&[0.5]), vec![0.5]);
+    }
 
-            let len = output_a.data.len();
-            for i in 0..len {
-                output_err.data[i] = target_batch.data[i] - output_a.data[i];
+    #[test]
+    #[should_panic(expected = "softmax is only supported on the output layer")]
+    fn softmax_is_rejected_on_hidden_layers() {
+        Network::with_activations(
+            vec![2, 2, 2],
+            vec![Activation::Softmax, Activation::Softmax],
+            0.1,
+        );
+    }
+
+    #[test]
+    fn evaluate_averages_the_loss_over_samples() {
+        let mut net = Network::new(vec![2, 3, 1], 0.1).with_loss(Loss::MeanAbsoluteError);
+        let inputs = vec![vec![0.1, 0.2], vec![-0.3, 0.4]];
+        let targets = vec![vec![1.0], vec![-1.0]];
+        let expected: f32 = inputs
+            .iter()
+            .zip(&targets)
+            .map(|(x, t)| Loss::MeanAbsoluteError.value(&net.forward(x), t))
+            .sum::<f32>()
+            / 2.0;
+        assert!((net.evaluate(&inputs, &targets) - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn evaluate_checks_the_lengths() {
+        let mut net = Network::new(vec![2, 3, 1], 0.1);
+        let inputs = vec![vec![0.1, 0.2], vec![-0.3, 0.4]];
+        assert!(matches!(
+            net.try_evaluate(&inputs, &[vec![1.0]]),
+            Err(RustingBrainError::BatchSizeMismatch { inputs: 2, targets: 1 })
+        ));
+        assert!(matches!(
+            net.try_evaluate(&inputs, &[vec![1.0], vec![1.0, 0.0]]),
+            Err(RustingBrainError::ShapeMismatch { expected: (1, 1), found: (2, 1) })
+        ));
+        assert_eq!(net.try_evaluate(&[], &[]).unwrap(), 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "shape mismatch")]
+    fn evaluate_panics_on_a_wrong_target_length() {
+        let mut net = Network::new(vec![2, 3, 1], 0.1);
+        net.evaluate(&[vec![0.1, 0.2]], &[vec![1.0, 0.0]]);
+    }
+
+    fn saved(net: &Network) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        net.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn data(matrices: &[Matrix]) -> Vec<Vec<f32>> {
+        matrices.iter().map(|m| m.data.clone()).collect()
+    }
+
+    fn read_error(mut bytes: &[u8]) -> RustingBrainError {
+        match Network::read_from(&mut bytes) {
+            Ok(_) => panic!("read succeeded"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn binary_round_trip_keeps_everything() {
+        let net = Network::with_activations(
+            vec![3, 4, 2],
+            vec![Activation::LeakyRelu(0.05), Activation::Softmax],
+            0.03,
+        )
+        .with_loss(Loss::Huber(0.7));
+        let bytes = saved(&net);
+        assert_eq!(&bytes[..4], b"RBNN");
+
+        let mut loaded = Network::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(loaded.layers, net.layers);
+        assert_eq!(loaded.layer_activations(), net.layer_activations());
+        assert_eq!(loaded.loss(), Loss::Huber(0.7));
+        assert_eq!(loaded.learning_rate(), 0.03);
+        assert_eq!(data(&loaded.weights), data(&net.weights));
+        assert_eq!(data(&loaded.biases), data(&net.biases));
+        assert_eq!(saved(&loaded), bytes);
+        assert_eq!(loaded.forward(&[0.1, 0.2, 0.3]).len(), 2);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let net = Network::new(vec![2, 3, 1], 0.1);
+        let path = std::env::temp_dir().join(format!("rb-network-{}.bin", std::process::id()));
+        net.save(&path).unwrap();
+        let loaded = Network::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data(&loaded.unwrap().weights), data(&net.weights));
+    }
+
+    #[test]
+    fn read_rejects_bad_headers_and_short_files() {
+        let bytes = saved(&Network::new(vec![2, 3, 1], 0.1));
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        let err = read_error(&wrong_magic);
+        assert!(matches!(err, RustingBrainError::BadMagic));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4..8].copy_from_slice(&99u32.to_le_bytes());
+        let err = read_error(&wrong_version);
+        assert!(matches!(err, RustingBrainError::UnsupportedVersion(99)));
+
+        // Cut inside the header, the layer list and the last matrix.
+        for len in [6, 14, bytes.len() - 1] {
+            let err = read_error(&bytes[..len]);
+            assert!(matches!(err, RustingBrainError::Truncated), "{} bytes: {}", len, err);
+        }
+    }
+
+    #[test]
+    fn read_does_not_trust_declared_sizes() {
+        let bytes = saved(&Network::new(vec![2, 3], 0.1));
+        let size = |bytes: &mut Vec<u8>, at: usize, v: u32| {
+            bytes[at..at + 4].copy_from_slice(&v.to_le_bytes())
+        };
+
+        // A layer count of u32::MAX over a short file.
+        let mut huge_count = bytes[..20].to_vec();
+        size(&mut huge_count, 8, u32::MAX);
+        assert!(matches!(read_error(&huge_count), RustingBrainError::Truncated));
+
+        // A 60000x60000 matrix (~14 GB) with a few bytes of data behind it.
+        let mut huge_matrix = bytes.clone();
+        for at in [12, 16, 34, 38] {
+            size(&mut huge_matrix, at, 60_000);
+        }
+        assert!(matches!(read_error(&huge_matrix), RustingBrainError::Truncated));
+
+        // rows * cols * 4 overflows.
+        let mut overflow = bytes.clone();
+        for at in [12, 16, 34, 38] {
+            size(&mut overflow, at, u32::MAX);
+        }
+        assert!(matches!(
+            read_error(&overflow),
+            RustingBrainError::InvalidArchitecture(_)
+        ));
+    }
+
+    #[test]
+    fn read_rejects_inconsistent_architectures() {
+        let mut bytes = saved(&Network::new(vec![2, 3, 1], 0.1));
+        // The second layer claims 4 neurons, so the first weights are 3x2 instead of 4x2.
+        bytes[16..20].copy_from_slice(&4u32.to_le_bytes());
+        let err = read_error(&bytes);
+        assert!(matches!(
+            err,
+            RustingBrainError::ShapeMismatch {
+                expected: (4, 2),
+                found: (3, 2)
             }
+        ));
+
+        let mut bytes = saved(&Network::new(vec![2, 3, 1], 0.1));
+        bytes[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let err = read_error(&bytes);
+        assert!(matches!(err, RustingBrainError::InvalidArchitecture(_)));
+    }
+
+    #[test]
+    fn json_round_trip_keeps_everything() {
+        let mut net = Network::with_activations(
+            vec![2, 3, 2],
+            vec![Activation::Elu(0.5), Activation::Softmax],
+            0.25,
+        )
+        .with_loss(Loss::CategoricalCrossEntropy);
+        // A diverged model still exports.
+        net.weights[0].data[0] = f32::NAN;
+        net.weights[0].data[1] = f32::INFINITY;
+        net.biases[1].data[0] = f32::NEG_INFINITY;
+
+        let loaded = Network::from_json(&net.to_json()).unwrap();
+        assert_eq!(loaded.layers, net.layers);
+        assert_eq!(loaded.layer_activations(), net.layer_activations());
+        assert_eq!(loaded.loss(), Loss::CategoricalCrossEntropy);
+        assert_eq!(loaded.learning_rate(), 0.25);
+        let bits = |ms: &[Matrix]| -> Vec<u32> {
+            ms.iter().flat_map(|m| m.data.iter().map(|v| v.to_bits())).collect()
+        };
+        assert_eq!(bits(&loaded.weights), bits(&net.weights));
+        assert_eq!(bits(&loaded.biases), bits(&net.biases));
+        assert_eq!(loaded.to_json(), net.to_json());
+    }
+
+    #[test]
+    fn from_json_rejects_foreign_or_broken_files() {
+        let text = Network::new(vec![2, 3, 1], 0.1).to_json();
+        let load = |text: &str| match Network::from_json(text) {
+            Ok(_) => panic!("load succeeded"),
+            Err(e) => e,
+        };
+        assert!(matches!(load("{"), RustingBrainError::InvalidJson(_)));
+        assert!(matches!(
+            load(&text.replace("\"rustingbrain\"", "\"other\"")),
+            RustingBrainError::BadMagic
+        ));
+        let version = format!("\"version\": {}", persistence::VERSION);
+        assert!(matches!(
+            load(&text.replace(&version, "\"version\": 7")),
+            RustingBrainError::UnsupportedVersion(7)
+        ));
+        assert!(matches!(
+            load(&text.replace("\"layers\": [2, 3, 1]", "\"layers\": [2, 4, 1]")),
+            RustingBrainError::ShapeMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn default_initializers_follow_the_activation() {
+        let net = Network::new_with_seed(vec![50, 40, 30], 0.1, 3);
+        // He bound for the ReLU layer, the tighter Xavier bound for the linear output.
+        let bounds = [(6.0f32 / 50.0).sqrt(), (6.0f32 / 70.0).sqrt()];
+        for ((weight, bias), bound) in net.weights.iter().zip(&net.biases).zip(bounds) {
+            assert!(weight.data.iter().all(|w| w.abs() <= bound));
+            assert!(weight.data.iter().any(|w| w.abs() > 0.9 * bound));
+            assert!(bias.data.iter().all(|&b| b == 0.0));
         }
 
-        for l in (0..num_layers).rev() {
-            let curr_error = &errors_batch[l + 1];
-            let prev_activation = &activations_batch[l];
-
-            let rows = layers[l + 1];
-            let cols = layers[l];
-
-            let mut grad_w = Matrix::new(rows, cols);
-            curr_error.dot_rhs_transposed(prev_activation, &mut grad_w);
-
-            let mut grad_b = Matrix::new(rows, 1);
-            let batch_cols = curr_error.cols;
-            for r in 0..rows {
-                let mut sum = 0.0;
-                let row_offset = r * batch_cols;
-                for c in 0..batch_cols {
-                    sum += curr_error.data[row_offset + c];
-                }
-                grad_b.data[r] = sum;
+        let uniform = Network::new_with_seed(vec![50, 40, 30], 0.1, 3)
+            .with_initializer(Initializer::RandomUniform, Initializer::RandomUniform);
+        for (weight, bias) in uniform.weights.iter().zip(&uniform.biases) {
+            assert!(weight.data.iter().chain(&bias.data).all(|x| (0.0..1.0).contains(x)));
+        }
+    }
+
+    #[test]
+    fn with_initializer_replaces_every_layer() {
+        let net = Network::new(vec![4, 3, 2], 0.1)
+            .with_initializer(Initializer::Orthogonal(1.0), Initializer::Zeros);
+        for (weight, bias) in net.weights.iter().zip(&net.biases) {
+            assert!(bias.data.iter().all(|&b| b == 0.0));
+            assert!(weight.data.iter().any(|&w| w < 0.0));
+        }
+    }
+
+    fn xor_batch() -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let mut inputs = Vec::new();
+        let mut targets = Vec::new();
+        for i in 0..16 {
+            let (a, b) = ((i & 1) as f32, ((i >> 1) & 1) as f32);
+            inputs.push(vec![a, b]);
+            targets.push(vec![if a == b { 0.0 } else { 1.0 }]);
+        }
+        (inputs, targets)
+    }
+
+    fn parameter_bits(net: &Network) -> Vec<u32> {
+        net.weights
+            .iter()
+            .chain(&net.biases)
+            .flat_map(|m| m.data.iter().map(|v| v.to_bits()))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_initial_parameters() {
+        let a = Network::new_with_seed(vec![2, 5, 1], 0.1, 42);
+        let b = Network::new(vec![2, 5, 1], 0.1).with_seed(42);
+        let c = Network::new_with_seed(vec![2, 5, 1], 0.1, 43);
+        assert_eq!(parameter_bits(&a), parameter_bits(&b));
+        assert_ne!(parameter_bits(&a), parameter_bits(&c));
+    }
+
+    #[test]
+    fn seeded_parallel_training_is_bit_identical() {
+        use crate::dropout::Dropout;
+        let (inputs, targets) = xor_batch();
+        let run = |threads| {
+            let mut net = Network::new_with_seed(vec![2, 8, 1], 0.1, 7)
+                .with_optimizer(crate::optimizer::Adam::default())
+                .with_dropout(1, Dropout::Standard(0.2))
+                .with_batch_norm(1);
+            for _ in 0..20 {
+                net.train_batch_parallel(&inputs, &targets, threads);
+            }
+            parameter_bits(&net)
+        };
+        // The chunks, their dropout seeds and the summation order follow the
+        // batch, not the thread count.
+        let one = run(1);
+        assert_eq!(one, run(1));
+        assert_eq!(one, run(2));
+        assert_eq!(one, run(4));
+    }
+
+    #[test]
+    fn try_constructors_report_invalid_architectures() {
+        for layers in [vec![3], vec![2, 0, 1]] {
+            let err = Network::try_new(layers, 0.1).err().unwrap();
+            assert!(matches!(err, RustingBrainError::InvalidArchitecture(_)));
+        }
+        let err = Network::try_with_activations(vec![2, 2], vec![], 0.1).err().unwrap();
+        assert!(matches!(err, RustingBrainError::InvalidArchitecture(_)));
+        let softmax = vec![Activation::Softmax, Activation::Identity];
+        let err = Network::try_with_activations(vec![2, 2, 1], softmax, 0.1).err().unwrap();
+        assert!(err.to_string().contains("softmax"));
+    }
+
+    #[test]
+    fn try_methods_report_bad_batches() {
+        let mut net = Network::new(vec![2, 3, 1], 0.1);
+        assert!(matches!(
+            net.try_forward(&[1.0]),
+            Err(RustingBrainError::ShapeMismatch {
+                expected: (2, 1),
+                found: (1, 1)
+            })
+        ));
+        assert!(matches!(
+            net.try_train(&[1.0, 2.0], &[1.0, 2.0]),
+            Err(RustingBrainError::ShapeMismatch { .. })
+        ));
+        assert!(matches!(
+            net.try_train_batch_parallel(&[], &[], 2),
+            Err(RustingBrainError::EmptyBatch)
+        ));
+        assert!(matches!(
+            net.try_train_batch_parallel(&[vec![1.0, 2.0]], &[], 2),
+            Err(RustingBrainError::BatchSizeMismatch {
+                inputs: 1,
+                targets: 0
+            })
+        ));
+        assert!(net.try_train(&[1.0, 2.0], &[0.5]).is_ok());
+    }
+
+    #[test]
+    fn schedulers_follow_steps_or_epochs() {
+        use crate::scheduler::{Interval, StepDecay};
+        let mut net = Network::new(vec![1, 2, 1], 0.8).with_scheduler(StepDecay::new(1, 0.5));
+        net.train(&[0.1], &[0.2]);
+        assert_eq!(net.current_learning_rate(), 0.8);
+        net.end_epoch(0.0);
+        assert_eq!(net.current_learning_rate(), 0.4);
+        assert_eq!(net.learning_rate(), 0.8);
+
+        let decay = StepDecay::new(2, 0.5).with_interval(Interval::Step);
+        let mut net = Network::new(vec![1, 2, 1], 0.8).with_scheduler(decay);
+        for _ in 0..2 {
+            net.train(&[0.1], &[0.2]);
+        }
+        net.end_epoch(0.0);
+        assert_eq!(net.current_learning_rate(), 0.4);
+    }
+
+    #[test]
+    fn clipping_limits_the_step_and_reports_the_raw_norm() {
+        use crate::clipping::GradientClipping;
+        let mut free = Network::new_with_seed(vec![2, 3, 1], 1.0, 8);
+        let mut clipped = Network::new_with_seed(vec![2, 3, 1], 1.0, 8)
+            .with_gradient_clipping(GradientClipping::GlobalNorm(1e-3));
+        let before = parameter_bits(&clipped);
+        free.train(&[1.0, -2.0], &[10.0]);
+        clipped.train(&[1.0, -2.0], &[10.0]);
+        assert_eq!(clipped.gradient_norm(), free.gradient_norm());
+        assert!(clipped.gradient_norm() > 1e-3);
+
+        let moved: f32 = clipped
+            .weights
+            .iter()
+            .chain(&clipped.biases)
+            .flat_map(|m| &m.data)
+            .zip(&before)
+            .map(|(a, &b)| (a - f32::from_bits(b)).powi(2))
+            .sum();
+        assert!((moved.sqrt() - 1e-3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn regularization_adds_to_the_reported_loss() {
+        use crate::regularizer::Regularizer;
+        let reg = Regularizer::l2(0.01).exclude_biases();
+        let mut net = Network::new_with_seed(vec![2, 3, 1], 0.1, 9).with_layer_regularizer(1, reg);
+        let expected = reg.penalty(&net.weights[1]);
+        assert!((net.regularization_loss() - expected).abs() < 1e-6);
+
+        let mut plain = Network::new_with_seed(vec![2, 3, 1], 0.1, 9);
+        let (x, t) = (vec![vec![0.3, -0.4]], vec![vec![1.0]]);
+        let gap = net.evaluate(&x, &t) - plain.evaluate(&x, &t);
+        assert!((gap - expected).abs() < 1e-6);
+        let gap = net.train(&x[0], &t[0]) - plain.train(&x[0], &t[0]);
+        assert!((gap - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn dropout_only_applies_while_training() {
+        use crate::dropout::Dropout;
+        let (inputs, targets) = xor_batch();
+        let run = || {
+            let mut net = Network::new_with_seed(vec![2, 16, 1], 0.1, 6)
+                .with_dropout(1, Dropout::Standard(0.5));
+            let first = net.forward(&inputs[1]);
+            assert_eq!(net.forward(&inputs[1]), first);
+            for _ in 0..5 {
+                net.train_batch_parallel(&inputs, &targets, 4);
             }
+            parameter_bits(&net)
+        };
+        // Masks come from the seeded generator.
+        assert_eq!(run(), run());
+
+        let mut with = Network::new_with_seed(vec![2, 16, 1], 0.1, 6)
+            .with_dropout(1, Dropout::Standard(0.5));
+        let mut without = Network::new_with_seed(vec![2, 16, 1], 0.1, 6);
+        with.train_batch_parallel(&inputs, &targets, 1);
+        without.train_batch_parallel(&inputs, &targets, 1);
+        assert_ne!(parameter_bits(&with), parameter_bits(&without));
+    }
 
-            grads.d_weights[l] = grad_w;
-            grads.d_biases[l] = grad_b;
+    #[test]
+    #[should_panic(expected = "dropout can only be applied to hidden layers")]
+    fn dropout_is_rejected_on_the_output_layer() {
+        Network::new(vec![2, 3, 1], 0.1).with_dropout(2, crate::dropout::Dropout::Standard(0.1));
+    }
 
-            if l > 0 {
-                let w = &weights[l];
-                let next_error = curr_error;
+    #[test]
+    #[should_panic(expected = "one per non-input layer")]
+    fn with_activations_checks_the_count() {
+        Network::with_activations(vec![2, 2, 1], vec![Activation::Relu], 0.1);
+    }
 
-                let mut prev_error = Matrix::new(cols, batch_cols);
-                w.dot_self_transposed(next_error, &mut prev_error);
+    #[test]
+    fn batch_norm_running_statistics_only_move_while_training() {
+        let (inputs, targets) = xor_batch();
+        let mut net = Network::new_with_seed(vec![2, 4, 1], 0.1, 3).with_batch_norm(1);
+        let running = |net: &Network| {
+            let norm = net.normalization()[0].as_ref().unwrap();
+            (norm.running_mean.data.clone(), norm.running_var.data.clone())
+        };
+        let before = running(&net);
+        net.forward(&inputs[1]);
+        assert_eq!(running(&net), before);
+        net.train_batch_parallel(&inputs, &targets, 2);
+        assert_ne!(running(&net), before);
+    }
 
-                let prev_z = &weighted_sums_batch[l];
-                let len = prev_error.data.len();
-                for i in 0..len {
-                    prev_error.data[i] *= Self::relu_derivative(prev_z.data[i]);
-                }
+    #[test]
+    fn batch_statistics_span_every_chunk() {
+        let (inputs, targets) = xor_batch();
+        let mut net = Network::new_with_seed(vec![2, 4, 1], 0.1, 3).with_batch_norm(1);
+        let (w, b) = (&net.weights[0], &net.biases[0]);
+        let mean: Vec<f32> = (0..4)
+            .map(|j| {
+                let z = |x: &Vec<f32>| w.data[j * 2] * x[0] + w.data[j * 2 + 1] * x[1] + b.data[j];
+                inputs.iter().map(z).sum::<f32>() / inputs.len() as f32
+            })
+            .collect();
+        net.train_batch_parallel(&inputs, &targets, 4);
+        // The running mean starts at zero and moves by 1 - momentum towards
+        // the mean of the whole batch, not of one chunk.
+        let norm = net.normalization()[0].as_ref().unwrap();
+        for (r, m) in norm.running_mean.data.iter().zip(&mean) {
+            assert!((r - 0.01 * m).abs() < 1e-6, "{} vs {}", r, 0.01 * m);
+        }
+    }
 
-                errors_batch[l] = prev_error;
+    #[test]
+    fn normalization_round_trips_through_both_formats() {
+        let (inputs, targets) = xor_batch();
+        let mut net = Network::new_with_seed(vec![2, 4, 3, 1], 0.1, 5)
+            .with_batch_norm(1)
+            .with_layer_norm(2);
+        net.train_batch_parallel(&inputs, &targets, 2);
+
+        let from_bytes = Network::read_from(&mut saved(&net).as_slice()).unwrap();
+        let from_json = Network::from_json(&net.to_json()).unwrap();
+        for loaded in [from_bytes, from_json] {
+            assert!(loaded.normalization()[2].is_none());
+            for (a, b) in loaded.normalization().iter().zip(net.normalization()).take(2) {
+                let (a, b) = (a.as_ref().unwrap(), b.as_ref().unwrap());
+                assert_eq!(a.kind, b.kind);
+                assert_eq!(a.gamma.data, b.gamma.data);
+                assert_eq!(a.beta.data, b.beta.data);
+                assert_eq!(a.running_mean.data, b.running_mean.data);
+                assert_eq!(a.running_var.data, b.running_var.data);
             }
         }
+    }
 
-        grads
+    #[test]
+    fn try_builders_reject_invalid_layers() {
+        use crate::dropout::Dropout;
+        let net = || Network::new(vec![2, 3, 1], 0.1);
+        for layer in [0, 2, 7] {
+            assert!(matches!(
+                net().try_with_dropout(layer, Dropout::Standard(0.1)),
+                Err(RustingBrainError::InvalidArchitecture(_))
+            ));
+        }
+        assert!(matches!(
+            net().try_with_dropout(1, Dropout::Alpha(1.0)),
+            Err(RustingBrainError::InvalidArchitecture(_))
+        ));
+        assert!(matches!(
+            net().try_with_normalization(1, Normalization::batch(4)),
+            Err(RustingBrainError::ShapeMismatch {
+                expected: (3, 1),
+                found: (4, 1)
+            })
+        ));
+        assert!(net().try_with_dropout(1, Dropout::Standard(0.1)).is_ok());
+        assert!(net().try_with_normalization(1, Normalization::layer(3)).is_ok());
     }
 
-    pub fn train_batch_parallel(
-        &mut self,
-        inputs: &[Vec<f32>],
-        targets: &[Vec<f32>],
-        num_threads: usize,
-    ) {
-        let batch_size = inputs.len();
-        if batch_size == 0 {
-            return;
+    #[test]
+    fn layer_regularizers_index_the_weights() {
+        use crate::regularizer::Regularizer;
+        let net = || Network::new(vec![2, 3, 1], 0.1);
+        assert!(matches!(
+            net().try_with_layer_regularizer(2, Regularizer::l1(0.1)),
+            Err(RustingBrainError::InvalidArchitecture(_))
+        ));
+        let reg = Regularizer::l1(0.1).exclude_biases();
+        let net = net().with_layer_regularizer(0, reg);
+        let expected = reg.penalty(&net.weights[0]);
+        assert_eq!(net.regularization_loss(), expected);
+    }
+
+    #[test]
+    fn out_of_range_norm_layers_are_errors() {
+        let net = || Network::new(vec![2, 3, 1], 0.1);
+        // Index 5 used to panic while looking up the layer size.
+        for layer in [0, 2, 5] {
+            assert!(matches!(
+                net().try_with_batch_norm(layer),
+                Err(RustingBrainError::InvalidArchitecture(_))
+            ));
+            assert!(matches!(
+                net().try_with_layer_norm(layer),
+                Err(RustingBrainError::InvalidArchitecture(_))
+            ));
         }
+        assert!(net().try_with_batch_norm(1).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "normalization can only be applied to hidden layers")]
+    fn with_batch_norm_panics_with_the_error() {
+        Network::new(vec![2, 3, 1], 0.1).with_batch_norm(5);
+    }
 
-        let layers = self.layers.clone();
-        let weights = &self.weights;
-        let biases = &self.biases;
+    fn gradient_batch() -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let inputs = vec![
+            vec![0.5, -0.2, 0.1],
+            vec![-0.7, 0.3, 0.9],
+            vec![0.2, 0.8, -0.4],
+            vec![-0.1, -0.6, 0.3],
+        ];
+        let targets = vec![
+            vec![0.3, -0.5, 0.2],
+            vec![-0.2, 0.4, 0.7],
+            vec![0.6, 0.1, -0.8],
+            vec![-0.4, -0.3, 0.5],
+        ];
+        (inputs, targets)
+    }
 
-        let chunks = num_threads.max(1).min(batch_size);
-        let chunk_size = (batch_size + chunks - 1) / chunks;
+    fn checked(learning_rate: f32) -> Network {
+        let activations = vec![Activation::Tanh, Activation::Sigmoid, Activation::Identity];
+        Network::with_activations(vec![3, 4, 4, 3], activations, learning_rate)
+            .with_seed(3)
+            .with_optimizer(crate::optimizer::Sgd::new())
+    }
 
-        let total_grads = inputs
-            .par_chunks(chunk_size)
-            .zip(targets.par_chunks(chunk_size))
-            .map(|(in_chunk, tgt_chunk)| {
-                Self::compute_batch_gradients_chunk(
-                    &layers,
-                    weights,
-                    biases,
-                    in_chunk,
-                    tgt_chunk,
-                )
+    // Weights and biases come first, then gamma, beta and the running
+    // statistics of each normalized layer.
+    fn trained(k: usize) -> bool {
+        k < 6 || (k - 6) % 4 < 2
+    }
+
+    #[test]
+    fn dense_gradients() {
+        let (inputs, targets) = gradient_batch();
+        check_model(checked, &inputs, &targets, |_| true);
+    }
+
+    #[test]
+    fn batch_normalization_gradients() {
+        let (inputs, targets) = gradient_batch();
+        let build = |lr| checked(lr).with_batch_norm(1).with_batch_norm(2);
+        check_model(build, &inputs, &targets, trained);
+    }
+
+    #[test]
+    fn layer_normalization_gradients() {
+        let (inputs, targets) = gradient_batch();
+        let build = |lr| checked(lr).with_layer_norm(1).with_layer_norm(2);
+        check_model(build, &inputs, &targets, trained);
+    }
+
+    #[test]
+    fn batch_norm_needs_two_samples_to_train() {
+        let mut net = Network::new(vec![2, 3, 1], 0.1).with_batch_norm(1);
+        assert_eq!(net.min_batch_size(), 2);
+        assert_eq!(Network::new(vec![2, 3, 1], 0.1).with_layer_norm(1).min_batch_size(), 1);
+        assert!(matches!(
+            net.try_train(&[0.1, 0.2], &[1.0]),
+            Err(RustingBrainError::BatchTooSmall {
+                found: 1,
+                minimum: 2
             })
-            .reduce(
-                || {
-                    let mut g = Gradients::new(&layers);
-                    g.zero();
-                    g
-                },
-                |mut a, b| {
-                    a.add(&b);
-                    a
-                },
-            );
+        ));
+        assert!(matches!(
+            net.try_train_batch_parallel(&[vec![0.1, 0.2]], &[vec![1.0]], 1),
+            Err(RustingBrainError::BatchTooSmall { .. })
+        ));
+        let (inputs, targets) = xor_batch();
+        assert!(net.try_train_batch_parallel(&inputs, &targets, 4).is_ok());
+    }
 
-        let scale = 1.0 / (batch_size as f32);
-        let mut total_grads = total_grads;
-        total_grads.scale(scale);
-        self.apply_gradients(&total_grads, 1.0);
+    #[test]
+    #[should_panic(expected = "training batch has 1 samples but the model needs at least 2")]
+    fn batch_norm_panics_on_a_single_sample() {
+        Network::new(vec![2, 3, 1], 0.1).with_batch_norm(1).train(&[0.1, 0.2], &[1.0]);
+    }
+
+    #[test]
+    fn regularized_gradients() {
+        use crate::regularizer::Regularizer;
+        let (inputs, targets) = gradient_batch();
+        let build = |lr| {
+            checked(lr)
+                .with_layer_regularizer(0, Regularizer::elastic_net(0.01, 0.05))
+                .with_layer_regularizer(2, Regularizer::l2(0.1).exclude_biases())
+        };
+        check_model(build, &inputs, &targets, |_| true);
     }
 }
@@ -0,0 +1,392 @@
+use crate::matrix::Matrix;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormKind {
+    // Normalizes every feature over the batch; keeps running statistics for
+    // inference, updated as `momentum * running + (1 - momentum) * batch`.
+    Batch { momentum: f32 },
+    // Normalizes every sample over its features; same math in training and
+    // inference.
+    Layer,
+}
+
+// Normalization of a layer's weighted sums, applied before its activation
+// and followed by a learned per-feature scale (`gamma`) and shift (`beta`).
+#[derive(Clone, Debug)]
+pub struct Normalization {
+    pub kind: NormKind,
+    pub epsilon: f32,
+    pub gamma: Matrix,
+    pub beta: Matrix,
+    // Only used by batch normalization.
+    pub running_mean: Matrix,
+    pub running_var: Matrix,
+}
+
+// Mean and 1 / sqrt(var + epsilon) of one pass, indexed by feature for batch
+// normalization and by sample for layer normalization.
+#[derive(Clone, Debug)]
+pub(crate) struct NormStats {
+    pub mean: Vec<f32>,
+    pub inv_std: Vec<f32>,
+}
+
+impl Normalization {
+    pub fn batch(features: usize) -> Self {
+        Self::with_kind(NormKind::Batch { momentum: 0.99 }, features)
+    }
+
+    pub fn layer(features: usize) -> Self {
+        Self::with_kind(NormKind::Layer, features)
+    }
+
+    fn with_kind(kind: NormKind, features: usize) -> Self {
+        let mut gamma = Matrix::new(features, 1);
+        gamma.data.fill(1.0);
+        let mut running_var = Matrix::new(features, 1);
+        running_var.data.fill(1.0);
+
+        Normalization {
+            kind,
+            epsilon: 1e-3,
+            gamma,
+            beta: Matrix::new(features, 1),
+            running_mean: Matrix::new(features, 1),
+            running_var,
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f32) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    // Only meaningful for batch normalization.
+    pub fn with_momentum(mut self, momentum: f32) -> Self {
+        if let NormKind::Batch { .. } = self.kind {
+            self.kind = NormKind::Batch { momentum };
+        }
+        self
+    }
+
+    pub fn features(&self) -> usize {
+        self.gamma.rows
+    }
+
+    pub fn is_batch(&self) -> bool {
+        matches!(self.kind, NormKind::Batch { .. })
+    }
+
+    // Statistics for inference: the running estimates for batch
+    // normalization, the sample's own for layer normalization.
+    pub(crate) fn inference_stats(&self, z: &Matrix) -> NormStats {
+        match self.kind {
+            NormKind::Batch { .. } => NormStats {
+                mean: self.running_mean.data.clone(),
+                inv_std: self
+                    .running_var
+                    .data
+                    .iter()
+                    .map(|v| 1.0 / (v + self.epsilon).sqrt())
+                    .collect(),
+            },
+            NormKind::Layer => self.sample_stats(z),
+        }
+    }
+
+    // Per-sample statistics over the features of each column.
+    pub(crate) fn sample_stats(&self, z: &Matrix) -> NormStats {
+        let (rows, cols) = (z.rows, z.cols);
+        let mut mean = vec![0.0; cols];
+        let mut inv_std = vec![0.0; cols];
+        for c in 0..cols {
+            let m = (0..rows).map(|r| z.data[r * cols + c]).sum::<f32>() / rows as f32;
+            let var = (0..rows)
+                .map(|r| {
+                    let d = z.data[r * cols + c] - m;
+                    d * d
+                })
+                .sum::<f32>()
+                / rows as f32;
+            mean[c] = m;
+            inv_std[c] = 1.0 / (var + self.epsilon).sqrt();
+        }
+        NormStats { mean, inv_std }
+    }
+
+    // Per-feature sums and sums of squares of a (part of a) batch, so batch
+    // statistics can be combined across chunks before normalizing.
+    pub(crate) fn partial_moments(z: &Matrix) -> (Vec<f64>, Vec<f64>) {
+        let cols = z.cols;
+        let mut sums = vec![0.0f64; z.rows];
+        let mut squares = vec![0.0f64; z.rows];
+        for r in 0..z.rows {
+            for &v in &z.data[r * cols..(r + 1) * cols] {
+                sums[r] += v as f64;
+                squares[r] += (v as f64) * (v as f64);
+            }
+        }
+        (sums, squares)
+    }
+
+    // Batch statistics from combined moments, together with the (biased)
+    // batch variance for the running estimate.
+    pub(crate) fn batch_stats(
+        &self,
+        sums: &[f64],
+        squares: &[f64],
+        count: usize,
+    ) -> (NormStats, Vec<f32>) {
+        let n = count as f64;
+        let mean: Vec<f32> = sums.iter().map(|s| (s / n) as f32).collect();
+        let var: Vec<f32> = sums
+            .iter()
+            .zip(squares)
+            .map(|(s, sq)| (sq / n - (s / n) * (s / n)).max(0.0) as f32)
+            .collect();
+        let inv_std = var
+            .iter()
+            .map(|v| 1.0 / (v + self.epsilon).sqrt())
+            .collect();
+        (NormStats { mean, inv_std }, var)
+    }
+
+    pub(crate) fn update_running(&mut self, mean: &[f32], var: &[f32]) {
+        let NormKind::Batch { momentum } = self.kind else {
+            return;
+        };
+        for (r, m) in self.running_mean.data.iter_mut().zip(mean) {
+            *r = momentum * *r + (1.0 - momentum) * m;
+        }
+        for (r, v) in self.running_var.data.iter_mut().zip(var) {
+            *r = momentum * *r + (1.0 - momentum) * v;
+        }
+    }
+
+    // Normalizes `z` in place into `gamma * x_hat + beta` and returns
+    // `x_hat`, which backprop needs.
+    pub(crate) fn normalize(&self, z: &mut Matrix, stats: &NormStats) -> Matrix {
+        let (rows, cols) = (z.rows, z.cols);
+        let per_feature = self.is_batch();
+        let mut x_hat = Matrix::new(rows, cols);
+        for r in 0..rows {
+            for c in 0..cols {
+                let s = if per_feature { r } else { c };
+                let idx = r * cols + c;
+                let x = (z.data[idx] - stats.mean[s]) * stats.inv_std[s];
+                x_hat.data[idx] = x;
+                z.data[idx] = self.gamma.data[r] * x + self.beta.data[r];
+            }
+        }
+        x_hat
+    }
+
+    // Gradients of gamma and beta (summed over the columns) from the error
+    // at the normalized output.
+    pub(crate) fn parameter_gradients(error: &Matrix, x_hat: &Matrix) -> (Matrix, Matrix) {
+        let (rows, cols) = (error.rows, error.cols);
+        let mut d_gamma = Matrix::new(rows, 1);
+        let mut d_beta = Matrix::new(rows, 1);
+        for r in 0..rows {
+            for c in 0..cols {
+                let idx = r * cols + c;
+                d_gamma.data[r] += error.data[idx] * x_hat.data[idx];
+                d_beta.data[r] += error.data[idx];
+            }
+        }
+        (d_gamma, d_beta)
+    }
+
+    // Turns the error at the normalized output into the error at `z`, in
+    // place. `batch_totals` carries the gamma/beta gradients summed over the
+    // whole batch and its size when batch statistics were used; without it
+    // batch normalization is treated as a fixed affine map.
+    pub(crate) fn backward(
+        &self,
+        error: &mut Matrix,
+        x_hat: &Matrix,
+        stats: &NormStats,
+        batch_totals: Option<(&Matrix, &Matrix, usize)>,
+    ) {
+        let (rows, cols) = (error.rows, error.cols);
+
+        match (self.kind, batch_totals) {
+            (NormKind::Batch { .. }, Some((d_gamma, d_beta, count))) => {
+                let n = count as f32;
+                for r in 0..rows {
+                    let scale = self.gamma.data[r] * stats.inv_std[r];
+                    let mean_dy = d_beta.data[r] / n;
+                    let mean_dy_xhat = d_gamma.data[r] / n;
+                    for c in 0..cols {
+                        let idx = r * cols + c;
+                        error.data[idx] =
+                            scale * (error.data[idx] - mean_dy - x_hat.data[idx] * mean_dy_xhat);
+                    }
+                }
+            }
+            (NormKind::Batch { .. }, None) => {
+                for r in 0..rows {
+                    let scale = self.gamma.data[r] * stats.inv_std[r];
+                    for e in &mut error.data[r * cols..(r + 1) * cols] {
+                        *e *= scale;
+                    }
+                }
+            }
+            (NormKind::Layer, _) => {
+                for c in 0..cols {
+                    let mut mean_dx = 0.0;
+                    let mut mean_dx_xhat = 0.0;
+                    for r in 0..rows {
+                        let idx = r * cols + c;
+                        let dx = error.data[idx] * self.gamma.data[r];
+                        mean_dx += dx;
+                        mean_dx_xhat += dx * x_hat.data[idx];
+                    }
+                    mean_dx /= rows as f32;
+                    mean_dx_xhat /= rows as f32;
+
+                    for r in 0..rows {
+                        let idx = r * cols + c;
+                        let dx = error.data[idx] * self.gamma.data[r];
+                        error.data[idx] =
+                            stats.inv_std[c] * (dx - mean_dx - x_hat.data[idx] * mean_dx_xhat);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(rows: usize, cols: usize, data: &[f32]) -> Matrix {
+        let mut m = Matrix::new(rows, cols);
+        m.data.copy_from_slice(data);
+        m
+    }
+
+    fn z() -> Matrix {
+        matrix(2, 4, &[1.0, 2.0, 4.0, -3.0, 0.5, -1.0, 2.5, 3.0])
+    }
+
+    fn batch_stats(norm: &Normalization, z: &Matrix) -> (NormStats, Vec<f32>) {
+        let (sums, squares) = Normalization::partial_moments(z);
+        norm.batch_stats(&sums, &squares, z.cols)
+    }
+
+    #[test]
+    fn batch_norm_normalizes_each_feature_over_the_batch() {
+        let norm = Normalization::batch(2).with_epsilon(0.0);
+        let mut out = z();
+        let (stats, _) = batch_stats(&norm, &out);
+        norm.normalize(&mut out, &stats);
+        for row in out.data.chunks(4) {
+            let mean = row.iter().sum::<f32>() / 4.0;
+            let var = row.iter().map(|v| v * v).sum::<f32>() / 4.0;
+            assert!(mean.abs() < 1e-6 && (var - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn layer_norm_normalizes_each_sample_over_its_features() {
+        let norm = Normalization::layer(2).with_epsilon(0.0);
+        let mut out = z();
+        let stats = norm.sample_stats(&out);
+        norm.normalize(&mut out, &stats);
+        // Two features per sample normalize to -1 and 1.
+        for c in 0..4 {
+            assert!((out.data[c] + out.data[4 + c]).abs() < 1e-6);
+            assert!((out.data[c].abs() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn moments_combine_across_chunks() {
+        let norm = Normalization::batch(2);
+        let whole = z();
+        let left = matrix(2, 1, &[whole.data[0], whole.data[4]]);
+        let right = matrix(2, 3, &[2.0, 4.0, -3.0, -1.0, 2.5, 3.0]);
+        let (mut sums, mut squares) = Normalization::partial_moments(&left);
+        let (s, q) = Normalization::partial_moments(&right);
+        for i in 0..2 {
+            sums[i] += s[i];
+            squares[i] += q[i];
+        }
+        let (split, split_var) = norm.batch_stats(&sums, &squares, 4);
+        let (full, full_var) = batch_stats(&norm, &whole);
+        assert_eq!(split.mean, full.mean);
+        assert_eq!(split.inv_std, full.inv_std);
+        assert_eq!(split_var, full_var);
+    }
+
+    #[test]
+    fn running_statistics_follow_the_momentum() {
+        let mut norm = Normalization::batch(1).with_momentum(0.75);
+        norm.update_running(&[4.0], &[5.0]);
+        assert_eq!(norm.running_mean.data, vec![1.0]);
+        assert_eq!(norm.running_var.data, vec![2.0]);
+        // Inference uses the running estimates, not the input.
+        let stats = norm.inference_stats(&z());
+        assert_eq!(stats.mean, vec![1.0]);
+
+        // Layer normalization has none to update.
+        let mut layer = Normalization::layer(1).with_momentum(0.75);
+        layer.update_running(&[4.0], &[5.0]);
+        assert_eq!(layer.running_mean.data, vec![0.0]);
+    }
+
+    // Compares `backward` against central differences of
+    // sum(weights * normalize(z)) for each input.
+    fn check_backward(norm: &Normalization) {
+        let weights = matrix(2, 4, &[0.3, -1.2, 0.7, 2.0, -0.4, 0.9, 1.5, -0.8]);
+        let stats_of = |z: &Matrix| {
+            if norm.is_batch() {
+                batch_stats(norm, z).0
+            } else {
+                norm.sample_stats(z)
+            }
+        };
+        let objective = |z: &Matrix| {
+            let mut out = z.clone();
+            norm.normalize(&mut out, &stats_of(z));
+            out.data.iter().zip(&weights.data).map(|(a, b)| (a * b) as f64).sum::<f64>()
+        };
+
+        let input = z();
+        let mut out = input.clone();
+        let stats = stats_of(&input);
+        let x_hat = norm.normalize(&mut out, &stats);
+        let (d_gamma, d_beta) = Normalization::parameter_gradients(&weights, &x_hat);
+        let mut error = weights.clone();
+        norm.backward(&mut error, &x_hat, &stats, Some((&d_gamma, &d_beta, 4)));
+
+        let h = 1e-2;
+        for i in 0..input.data.len() {
+            let (mut plus, mut minus) = (input.clone(), input.clone());
+            plus.data[i] += h;
+            minus.data[i] -= h;
+            let numeric = (objective(&plus) - objective(&minus)) / (2.0 * h as f64);
+            assert!(
+                (error.data[i] as f64 - numeric).abs() < 1e-2,
+                "input {}: {} vs {}",
+                i,
+                error.data[i],
+                numeric
+            );
+        }
+    }
+
+    #[test]
+    fn backward_matches_finite_differences() {
+        let mut norm = Normalization::batch(2);
+        norm.gamma.data = vec![1.5, -0.5];
+        norm.beta.data = vec![0.2, 0.1];
+        check_backward(&norm);
+
+        let mut norm = Normalization::layer(2);
+        norm.gamma.data = vec![0.8, 1.3];
+        check_backward(&norm);
+    }
+}
@@ -0,0 +1,517 @@
+use crate::layer::RowGradient;
+use crate::matrix::Matrix;
+
+// Gradients in this crate hold the descent direction (`target - output`
+// flavoured, i.e. the negative gradient), so every update adds to the
+// parameters. Optimizer state is kept per parameter tensor ("slot"), keyed
+// by the tensor's position in its model: for a `Network` all weight matrices,
+// then all biases, then gamma and beta of each normalized layer; for
+// `Sequential` and `Graph` every layer's parameters in layer order, including
+// those of layers that are not trained.
+pub trait Optimizer: Send {
+    fn begin_step(&mut self) {}
+
+    fn update(&mut self, slot: usize, param: &mut Matrix, grad: &Matrix, learning_rate: f32);
+
+    // Same as `update` for a gradient that is zero outside `grad.rows`. The
+    // default hands `update` the equivalent dense gradient; the built-in
+    // optimizers instead touch only those rows of the parameter and of their
+    // own state, so rows a batch never saw keep their values and moments.
+    fn update_rows(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        grad: &RowGradient,
+        learning_rate: f32,
+    ) {
+        self.update(slot, param, &grad.to_dense(param.rows), learning_rate);
+    }
+}
+
+// A gradient as `(parameter row, gradient row)` pairs: every row of a dense
+// gradient, or just the touched ones of a `RowGradient`.
+fn dense_rows(grad: &Matrix) -> impl Iterator<Item = (usize, &[f32])> {
+    grad.data.chunks(grad.cols.max(1)).enumerate()
+}
+
+fn sparse_rows(grad: &RowGradient) -> impl Iterator<Item = (usize, &[f32])> {
+    let cols = grad.values.cols.max(1);
+    grad.rows.iter().copied().zip(grad.values.data.chunks(cols))
+}
+
+fn row(m: &mut Matrix, r: usize) -> &mut [f32] {
+    let cols = m.cols;
+    &mut m.data[r * cols..(r + 1) * cols]
+}
+
+fn slot_state<'a>(state: &'a mut Vec<Matrix>, slot: usize, like: &Matrix) -> &'a mut Matrix {
+    while state.len() <= slot {
+        state.push(Matrix::new(0, 0));
+    }
+    if state[slot].data.len() != like.data.len() {
+        state[slot] = Matrix::new(like.rows, like.cols);
+    }
+    &mut state[slot]
+}
+
+pub struct Sgd {
+    pub momentum: f32,
+    pub nesterov: bool,
+    // Decoupled decay, applied to the parameters like in `AdamW`.
+    pub weight_decay: f32,
+    velocity: Vec<Matrix>,
+}
+
+impl Sgd {
+    pub fn new() -> Self {
+        Self::momentum(0.0)
+    }
+
+    pub fn momentum(momentum: f32) -> Self {
+        Sgd {
+            momentum,
+            nesterov: false,
+            weight_decay: 0.0,
+            velocity: Vec::new(),
+        }
+    }
+
+    pub fn nesterov(momentum: f32) -> Self {
+        Sgd {
+            momentum,
+            nesterov: true,
+            weight_decay: 0.0,
+            velocity: Vec::new(),
+        }
+    }
+
+    pub fn with_weight_decay(mut self, weight_decay: f32) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+}
+
+impl Default for Sgd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sgd {
+    fn apply<'g>(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        rows: impl Iterator<Item = (usize, &'g [f32])>,
+        learning_rate: f32,
+    ) {
+        let decay = 1.0 - learning_rate * self.weight_decay;
+        let (momentum, nesterov) = (self.momentum, self.nesterov);
+        let mut velocity = (momentum != 0.0).then(|| slot_state(&mut self.velocity, slot, param));
+
+        for (r, grad) in rows {
+            let param = row(param, r);
+            if decay != 1.0 {
+                for p in param.iter_mut() {
+                    *p *= decay;
+                }
+            }
+
+            let Some(velocity) = velocity.as_deref_mut() else {
+                for (p, g) in param.iter_mut().zip(grad) {
+                    *p += g * learning_rate;
+                }
+                continue;
+            };
+            for ((p, g), v) in param.iter_mut().zip(grad).zip(row(velocity, r)) {
+                *v = momentum * *v + g;
+                let direction = if nesterov { g + momentum * *v } else { *v };
+                *p += direction * learning_rate;
+            }
+        }
+    }
+}
+
+impl Optimizer for Sgd {
+    fn update(&mut self, slot: usize, param: &mut Matrix, grad: &Matrix, learning_rate: f32) {
+        self.apply(slot, param, dense_rows(grad), learning_rate);
+    }
+
+    fn update_rows(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        grad: &RowGradient,
+        learning_rate: f32,
+    ) {
+        self.apply(slot, param, sparse_rows(grad), learning_rate);
+    }
+}
+
+pub struct Adagrad {
+    pub epsilon: f32,
+    accumulator: Vec<Matrix>,
+}
+
+impl Adagrad {
+    pub fn new(epsilon: f32) -> Self {
+        Adagrad {
+            epsilon,
+            accumulator: Vec::new(),
+        }
+    }
+}
+
+impl Default for Adagrad {
+    fn default() -> Self {
+        Self::new(1e-8)
+    }
+}
+
+impl Adagrad {
+    fn apply<'g>(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        rows: impl Iterator<Item = (usize, &'g [f32])>,
+        learning_rate: f32,
+    ) {
+        let epsilon = self.epsilon;
+        let acc = slot_state(&mut self.accumulator, slot, param);
+        for (r, grad) in rows {
+            for ((p, g), a) in row(param, r).iter_mut().zip(grad).zip(row(acc, r)) {
+                *a += g * g;
+                *p += learning_rate * g / (a.sqrt() + epsilon);
+            }
+        }
+    }
+}
+
+impl Optimizer for Adagrad {
+    fn update(&mut self, slot: usize, param: &mut Matrix, grad: &Matrix, learning_rate: f32) {
+        self.apply(slot, param, dense_rows(grad), learning_rate);
+    }
+
+    fn update_rows(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        grad: &RowGradient,
+        learning_rate: f32,
+    ) {
+        self.apply(slot, param, sparse_rows(grad), learning_rate);
+    }
+}
+
+pub struct RmsProp {
+    pub rho: f32,
+    pub epsilon: f32,
+    mean_square: Vec<Matrix>,
+}
+
+impl RmsProp {
+    pub fn new(rho: f32, epsilon: f32) -> Self {
+        RmsProp {
+            rho,
+            epsilon,
+            mean_square: Vec::new(),
+        }
+    }
+}
+
+impl Default for RmsProp {
+    fn default() -> Self {
+        Self::new(0.9, 1e-7)
+    }
+}
+
+impl RmsProp {
+    fn apply<'g>(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        rows: impl Iterator<Item = (usize, &'g [f32])>,
+        learning_rate: f32,
+    ) {
+        let (rho, epsilon) = (self.rho, self.epsilon);
+        let ms = slot_state(&mut self.mean_square, slot, param);
+        for (r, grad) in rows {
+            for ((p, g), s) in row(param, r).iter_mut().zip(grad).zip(row(ms, r)) {
+                *s = rho * *s + (1.0 - rho) * g * g;
+                *p += learning_rate * g / (s.sqrt() + epsilon);
+            }
+        }
+    }
+}
+
+impl Optimizer for RmsProp {
+    fn update(&mut self, slot: usize, param: &mut Matrix, grad: &Matrix, learning_rate: f32) {
+        self.apply(slot, param, dense_rows(grad), learning_rate);
+    }
+
+    fn update_rows(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        grad: &RowGradient,
+        learning_rate: f32,
+    ) {
+        self.apply(slot, param, sparse_rows(grad), learning_rate);
+    }
+}
+
+pub struct Adam {
+    pub beta1: f32,
+    pub beta2: f32,
+    pub epsilon: f32,
+    step: i32,
+    first_moment: Vec<Matrix>,
+    second_moment: Vec<Matrix>,
+}
+
+impl Adam {
+    pub fn new(beta1: f32, beta2: f32, epsilon: f32) -> Self {
+        Adam {
+            beta1,
+            beta2,
+            epsilon,
+            step: 0,
+            first_moment: Vec::new(),
+            second_moment: Vec::new(),
+        }
+    }
+}
+
+impl Default for Adam {
+    fn default() -> Self {
+        Self::new(0.9, 0.999, 1e-8)
+    }
+}
+
+impl Adam {
+    // Decoupled `weight_decay` shrinks each visited row first, for `AdamW`.
+    fn apply<'g>(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        rows: impl Iterator<Item = (usize, &'g [f32])>,
+        weight_decay: f32,
+        learning_rate: f32,
+    ) {
+        let (beta1, beta2, epsilon) = (self.beta1, self.beta2, self.epsilon);
+        let step = self.step.max(1);
+        let bias_correction1 = 1.0 - beta1.powi(step);
+        let bias_correction2 = 1.0 - beta2.powi(step);
+        let decay = 1.0 - learning_rate * weight_decay;
+
+        let m = slot_state(&mut self.first_moment, slot, param);
+        let v = slot_state(&mut self.second_moment, slot, param);
+
+        for (r, grad) in rows {
+            let param = row(param, r);
+            if decay != 1.0 {
+                for p in param.iter_mut() {
+                    *p *= decay;
+                }
+            }
+            for (((p, g), m), v) in param.iter_mut().zip(grad).zip(row(m, r)).zip(row(v, r)) {
+                *m = beta1 * *m + (1.0 - beta1) * g;
+                *v = beta2 * *v + (1.0 - beta2) * g * g;
+                let m_hat = *m / bias_correction1;
+                let v_hat = *v / bias_correction2;
+                *p += learning_rate * m_hat / (v_hat.sqrt() + epsilon);
+            }
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn begin_step(&mut self) {
+        self.step += 1;
+    }
+
+    fn update(&mut self, slot: usize, param: &mut Matrix, grad: &Matrix, learning_rate: f32) {
+        self.apply(slot, param, dense_rows(grad), 0.0, learning_rate);
+    }
+
+    fn update_rows(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        grad: &RowGradient,
+        learning_rate: f32,
+    ) {
+        self.apply(slot, param, sparse_rows(grad), 0.0, learning_rate);
+    }
+}
+
+// Adam with decoupled weight decay: parameters shrink towards zero by
+// `learning_rate * weight_decay` each step, independently of the moments.
+pub struct AdamW {
+    pub weight_decay: f32,
+    adam: Adam,
+}
+
+impl AdamW {
+    pub fn new(weight_decay: f32) -> Self {
+        AdamW {
+            weight_decay,
+            adam: Adam::default(),
+        }
+    }
+
+    pub fn with_betas(beta1: f32, beta2: f32, epsilon: f32, weight_decay: f32) -> Self {
+        AdamW {
+            weight_decay,
+            adam: Adam::new(beta1, beta2, epsilon),
+        }
+    }
+}
+
+impl Default for AdamW {
+    fn default() -> Self {
+        Self::new(0.01)
+    }
+}
+
+impl Optimizer for AdamW {
+    fn begin_step(&mut self) {
+        self.adam.begin_step();
+    }
+
+    fn update(&mut self, slot: usize, param: &mut Matrix, grad: &Matrix, learning_rate: f32) {
+        let decay = self.weight_decay;
+        self.adam
+            .apply(slot, param, dense_rows(grad), decay, learning_rate);
+    }
+
+    fn update_rows(
+        &mut self,
+        slot: usize,
+        param: &mut Matrix,
+        grad: &RowGradient,
+        learning_rate: f32,
+    ) {
+        let decay = self.weight_decay;
+        self.adam
+            .apply(slot, param, sparse_rows(grad), decay, learning_rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(data: &[f32]) -> Matrix {
+        Matrix {
+            rows: 1,
+            cols: data.len(),
+            data: data.to_vec(),
+        }
+    }
+
+    // Runs one step per gradient on a single slot and returns the parameter.
+    fn run(optimizer: &mut dyn Optimizer, start: f32, grads: &[f32], learning_rate: f32) -> f32 {
+        let mut param = row(&[start]);
+        for &g in grads {
+            optimizer.begin_step();
+            optimizer.update(0, &mut param, &row(&[g]), learning_rate);
+        }
+        param.data[0]
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn sgd_adds_the_scaled_direction() {
+        let mut param = row(&[0.0, 1.0]);
+        Sgd::new().update(0, &mut param, &row(&[1.0, -2.0]), 0.1);
+        assert_near(param.data[0], 0.1);
+        assert_near(param.data[1], 0.8);
+    }
+
+    #[test]
+    fn sgd_weight_decay_shrinks_before_the_step() {
+        let mut sgd = Sgd::new().with_weight_decay(0.1);
+        assert_near(run(&mut sgd, 2.0, &[1.0], 0.5), 2.0 * 0.95 + 0.5);
+    }
+
+    #[test]
+    fn momentum_accumulates_velocity() {
+        // v = 1 then 1.9.
+        assert_near(run(&mut Sgd::momentum(0.9), 0.0, &[1.0, 1.0], 0.1), 0.29);
+        // Steps along g + 0.9 v: 1.9 then 2.71.
+        assert_near(run(&mut Sgd::nesterov(0.9), 0.0, &[1.0, 1.0], 0.1), 0.461);
+    }
+
+    #[test]
+    fn adagrad_divides_by_the_accumulated_norm() {
+        let expected = 0.1 + 0.2 / 8.0f32.sqrt();
+        assert_near(run(&mut Adagrad::new(0.0), 0.0, &[2.0, 2.0], 0.1), expected);
+    }
+
+    #[test]
+    fn rmsprop_divides_by_the_running_mean_square() {
+        assert_near(run(&mut RmsProp::new(0.9, 0.0), 0.0, &[1.0], 0.1), 0.1 / 0.1f32.sqrt());
+    }
+
+    #[test]
+    fn adam_corrects_the_moment_bias() {
+        // The first step moves by the learning rate whatever the gradient's size.
+        assert_near(run(&mut Adam::new(0.9, 0.999, 0.0), 0.0, &[5.0], 0.1), 0.1);
+        // m = -0.01 and v = 0.001999 after a flip, so m_hat / sqrt(v_hat) = -0.01 / 0.19.
+        let expected = 0.1 - 0.1 * 0.01 / 0.19;
+        let actual = run(&mut Adam::new(0.9, 0.999, 0.0), 0.0, &[1.0, -1.0], 0.1);
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn adamw_decays_before_the_adam_step() {
+        let mut adamw = AdamW::with_betas(0.9, 0.999, 0.0, 0.1);
+        assert_near(run(&mut adamw, 1.0, &[1.0], 0.1), 0.99 + 0.1);
+    }
+
+    #[test]
+    fn slots_keep_their_own_state() {
+        let mut sgd = Sgd::momentum(0.5);
+        let (mut a, mut b) = (row(&[0.0]), row(&[0.0]));
+        sgd.update(0, &mut a, &row(&[1.0]), 1.0);
+        sgd.update(1, &mut b, &row(&[-1.0]), 1.0);
+        sgd.update(0, &mut a, &row(&[1.0]), 1.0);
+        assert_near(a.data[0], 2.5);
+        assert_near(b.data[0], -1.0);
+    }
+
+    #[test]
+    fn row_updates_only_touch_their_rows() {
+        let mut sparse = RowGradient::new(2);
+        sparse.add_row(1, &[0.5, -1.0]);
+        let optimizers: Vec<Box<dyn Fn() -> Box<dyn Optimizer>>> = vec![
+            Box::new(|| Box::new(Sgd::momentum(0.9))),
+            Box::new(|| Box::new(Adagrad::default())),
+            Box::new(|| Box::new(RmsProp::default())),
+            Box::new(|| Box::new(Adam::default())),
+        ];
+        for make in optimizers {
+            let start = Matrix {
+                rows: 3,
+                cols: 2,
+                data: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            };
+            let (mut rows, mut dense) = (start.clone(), start.clone());
+            let (mut by_rows, mut by_dense) = (make(), make());
+            for _ in 0..2 {
+                by_rows.begin_step();
+                by_rows.update_rows(0, &mut rows, &sparse, 0.1);
+                by_dense.begin_step();
+                by_dense.update(0, &mut dense, &sparse.to_dense(3), 0.1);
+            }
+            // The touched row moves exactly like under a dense update.
+            assert_eq!(rows.data[2..4], dense.data[2..4]);
+            assert_eq!(rows.data[..2], start.data[..2]);
+            assert_eq!(rows.data[4..], start.data[4..]);
+        }
+    }
+}
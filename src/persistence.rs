@@ -0,0 +1,560 @@
+use std::io::{self, Read, Write};
+
+use crate::activation::Activation;
+use crate::error::RustingBrainError;
+use crate::json::JsonValue;
+use crate::loss::Loss;
+use crate::matrix::Matrix;
+use crate::normalization::{NormKind, Normalization};
+
+// Binary model layout, all little-endian:
+//
+//   magic "RBNN" | version u32
+//   layer count u32 | layer sizes u32...
+//   per layer: activation tag u8, activation parameter f32
+//   loss tag u8, loss parameter f32 | learning rate f32
+//   per layer: weights matrix, biases matrix
+//   per layer (since version 2): normalization tag u8, then
+//     batch: epsilon f32 | momentum f32 | gamma | beta | running mean | running var
+//     layer: epsilon f32 | gamma | beta
+//
+// where a matrix is rows u32 | cols u32 | rows * cols f32 values. Version 1
+// files, which have no normalization section, still load.
+pub const MAGIC: [u8; 4] = *b"RBNN";
+pub const VERSION: u32 = 2;
+
+pub(crate) fn write_u8<W: Write>(w: &mut W, v: u8) -> io::Result<()> {
+    w.write_all(&[v])
+}
+
+pub(crate) fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn write_f32<W: Write>(w: &mut W, v: f32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn write_matrix<W: Write>(w: &mut W, m: &Matrix) -> io::Result<()> {
+    write_u32(w, m.rows as u32)?;
+    write_u32(w, m.cols as u32)?;
+    let mut bytes = Vec::with_capacity(m.data.len() * 4);
+    for v in &m.data {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    w.write_all(&bytes)
+}
+
+pub(crate) fn write_activation<W: Write>(w: &mut W, activation: Activation) -> io::Result<()> {
+    let (tag, param) = match activation {
+        Activation::Identity => (0, 0.0),
+        Activation::Sigmoid => (1, 0.0),
+        Activation::Tanh => (2, 0.0),
+        Activation::Relu => (3, 0.0),
+        Activation::LeakyRelu(alpha) => (4, alpha),
+        Activation::Elu(alpha) => (5, alpha),
+        Activation::Gelu => (6, 0.0),
+        Activation::Silu => (7, 0.0),
+        Activation::Softplus => (8, 0.0),
+        Activation::Softmax => (9, 0.0),
+        Activation::Selu => (10, 0.0),
+    };
+    write_u8(w, tag)?;
+    write_f32(w, param)
+}
+
+pub(crate) fn write_loss<W: Write>(w: &mut W, loss: Loss) -> io::Result<()> {
+    let (tag, param) = match loss {
+        Loss::MeanSquaredError => (0, 0.0),
+        Loss::MeanAbsoluteError => (1, 0.0),
+        Loss::Huber(delta) => (2, delta),
+        Loss::BinaryCrossEntropy => (3, 0.0),
+        Loss::CategoricalCrossEntropy => (4, 0.0),
+        Loss::Hinge => (5, 0.0),
+        Loss::KlDivergence => (6, 0.0),
+    };
+    write_u8(w, tag)?;
+    write_f32(w, param)
+}
+
+pub(crate) fn read_u8<R: Read>(r: &mut R) -> Result<u8, RustingBrainError> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> Result<u32, RustingBrainError> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_f32<R: Read>(r: &mut R) -> Result<f32, RustingBrainError> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_header<R: Read>(r: &mut R) -> Result<u32, RustingBrainError> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(RustingBrainError::BadMagic);
+    }
+
+    let version = read_u32(r)?;
+    if version == 0 || version > VERSION {
+        return Err(RustingBrainError::UnsupportedVersion(version));
+    }
+    Ok(version)
+}
+
+// Files written by `Sequential::save` and `Graph::save`, whose architecture
+// lives in code rather than in the file:
+//
+//   magic "RBPM" | version u32 | tensor count u32 | matrices...
+//
+// holding every trainable tensor in layer order, then every state tensor
+// such as batch normalization's running statistics, like `Model::snapshot`.
+pub const PARAMETERS_MAGIC: [u8; 4] = *b"RBPM";
+
+pub(crate) fn write_tensors<W: Write>(w: &mut W, tensors: &[&Matrix]) -> io::Result<()> {
+    w.write_all(&PARAMETERS_MAGIC)?;
+    write_u32(w, VERSION)?;
+    write_u32(w, tensors.len() as u32)?;
+    for m in tensors {
+        write_matrix(w, m)?;
+    }
+    Ok(())
+}
+
+// Reads tensors shaped like `expected`.
+pub(crate) fn read_tensors<R: Read>(
+    r: &mut R,
+    expected: &[&Matrix],
+) -> Result<Vec<Matrix>, RustingBrainError> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if magic != PARAMETERS_MAGIC {
+        return Err(RustingBrainError::BadMagic);
+    }
+    let version = read_u32(r)?;
+    if version == 0 || version > VERSION {
+        return Err(RustingBrainError::UnsupportedVersion(version));
+    }
+
+    let count = read_u32(r)? as usize;
+    if count != expected.len() {
+        return Err(RustingBrainError::InvalidArchitecture(format!(
+            "file holds {} tensors, the model has {}",
+            count,
+            expected.len()
+        )));
+    }
+    expected
+        .iter()
+        .map(|m| read_matrix(r, m.rows, m.cols))
+        .collect()
+}
+
+pub(crate) fn read_matrix<R: Read>(
+    r: &mut R,
+    rows: usize,
+    cols: usize,
+) -> Result<Matrix, RustingBrainError> {
+    let found = (read_u32(r)? as usize, read_u32(r)? as usize);
+    if found != (rows, cols) {
+        return Err(RustingBrainError::ShapeMismatch {
+            expected: (rows, cols),
+            found,
+        });
+    }
+
+    // The shape comes from the file, so nothing is allocated up front: the
+    // bytes are read as they arrive and a short stream ends as `Truncated`.
+    let len = rows
+        .checked_mul(cols)
+        .and_then(|n| n.checked_mul(4))
+        .ok_or_else(|| {
+            RustingBrainError::InvalidArchitecture(format!(
+                "a {}x{} matrix does not fit in memory",
+                rows, cols
+            ))
+        })?;
+    let mut bytes = Vec::new();
+    r.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(RustingBrainError::Truncated);
+    }
+
+    let mut m = Matrix::new(rows, cols);
+    for (v, chunk) in m.data.iter_mut().zip(bytes.chunks_exact(4)) {
+        *v = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    Ok(m)
+}
+
+pub(crate) fn read_activation<R: Read>(r: &mut R) -> Result<Activation, RustingBrainError> {
+    let tag = read_u8(r)?;
+    let param = read_f32(r)?;
+    Ok(match tag {
+        0 => Activation::Identity,
+        1 => Activation::Sigmoid,
+        2 => Activation::Tanh,
+        3 => Activation::Relu,
+        4 => Activation::LeakyRelu(param),
+        5 => Activation::Elu(param),
+        6 => Activation::Gelu,
+        7 => Activation::Silu,
+        8 => Activation::Softplus,
+        9 => Activation::Softmax,
+        10 => Activation::Selu,
+        _ => return Err(RustingBrainError::UnknownActivation(tag)),
+    })
+}
+
+pub(crate) fn read_loss<R: Read>(r: &mut R) -> Result<Loss, RustingBrainError> {
+    let tag = read_u8(r)?;
+    let param = read_f32(r)?;
+    Ok(match tag {
+        0 => Loss::MeanSquaredError,
+        1 => Loss::MeanAbsoluteError,
+        2 => Loss::Huber(param),
+        3 => Loss::BinaryCrossEntropy,
+        4 => Loss::CategoricalCrossEntropy,
+        5 => Loss::Hinge,
+        6 => Loss::KlDivergence,
+        _ => return Err(RustingBrainError::UnknownLoss(tag)),
+    })
+}
+
+pub(crate) fn write_normalization<W: Write>(
+    w: &mut W,
+    normalization: Option<&Normalization>,
+) -> io::Result<()> {
+    let Some(norm) = normalization else {
+        return write_u8(w, 0);
+    };
+
+    match norm.kind {
+        NormKind::Batch { momentum } => {
+            write_u8(w, 1)?;
+            write_f32(w, norm.epsilon)?;
+            write_f32(w, momentum)?;
+            write_matrix(w, &norm.gamma)?;
+            write_matrix(w, &norm.beta)?;
+            write_matrix(w, &norm.running_mean)?;
+            write_matrix(w, &norm.running_var)
+        }
+        NormKind::Layer => {
+            write_u8(w, 2)?;
+            write_f32(w, norm.epsilon)?;
+            write_matrix(w, &norm.gamma)?;
+            write_matrix(w, &norm.beta)
+        }
+    }
+}
+
+pub(crate) fn read_normalization<R: Read>(
+    r: &mut R,
+    features: usize,
+) -> Result<Option<Normalization>, RustingBrainError> {
+    let tag = read_u8(r)?;
+    let mut norm = match tag {
+        0 => return Ok(None),
+        1 => {
+            let epsilon = read_f32(r)?;
+            let momentum = read_f32(r)?;
+            Normalization::batch(features)
+                .with_epsilon(epsilon)
+                .with_momentum(momentum)
+        }
+        2 => Normalization::layer(features).with_epsilon(read_f32(r)?),
+        _ => return Err(RustingBrainError::UnknownNormalization(tag)),
+    };
+
+    norm.gamma = read_matrix(r, features, 1)?;
+    norm.beta = read_matrix(r, features, 1)?;
+    if norm.is_batch() {
+        norm.running_mean = read_matrix(r, features, 1)?;
+        norm.running_var = read_matrix(r, features, 1)?;
+    }
+    Ok(Some(norm))
+}
+
+// JSON layout mirrors the binary one, with activations and losses spelled out
+// by name so other tooling does not need to know the numeric tags:
+//
+//   { "format": "rustingbrain", "version": 2, "layers": [...],
+//     "activations": [{"name": "leaky_relu", "alpha": 0.01}, ...],
+//     "loss": {"name": "huber", "delta": 1.0}, "learning_rate": 0.01,
+//     "weights": [{"rows": r, "cols": c, "data": [...]}, ...],
+//     "biases": [...],
+//     "normalization": [null, {"name": "batch", "epsilon": 0.001,
+//       "momentum": 0.99, "gamma": {...}, "beta": {...},
+//       "running_mean": {...}, "running_var": {...}}, ...] }
+//
+// "normalization" (one entry per non-input layer) is new in version 2 and
+// may be left out. Non-finite values are stored as the strings "NaN",
+// "Infinity" and "-Infinity" so the file stays valid JSON.
+pub const JSON_FORMAT: &str = "rustingbrain";
+
+fn json_error(msg: &str) -> RustingBrainError {
+    RustingBrainError::InvalidJson(msg.to_string())
+}
+
+fn named(name: &str, params: Vec<(&str, f32)>) -> JsonValue {
+    let mut fields = vec![("name".to_string(), JsonValue::String(name.to_string()))];
+    for (key, value) in params {
+        fields.push((key.to_string(), JsonValue::from_f32(value)));
+    }
+    JsonValue::Object(fields)
+}
+
+pub(crate) fn json_field<'a>(
+    value: &'a JsonValue,
+    key: &str,
+) -> Result<&'a JsonValue, RustingBrainError> {
+    value
+        .get(key)
+        .ok_or_else(|| RustingBrainError::InvalidJson(format!("missing field '{}'", key)))
+}
+
+pub(crate) fn json_f32(value: &JsonValue, key: &str) -> Result<f32, RustingBrainError> {
+    json_field(value, key)?
+        .as_f32()
+        .ok_or_else(|| RustingBrainError::InvalidJson(format!("field '{}' is not a number", key)))
+}
+
+pub(crate) fn json_usize(value: &JsonValue, key: &str) -> Result<usize, RustingBrainError> {
+    json_field(value, key)?
+        .as_usize()
+        .ok_or_else(|| RustingBrainError::InvalidJson(format!("field '{}' is not an integer", key)))
+}
+
+pub(crate) fn json_array<'a>(
+    value: &'a JsonValue,
+    key: &str,
+) -> Result<&'a [JsonValue], RustingBrainError> {
+    json_field(value, key)?
+        .as_array()
+        .ok_or_else(|| RustingBrainError::InvalidJson(format!("field '{}' is not an array", key)))
+}
+
+fn json_name(value: &JsonValue) -> Result<&str, RustingBrainError> {
+    json_field(value, "name")?
+        .as_str()
+        .ok_or_else(|| json_error("field 'name' is not a string"))
+}
+
+pub(crate) fn activation_to_json(activation: Activation) -> JsonValue {
+    match activation {
+        Activation::Identity => named("identity", vec![]),
+        Activation::Sigmoid => named("sigmoid", vec![]),
+        Activation::Tanh => named("tanh", vec![]),
+        Activation::Relu => named("relu", vec![]),
+        Activation::LeakyRelu(alpha) => named("leaky_relu", vec![("alpha", alpha)]),
+        Activation::Elu(alpha) => named("elu", vec![("alpha", alpha)]),
+        Activation::Gelu => named("gelu", vec![]),
+        Activation::Silu => named("silu", vec![]),
+        Activation::Softplus => named("softplus", vec![]),
+        Activation::Selu => named("selu", vec![]),
+        Activation::Softmax => named("softmax", vec![]),
+    }
+}
+
+pub(crate) fn activation_from_json(value: &JsonValue) -> Result<Activation, RustingBrainError> {
+    Ok(match json_name(value)? {
+        "identity" | "linear" => Activation::Identity,
+        "sigmoid" => Activation::Sigmoid,
+        "tanh" => Activation::Tanh,
+        "relu" => Activation::Relu,
+        "leaky_relu" => Activation::LeakyRelu(json_f32(value, "alpha")?),
+        "elu" => Activation::Elu(json_f32(value, "alpha")?),
+        "gelu" => Activation::Gelu,
+        "silu" | "swish" => Activation::Silu,
+        "softplus" => Activation::Softplus,
+        "selu" => Activation::Selu,
+        "softmax" => Activation::Softmax,
+        other => {
+            return Err(RustingBrainError::InvalidJson(format!(
+                "unknown activation '{}'",
+                other
+            )));
+        }
+    })
+}
+
+pub(crate) fn loss_to_json(loss: Loss) -> JsonValue {
+    match loss {
+        Loss::MeanSquaredError => named("mean_squared_error", vec![]),
+        Loss::MeanAbsoluteError => named("mean_absolute_error", vec![]),
+        Loss::Huber(delta) => named("huber", vec![("delta", delta)]),
+        Loss::BinaryCrossEntropy => named("binary_crossentropy", vec![]),
+        Loss::CategoricalCrossEntropy => named("categorical_crossentropy", vec![]),
+        Loss::Hinge => named("hinge", vec![]),
+        Loss::KlDivergence => named("kl_divergence", vec![]),
+    }
+}
+
+pub(crate) fn loss_from_json(value: &JsonValue) -> Result<Loss, RustingBrainError> {
+    Ok(match json_name(value)? {
+        "mean_squared_error" | "mse" => Loss::MeanSquaredError,
+        "mean_absolute_error" | "mae" => Loss::MeanAbsoluteError,
+        "huber" => Loss::Huber(json_f32(value, "delta")?),
+        "binary_crossentropy" => Loss::BinaryCrossEntropy,
+        "categorical_crossentropy" => Loss::CategoricalCrossEntropy,
+        "hinge" => Loss::Hinge,
+        "kl_divergence" => Loss::KlDivergence,
+        other => {
+            return Err(RustingBrainError::InvalidJson(format!(
+                "unknown loss '{}'",
+                other
+            )));
+        }
+    })
+}
+
+// JSON counterpart of `write_tensors`:
+//
+//   { "format": "rustingbrain-parameters", "version": 2,
+//     "tensors": [{"rows": r, "cols": c, "data": [...]}, ...] }
+pub const PARAMETERS_JSON_FORMAT: &str = "rustingbrain-parameters";
+
+pub(crate) fn tensors_to_json(tensors: &[&Matrix]) -> String {
+    JsonValue::Object(vec![
+        (
+            "format".to_string(),
+            JsonValue::String(PARAMETERS_JSON_FORMAT.to_string()),
+        ),
+        ("version".to_string(), JsonValue::from_usize(VERSION as usize)),
+        (
+            "tensors".to_string(),
+            JsonValue::Array(tensors.iter().map(|m| matrix_to_json(m)).collect()),
+        ),
+    ])
+    .to_pretty_string()
+}
+
+pub(crate) fn tensors_from_json(
+    text: &str,
+    expected: &[&Matrix],
+) -> Result<Vec<Matrix>, RustingBrainError> {
+    let root = JsonValue::parse(text).map_err(RustingBrainError::InvalidJson)?;
+    if json_field(&root, "format")?.as_str() != Some(PARAMETERS_JSON_FORMAT) {
+        return Err(RustingBrainError::BadMagic);
+    }
+    let version = json_usize(&root, "version")? as u32;
+    if version == 0 || version > VERSION {
+        return Err(RustingBrainError::UnsupportedVersion(version));
+    }
+
+    let values = json_array(&root, "tensors")?;
+    if values.len() != expected.len() {
+        return Err(RustingBrainError::InvalidArchitecture(format!(
+            "file holds {} tensors, the model has {}",
+            values.len(),
+            expected.len()
+        )));
+    }
+    values
+        .iter()
+        .zip(expected)
+        .map(|(value, m)| matrix_from_json(value, m.rows, m.cols))
+        .collect()
+}
+
+pub(crate) fn matrix_to_json(m: &Matrix) -> JsonValue {
+    JsonValue::Object(vec![
+        ("rows".to_string(), JsonValue::from_usize(m.rows)),
+        ("cols".to_string(), JsonValue::from_usize(m.cols)),
+        (
+            "data".to_string(),
+            JsonValue::Array(m.data.iter().map(|&v| JsonValue::from_f32(v)).collect()),
+        ),
+    ])
+}
+
+pub(crate) fn matrix_from_json(
+    value: &JsonValue,
+    rows: usize,
+    cols: usize,
+) -> Result<Matrix, RustingBrainError> {
+    let found = (json_usize(value, "rows")?, json_usize(value, "cols")?);
+    if found != (rows, cols) {
+        return Err(RustingBrainError::ShapeMismatch {
+            expected: (rows, cols),
+            found,
+        });
+    }
+
+    let data = json_array(value, "data")?;
+    if data.len() != rows * cols {
+        return Err(RustingBrainError::InvalidJson(format!(
+            "matrix data has {} values, expected {}",
+            data.len(),
+            rows * cols
+        )));
+    }
+
+    let mut m = Matrix::new(rows, cols);
+    for (v, item) in m.data.iter_mut().zip(data) {
+        *v = item
+            .as_f32()
+            .ok_or_else(|| json_error("matrix data must be numbers"))?;
+    }
+    Ok(m)
+}
+
+pub(crate) fn normalization_to_json(normalization: Option<&Normalization>) -> JsonValue {
+    let Some(norm) = normalization else {
+        return JsonValue::Null;
+    };
+
+    let (name, mut params) = match norm.kind {
+        NormKind::Batch { momentum } => ("batch", vec![("momentum", momentum)]),
+        NormKind::Layer => ("layer", vec![]),
+    };
+    params.insert(0, ("epsilon", norm.epsilon));
+
+    let mut fields = vec![("name".to_string(), JsonValue::String(name.to_string()))];
+    for (key, value) in params {
+        fields.push((key.to_string(), JsonValue::from_f32(value)));
+    }
+    fields.push(("gamma".to_string(), matrix_to_json(&norm.gamma)));
+    fields.push(("beta".to_string(), matrix_to_json(&norm.beta)));
+    if norm.is_batch() {
+        fields.push(("running_mean".to_string(), matrix_to_json(&norm.running_mean)));
+        fields.push(("running_var".to_string(), matrix_to_json(&norm.running_var)));
+    }
+    JsonValue::Object(fields)
+}
+
+pub(crate) fn normalization_from_json(
+    value: &JsonValue,
+    features: usize,
+) -> Result<Option<Normalization>, RustingBrainError> {
+    if *value == JsonValue::Null {
+        return Ok(None);
+    }
+
+    let mut norm = match json_name(value)? {
+        "batch" => Normalization::batch(features).with_momentum(json_f32(value, "momentum")?),
+        "layer" => Normalization::layer(features),
+        other => {
+            return Err(RustingBrainError::InvalidJson(format!(
+                "unknown normalization '{}'",
+                other
+            )));
+        }
+    }
+    .with_epsilon(json_f32(value, "epsilon")?);
+
+    norm.gamma = matrix_from_json(json_field(value, "gamma")?, features, 1)?;
+    norm.beta = matrix_from_json(json_field(value, "beta")?, features, 1)?;
+    if norm.is_batch() {
+        norm.running_mean = matrix_from_json(json_field(value, "running_mean")?, features, 1)?;
+        norm.running_var = matrix_from_json(json_field(value, "running_var")?, features, 1)?;
+    }
+    Ok(Some(norm))
+}
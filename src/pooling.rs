@@ -0,0 +1,424 @@
+use rand::rngs::StdRng;
+
+use crate::conv::{Geometry, Padding, image_shape, sequence_shape};
+use crate::error::{Result, RustingBrainError};
+use crate::layer::{Cache, Layer, Pass};
+use crate::matrix::Matrix;
+
+// Window settings shared by the 2D pooling layers. The stride defaults to
+// the pool size, so windows do not overlap.
+struct Pool2D {
+    pool: (usize, usize),
+    stride: Option<(usize, usize)>,
+    padding: Padding,
+    channels: usize,
+    geometry: Geometry,
+    output_shape: Vec<usize>,
+}
+
+impl Pool2D {
+    fn new(pool: (usize, usize)) -> Self {
+        Pool2D {
+            pool,
+            stride: None,
+            padding: Padding::Valid,
+            channels: 0,
+            geometry: Geometry::default(),
+            output_shape: Vec::new(),
+        }
+    }
+
+    fn build(&mut self, name: &str, input_shape: &[usize]) -> Result<Vec<usize>> {
+        let (channels, h, w) = image_shape(name, input_shape)?;
+        let stride = self.stride.unwrap_or(self.pool);
+        self.channels = channels;
+        self.geometry = Geometry::new((h, w), self.pool, stride, (1, 1), self.padding)?;
+        self.output_shape = vec![channels, self.geometry.out_h, self.geometry.out_w];
+        Ok(self.output_shape.clone())
+    }
+
+    // Calls `f(output_row, input_rows)` for every output unit with the
+    // input rows its window covers, padding left out.
+    fn for_each_window(&self, mut f: impl FnMut(usize, &[usize])) {
+        let g = &self.geometry;
+        let plane = g.in_h * g.in_w;
+        let mut window = Vec::with_capacity(g.window_size());
+        for c in 0..self.channels {
+            for oy in 0..g.out_h {
+                for ox in 0..g.out_w {
+                    window.clear();
+                    for i in 0..g.kernel.0 {
+                        for j in 0..g.kernel.1 {
+                            if let Some(src) = g.source(oy, ox, i, j) {
+                                window.push(c * plane + src);
+                            }
+                        }
+                    }
+                    let out = (c * g.out_h + oy) * g.out_w + ox;
+                    f(out, &window);
+                }
+            }
+        }
+    }
+
+    fn input_rows(&self) -> usize {
+        self.channels * self.geometry.in_h * self.geometry.in_w
+    }
+
+    fn output_rows(&self) -> usize {
+        self.channels * self.geometry.output_positions()
+    }
+}
+
+// Largest value of every window, per channel.
+pub struct MaxPool2D {
+    pool: Pool2D,
+}
+
+impl MaxPool2D {
+    pub fn new(pool: (usize, usize)) -> Self {
+        MaxPool2D {
+            pool: Pool2D::new(pool),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: (usize, usize)) -> Self {
+        self.pool.stride = Some(stride);
+        self
+    }
+
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.pool.padding = padding;
+        self
+    }
+}
+
+impl Layer for MaxPool2D {
+    fn name(&self) -> &'static str {
+        "max_pool2d"
+    }
+
+    fn build(&mut self, input_shape: &[usize], _rng: &mut StdRng) -> Result<Vec<usize>> {
+        self.pool.build(self.name(), input_shape)
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.pool.output_shape
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let rows = self.pool.output_rows();
+        let (out, argmax) = max_pool(input, rows, |f| self.pool.for_each_window(f));
+        cache.push_indices(argmax);
+        out
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        max_unpool(error, cache.indices(0), self.pool.input_rows())
+    }
+}
+
+// Mean of every window, per channel. Padding is not counted.
+pub struct AvgPool2D {
+    pool: Pool2D,
+}
+
+impl AvgPool2D {
+    pub fn new(pool: (usize, usize)) -> Self {
+        AvgPool2D {
+            pool: Pool2D::new(pool),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: (usize, usize)) -> Self {
+        self.pool.stride = Some(stride);
+        self
+    }
+
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.pool.padding = padding;
+        self
+    }
+}
+
+impl Layer for AvgPool2D {
+    fn name(&self) -> &'static str {
+        "avg_pool2d"
+    }
+
+    fn build(&mut self, input_shape: &[usize], _rng: &mut StdRng) -> Result<Vec<usize>> {
+        self.pool.build(self.name(), input_shape)
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.pool.output_shape
+    }
+
+    fn forward(&self, input: &Matrix, _cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let n = input.cols;
+        let mut out = Matrix::new(self.pool.output_rows(), n);
+        self.pool.for_each_window(|o, window| {
+            let scale = 1.0 / window.len() as f32;
+            let dst = &mut out.data[o * n..(o + 1) * n];
+            for &src in window {
+                for (d, s) in dst.iter_mut().zip(&input.data[src * n..(src + 1) * n]) {
+                    *d += s * scale;
+                }
+            }
+        });
+        out
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        _cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        let n = error.cols;
+        let mut input_error = Matrix::new(self.pool.input_rows(), n);
+        self.pool.for_each_window(|o, window| {
+            let scale = 1.0 / window.len() as f32;
+            let e = &error.data[o * n..(o + 1) * n];
+            for &src in window {
+                for (d, s) in input_error.data[src * n..(src + 1) * n].iter_mut().zip(e) {
+                    *d += s * scale;
+                }
+            }
+        });
+        input_error
+    }
+}
+
+// Largest value of every window along a `[channels, length]` sequence,
+// pooled as a one-row image.
+pub struct MaxPool1D {
+    pool: MaxPool2D,
+    output_shape: Vec<usize>,
+}
+
+impl MaxPool1D {
+    pub fn new(pool: usize) -> Self {
+        MaxPool1D {
+            pool: MaxPool2D::new((1, pool)),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Self {
+        self.pool = self.pool.with_stride((1, stride));
+        self
+    }
+
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.pool = self.pool.with_padding(padding);
+        self
+    }
+}
+
+impl Layer for MaxPool1D {
+    fn name(&self) -> &'static str {
+        "max_pool1d"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let (channels, length) = sequence_shape(self.name(), input_shape)?;
+        let out = self.pool.build(&[channels, 1, length], rng)?;
+        self.output_shape = vec![out[0], out[2]];
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, pass: &mut Pass) -> Matrix {
+        self.pool.forward(input, cache, pass)
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        self.pool.backward(error, cache, gradients, pass)
+    }
+}
+
+// Max pooling given a window walker; returns the output and, per output
+// value, the input row it came from.
+pub(crate) fn max_pool(
+    input: &Matrix,
+    rows: usize,
+    walk: impl FnOnce(&mut dyn FnMut(usize, &[usize])),
+) -> (Matrix, Vec<usize>) {
+    let n = input.cols;
+    let mut out = Matrix::new(rows, n);
+    let mut argmax = vec![0; rows * n];
+    walk(&mut |o, window| {
+        for col in 0..n {
+            let mut best = f32::NEG_INFINITY;
+            let mut best_row = 0;
+            for &src in window {
+                let v = input.data[src * n + col];
+                if v > best {
+                    best = v;
+                    best_row = src;
+                }
+            }
+            out.data[o * n + col] = best;
+            argmax[o * n + col] = best_row;
+        }
+    });
+    (out, argmax)
+}
+
+// Routes every output error back to the input its maximum came from.
+pub(crate) fn max_unpool(error: &Matrix, argmax: &[usize], input_rows: usize) -> Matrix {
+    let n = error.cols;
+    let mut input_error = Matrix::new(input_rows, n);
+    for (i, (&e, &src)) in error.data.iter().zip(argmax).enumerate() {
+        let col = i % n;
+        input_error.data[src * n + col] += e;
+    }
+    input_error
+}
+
+// Averages each channel over all remaining axes, so `[channels, ...]`
+// becomes `[channels]`.
+pub struct GlobalAveragePooling {
+    channels: usize,
+    size: usize,
+    output_shape: Vec<usize>,
+}
+
+impl GlobalAveragePooling {
+    pub fn new() -> Self {
+        GlobalAveragePooling {
+            channels: 0,
+            size: 0,
+            output_shape: Vec::new(),
+        }
+    }
+}
+
+impl Default for GlobalAveragePooling {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Layer for GlobalAveragePooling {
+    fn name(&self) -> &'static str {
+        "global_average_pooling"
+    }
+
+    fn build(&mut self, input_shape: &[usize], _rng: &mut StdRng) -> Result<Vec<usize>> {
+        let [channels, ref rest @ ..] = *input_shape else {
+            return Err(RustingBrainError::InvalidArchitecture(
+                "global average pooling needs a shaped input".to_string(),
+            ));
+        };
+        if rest.is_empty() {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "global average pooling expects [channels, ...], got shape {:?}",
+                input_shape
+            )));
+        }
+        self.channels = channels;
+        self.size = rest.iter().product();
+        self.output_shape = vec![channels];
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn forward(&self, input: &Matrix, _cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let n = input.cols;
+        let scale = 1.0 / self.size as f32;
+        let mut out = Matrix::new(self.channels, n);
+        for c in 0..self.channels {
+            let dst = &mut out.data[c * n..(c + 1) * n];
+            for r in c * self.size..(c + 1) * self.size {
+                for (d, s) in dst.iter_mut().zip(&input.data[r * n..(r + 1) * n]) {
+                    *d += s * scale;
+                }
+            }
+        }
+        out
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        _cache: &Cache,
+        _gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        let n = error.cols;
+        let scale = 1.0 / self.size as f32;
+        let mut input_error = Matrix::new(self.channels * self.size, n);
+        for c in 0..self.channels {
+            let e = &error.data[c * n..(c + 1) * n];
+            for r in c * self.size..(c + 1) * self.size {
+                for (d, s) in input_error.data[r * n..(r + 1) * n].iter_mut().zip(e) {
+                    *d = s * scale;
+                }
+            }
+        }
+        input_error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::check_layer;
+
+    #[test]
+    fn max_pool2d_gradients() {
+        check_layer(MaxPool2D::new((2, 2)), &[2, 4, 4], 2);
+        check_layer(
+            MaxPool2D::new((3, 3))
+                .with_stride((2, 2))
+                .with_padding(Padding::Same),
+            &[1, 5, 5],
+            2,
+        );
+    }
+
+    #[test]
+    fn avg_pool2d_gradients() {
+        check_layer(AvgPool2D::new((2, 2)), &[2, 4, 4], 2);
+        check_layer(
+            AvgPool2D::new((3, 3))
+                .with_stride((2, 2))
+                .with_padding(Padding::Same),
+            &[1, 5, 5],
+            2,
+        );
+    }
+
+    #[test]
+    fn max_pool1d_gradients() {
+        check_layer(MaxPool1D::new(2), &[2, 6], 2);
+        check_layer(MaxPool1D::new(3).with_stride(2).with_padding(Padding::Same), &[1, 7], 2);
+    }
+
+    #[test]
+    fn global_average_pooling_gradients() {
+        check_layer(GlobalAveragePooling::new(), &[3, 2, 3], 2);
+        check_layer(GlobalAveragePooling::new(), &[2, 5], 2);
+    }
+}
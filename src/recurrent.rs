@@ -0,0 +1,556 @@
+use rand::rngs::StdRng;
+
+use crate::activation::Activation;
+use crate::conv::sequence_shape;
+use crate::error::{Result, RustingBrainError};
+use crate::initializer::Initializer;
+use crate::layer::{Cache, Layer, Pass, activation_backward, add_to};
+use crate::matrix::Matrix;
+use crate::regularizer::Regularizer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cell {
+    // Elman RNN: `h = activation(W x + U h_prev + b)`.
+    Simple(Activation),
+    // Input, forget, cell and output gates, in that order.
+    Lstm,
+    // Update, reset and candidate gates, with the reset gate applied after
+    // the recurrent product: `n = tanh(W_n x + b_n + r * (U_n h_prev))`.
+    Gru,
+}
+
+impl Cell {
+    fn gates(&self) -> usize {
+        match self {
+            Cell::Simple(_) => 1,
+            Cell::Lstm => 4,
+            Cell::Gru => 3,
+        }
+    }
+}
+
+// Matrices cached per time step: the hidden state, then the gates (or the
+// pre-activation for a simple cell), then the LSTM cell state or the GRU
+// recurrent product.
+const STEP_CACHE: usize = 3;
+
+// Recurrent layer over `[features, steps]` sequences, the layout `Conv1D`
+// uses. Outputs the last hidden state (`[units]`) or, with return
+// sequences, every one of them (`[units, steps]`). The input projections of
+// all steps are one sgemm; the recurrent ones run step by step.
+pub struct Recurrent {
+    cell: Cell,
+    units: usize,
+    return_sequences: bool,
+    truncation: Option<usize>,
+    weight_init: Initializer,
+    recurrent_init: Initializer,
+    regularizer: Option<Regularizer>,
+    features: usize,
+    steps: usize,
+    // Gate blocks of `units` rows each, stacked in the cell's gate order.
+    weights: Matrix,
+    recurrent: Matrix,
+    biases: Matrix,
+    output_shape: Vec<usize>,
+}
+
+impl Recurrent {
+    pub fn simple(units: usize) -> Self {
+        Self::new(Cell::Simple(Activation::Tanh), units)
+    }
+
+    pub fn lstm(units: usize) -> Self {
+        Self::new(Cell::Lstm, units)
+    }
+
+    pub fn gru(units: usize) -> Self {
+        Self::new(Cell::Gru, units)
+    }
+
+    pub fn new(cell: Cell, units: usize) -> Self {
+        Recurrent {
+            cell,
+            units,
+            return_sequences: false,
+            truncation: None,
+            weight_init: Initializer::XavierUniform,
+            recurrent_init: Initializer::Orthogonal(1.0),
+            regularizer: None,
+            features: 0,
+            steps: 0,
+            weights: Matrix::new(0, 0),
+            recurrent: Matrix::new(0, 0),
+            biases: Matrix::new(0, 0),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_return_sequences(mut self, return_sequences: bool) -> Self {
+        self.return_sequences = return_sequences;
+        self
+    }
+
+    // Truncated backpropagation through time: the sequence is cut into
+    // windows of `steps`, counted back from the last step, and gradients do
+    // not flow from one window into the previous one.
+    pub fn with_truncation(mut self, steps: usize) -> Self {
+        self.truncation = Some(steps.max(1));
+        self
+    }
+
+    // Initializers of the input and recurrent weights; biases start at zero
+    // except for the LSTM forget gate, which starts at one.
+    pub fn with_initializer(
+        mut self,
+        weight_init: Initializer,
+        recurrent_init: Initializer,
+    ) -> Self {
+        self.weight_init = weight_init;
+        self.recurrent_init = recurrent_init;
+        self
+    }
+
+    pub fn with_regularizer(mut self, regularizer: Regularizer) -> Self {
+        self.regularizer = Some(regularizer);
+        self
+    }
+
+    pub fn cell(&self) -> Cell {
+        self.cell
+    }
+
+    fn state<'c>(&self, cache: &'c Cache, t: usize, k: usize) -> &'c Matrix {
+        &cache[1 + STEP_CACHE * t + k]
+    }
+}
+
+// Columns `t * n .. (t + 1) * n` of `m`, i.e. time step `t` of a
+// `rows x (steps * batch)` sequence matrix.
+fn step_columns(m: &Matrix, t: usize, n: usize) -> Matrix {
+    let mut out = Matrix::new(m.rows, n);
+    for r in 0..m.rows {
+        let from = r * m.cols + t * n;
+        out.data[r * n..(r + 1) * n].copy_from_slice(&m.data[from..from + n]);
+    }
+    out
+}
+
+fn set_step_columns(m: &mut Matrix, t: usize, step: &Matrix) {
+    let n = step.cols;
+    for r in 0..m.rows {
+        let to = r * m.cols + t * n;
+        m.data[to..to + n].copy_from_slice(&step.data[r * n..(r + 1) * n]);
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    Activation::Sigmoid.activate(x)
+}
+
+impl Layer for Recurrent {
+    fn name(&self) -> &'static str {
+        match self.cell {
+            Cell::Simple(_) => "simple_rnn",
+            Cell::Lstm => "lstm",
+            Cell::Gru => "gru",
+        }
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let (features, steps) = sequence_shape(self.name(), input_shape)?;
+        if self.units == 0 {
+            return Err(RustingBrainError::InvalidArchitecture(format!(
+                "{} layer with zero units",
+                self.name()
+            )));
+        }
+        let rows = self.cell.gates() * self.units;
+        self.features = features;
+        self.steps = steps;
+        self.weights = self.weight_init.initialize(rows, features, rng);
+        self.recurrent = self.recurrent_init.initialize(rows, self.units, rng);
+        self.biases = Matrix::new(rows, 1);
+        if self.cell == Cell::Lstm {
+            self.biases.data[self.units..2 * self.units].fill(1.0);
+        }
+        self.output_shape = if self.return_sequences {
+            vec![self.units, steps]
+        } else {
+            vec![self.units]
+        };
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        vec![&self.weights, &self.recurrent, &self.biases]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        vec![&mut self.weights, &mut self.recurrent, &mut self.biases]
+    }
+
+    fn forward(&self, input: &Matrix, cache: &mut Cache, _pass: &mut Pass) -> Matrix {
+        let (u, n, steps) = (self.units, input.cols, self.steps);
+        let rows = self.cell.gates() * u;
+
+        // `[features, steps]` samples are a `features x (steps * batch)`
+        // matrix with step `t` in columns `t * n ..`.
+        let x = Matrix {
+            rows: self.features,
+            cols: steps * n,
+            data: input.data.clone(),
+        };
+        let mut projected = Matrix::new(rows, steps * n);
+        self.weights.dot(&x, &mut projected);
+        for r in 0..rows {
+            let b = self.biases.data[r];
+            for v in &mut projected.data[r * steps * n..(r + 1) * steps * n] {
+                *v += b;
+            }
+        }
+        cache.push(x);
+
+        let mut h = Matrix::new(u, n);
+        let mut c = Matrix::new(u, n);
+        let sequence_rows = if self.return_sequences { u } else { 0 };
+        let mut sequence = Matrix::new(sequence_rows, steps * n);
+        for t in 0..steps {
+            let mut z = step_columns(&projected, t, n);
+            let mut uh = Matrix::new(rows, n);
+            self.recurrent.dot(&h, &mut uh);
+
+            let extra = match self.cell {
+                Cell::Simple(activation) => {
+                    add_to(&mut z, &uh);
+                    activation.apply(&z, &mut h);
+                    Matrix::new(0, 0)
+                }
+                Cell::Lstm => {
+                    add_to(&mut z, &uh);
+                    for (k, v) in z.data.iter_mut().enumerate() {
+                        let is_candidate = k / (u * n) == 2;
+                        *v = if is_candidate { v.tanh() } else { sigmoid(*v) };
+                    }
+                    for k in 0..u * n {
+                        let (i, f, g, o) = (
+                            z.data[k],
+                            z.data[u * n + k],
+                            z.data[2 * u * n + k],
+                            z.data[3 * u * n + k],
+                        );
+                        c.data[k] = f * c.data[k] + i * g;
+                        h.data[k] = o * c.data[k].tanh();
+                    }
+                    c.clone()
+                }
+                Cell::Gru => {
+                    for k in 0..2 * u * n {
+                        z.data[k] = sigmoid(z.data[k] + uh.data[k]);
+                    }
+                    for k in 0..u * n {
+                        let r = z.data[u * n + k];
+                        let candidate = (z.data[2 * u * n + k] + r * uh.data[2 * u * n + k]).tanh();
+                        z.data[2 * u * n + k] = candidate;
+                        let update = z.data[k];
+                        h.data[k] = update * h.data[k] + (1.0 - update) * candidate;
+                    }
+                    uh
+                }
+            };
+
+            cache.push(h.clone());
+            cache.push(z);
+            cache.push(extra);
+            if self.return_sequences {
+                set_step_columns(&mut sequence, t, &h);
+            }
+        }
+
+        if self.return_sequences {
+            sequence.rows = u * steps;
+            sequence.cols = n;
+            sequence
+        } else {
+            h
+        }
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        _pass: &mut Pass,
+    ) -> Matrix {
+        let (u, n, steps) = (self.units, error.cols, self.steps);
+        let rows = self.cell.gates() * u;
+        let x = &cache[0];
+        let zeros = Matrix::new(u, n);
+        // The error at every step's hidden state, `[units, steps * batch]`;
+        // without return sequences only the last step has one.
+        let sequence_error = if self.return_sequences {
+            Matrix {
+                rows: u,
+                cols: steps * n,
+                data: error.data.clone(),
+            }
+        } else {
+            let mut sequence_error = Matrix::new(u, steps * n);
+            set_step_columns(&mut sequence_error, steps - 1, error);
+            sequence_error
+        };
+
+        let mut dh = Matrix::new(u, n);
+        let mut dc = Matrix::new(u, n);
+        let mut d_projected = Matrix::new(rows, steps * n);
+        let mut d_recurrent = Matrix::new(rows, u);
+        let mut scratch = Matrix::new(rows, u);
+        for t in (0..steps).rev() {
+            add_to(&mut dh, &step_columns(&sequence_error, t, n));
+
+            let h_prev = if t > 0 {
+                self.state(cache, t - 1, 0)
+            } else {
+                &zeros
+            };
+            let gates = self.state(cache, t, 1);
+            let extra = self.state(cache, t, 2);
+
+            // Errors at the input-side and recurrent-side pre-activations;
+            // they only differ for the GRU candidate.
+            let mut dz = Matrix::new(rows, n);
+            let mut dz_recurrent = None;
+            let mut dh_prev = Matrix::new(u, n);
+            match self.cell {
+                Cell::Simple(activation) => {
+                    dz = activation_backward(activation, gates, &dh);
+                }
+                Cell::Lstm => {
+                    let c_prev = if t > 0 {
+                        self.state(cache, t - 1, 2)
+                    } else {
+                        &zeros
+                    };
+                    for k in 0..u * n {
+                        let (i, f, g, o) = (
+                            gates.data[k],
+                            gates.data[u * n + k],
+                            gates.data[2 * u * n + k],
+                            gates.data[3 * u * n + k],
+                        );
+                        let tc = extra.data[k].tanh();
+                        let dc_total = dc.data[k] + dh.data[k] * o * (1.0 - tc * tc);
+                        dz.data[k] = dc_total * g * i * (1.0 - i);
+                        dz.data[u * n + k] = dc_total * c_prev.data[k] * f * (1.0 - f);
+                        dz.data[2 * u * n + k] = dc_total * i * (1.0 - g * g);
+                        dz.data[3 * u * n + k] = dh.data[k] * tc * o * (1.0 - o);
+                        dc.data[k] = dc_total * f;
+                    }
+                }
+                Cell::Gru => {
+                    let mut dzr = Matrix::new(rows, n);
+                    for k in 0..u * n {
+                        let (update, r, candidate) = (
+                            gates.data[k],
+                            gates.data[u * n + k],
+                            gates.data[2 * u * n + k],
+                        );
+                        let d = dh.data[k];
+                        let d_candidate = d * (1.0 - update) * (1.0 - candidate * candidate);
+                        let d_update = d * (h_prev.data[k] - candidate) * update * (1.0 - update);
+                        let d_reset = d_candidate * extra.data[2 * u * n + k] * r * (1.0 - r);
+                        dz.data[k] = d_update;
+                        dz.data[u * n + k] = d_reset;
+                        dz.data[2 * u * n + k] = d_candidate;
+                        dzr.data[k] = d_update;
+                        dzr.data[u * n + k] = d_reset;
+                        dzr.data[2 * u * n + k] = d_candidate * r;
+                        dh_prev.data[k] = d * update;
+                    }
+                    dz_recurrent = Some(dzr);
+                }
+            }
+
+            let dz_recurrent = dz_recurrent.as_ref().unwrap_or(&dz);
+            dz_recurrent.dot_rhs_transposed(h_prev, &mut scratch);
+            add_to(&mut d_recurrent, &scratch);
+            let mut through_recurrent = Matrix::new(u, n);
+            self.recurrent
+                .dot_self_transposed(dz_recurrent, &mut through_recurrent);
+            add_to(&mut dh_prev, &through_recurrent);
+            set_step_columns(&mut d_projected, t, &dz);
+
+            dh = dh_prev;
+            if let Some(window) = self.truncation
+                && (steps - t) % window == 0
+            {
+                dh.zeros();
+                dc.zeros();
+            }
+        }
+
+        let mut d_weights = Matrix::new(rows, self.features);
+        d_projected.dot_rhs_transposed(x, &mut d_weights);
+        add_to(&mut gradients[0], &d_weights);
+        add_to(&mut gradients[1], &d_recurrent);
+        for r in 0..rows {
+            gradients[2].data[r] += d_projected.data[r * steps * n..(r + 1) * steps * n]
+                .iter()
+                .sum::<f32>();
+        }
+
+        let mut input_error = Matrix::new(self.features, steps * n);
+        self.weights
+            .dot_self_transposed(&d_projected, &mut input_error);
+        input_error.rows = self.features * steps;
+        input_error.cols = n;
+        input_error
+    }
+
+    fn regularization_loss(&self) -> f32 {
+        let Some(reg) = &self.regularizer else {
+            return 0.0;
+        };
+        let mut penalty = reg.penalty(&self.weights) + reg.penalty(&self.recurrent);
+        if reg.include_biases {
+            penalty += reg.penalty(&self.biases);
+        }
+        penalty
+    }
+
+    fn add_regularization_gradients(&self, gradients: &mut [Matrix]) {
+        if let Some(reg) = &self.regularizer {
+            reg.add_gradient(&self.weights, &mut gradients[0]);
+            reg.add_gradient(&self.recurrent, &mut gradients[1]);
+            if reg.include_biases {
+                reg.add_gradient(&self.biases, &mut gradients[2]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::{check_layer, random};
+    use rand::SeedableRng;
+
+    fn check_both(layer: impl Fn() -> Recurrent) {
+        check_layer(layer(), &[2, 4], 2);
+        check_layer(layer().with_return_sequences(true), &[2, 4], 2);
+    }
+
+    #[test]
+    fn simple_gradients() {
+        check_both(|| Recurrent::simple(3));
+    }
+
+    #[test]
+    fn lstm_gradients() {
+        check_both(|| Recurrent::lstm(3));
+    }
+
+    #[test]
+    fn gru_gradients() {
+        check_both(|| Recurrent::gru(3));
+    }
+
+    // Input error and parameter gradients of one backward pass on a fixed
+    // batch of `[2, 4]` sequences.
+    fn backward(mut layer: Recurrent) -> (Matrix, Vec<Matrix>) {
+        let mut rng = StdRng::seed_from_u64(4);
+        layer.build(&[2, 4], &mut rng).unwrap();
+        let input = random(8, 2, &mut rng);
+        let error = random(layer.output_shape().iter().product(), 2, &mut rng);
+        let mut pass = Pass {
+            training: true,
+            rng: &mut rng,
+            statistics: None,
+            batch_size: 2,
+        };
+        let mut cache = Cache::new();
+        layer.forward(&input, &mut cache, &mut pass);
+        let mut gradients = layer.gradients();
+        let input_error = layer.backward(&error, &cache, &mut gradients, &mut pass);
+        (input_error, gradients)
+    }
+
+    #[test]
+    fn truncation_stops_gradients_at_the_window() {
+        for layer in [Recurrent::simple, Recurrent::lstm, Recurrent::gru] {
+            let (full, _) = backward(layer(3));
+            let (truncated, _) = backward(layer(3).with_truncation(2));
+            // Only the last two of the four steps (`f * 4 + t`) see the
+            // error at the last output.
+            for f in 0..2 {
+                for t in 0..4 {
+                    for c in 0..2 {
+                        let i = (f * 4 + t) * 2 + c;
+                        if t < 2 {
+                            assert_eq!(truncated.data[i], 0.0);
+                            assert_ne!(full.data[i], 0.0);
+                        } else {
+                            assert_eq!(truncated.data[i], full.data[i]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_windows_are_full_backpropagation() {
+        for window in [4, 9] {
+            let (full, full_gradients) = backward(Recurrent::lstm(3).with_return_sequences(true));
+            let (windowed, gradients) =
+                backward(Recurrent::lstm(3).with_return_sequences(true).with_truncation(window));
+            assert_eq!(windowed.data, full.data);
+            for (a, b) in gradients.iter().zip(&full_gradients) {
+                assert_eq!(a.data, b.data);
+            }
+        }
+    }
+
+    #[test]
+    fn last_state_error_matches_a_sequence_error_at_the_last_step() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let input = random(8, 2, &mut rng);
+        let last_error = random(3, 2, &mut rng);
+        let run = |return_sequences: bool, error: &Matrix| {
+            let mut layer = Recurrent::gru(3).with_return_sequences(return_sequences);
+            layer.build(&[2, 4], &mut StdRng::seed_from_u64(1)).unwrap();
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut pass = Pass {
+                training: true,
+                rng: &mut rng,
+                statistics: None,
+                batch_size: 2,
+            };
+            let mut cache = Cache::new();
+            layer.forward(&input, &mut cache, &mut pass);
+            let mut gradients = layer.gradients();
+            let input_error = layer.backward(error, &cache, &mut gradients, &mut pass);
+            (input_error.data, gradients)
+        };
+
+        // Outputs are `[units, steps]`, so unit `u` at the last step is row
+        // `u * 4 + 3`.
+        let mut sequence_error = Matrix::new(12, 2);
+        for u in 0..3 {
+            for c in 0..2 {
+                sequence_error.data[(u * 4 + 3) * 2 + c] = last_error.data[u * 2 + c];
+            }
+        }
+        let (a, a_gradients) = run(false, &last_error);
+        let (b, b_gradients) = run(true, &sequence_error);
+        assert_eq!(a, b);
+        for (x, y) in a_gradients.iter().zip(&b_gradients) {
+            assert_eq!(x.data, y.data);
+        }
+    }
+}
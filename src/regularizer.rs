@@ -0,0 +1,89 @@
+use crate::loss::sign;
+use crate::matrix::Matrix;
+
+// Weight penalty `l1 * sum(|w|) + l2 * sum(w^2)` for one layer. Setting both
+// factors gives elastic-net. Biases are penalised too unless excluded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Regularizer {
+    pub l1: f32,
+    pub l2: f32,
+    pub include_biases: bool,
+}
+
+impl Regularizer {
+    pub fn l1(l1: f32) -> Self {
+        Self::elastic_net(l1, 0.0)
+    }
+
+    pub fn l2(l2: f32) -> Self {
+        Self::elastic_net(0.0, l2)
+    }
+
+    pub fn elastic_net(l1: f32, l2: f32) -> Self {
+        Regularizer {
+            l1,
+            l2,
+            include_biases: true,
+        }
+    }
+
+    pub fn exclude_biases(mut self) -> Self {
+        self.include_biases = false;
+        self
+    }
+
+    pub fn penalty(&self, param: &Matrix) -> f32 {
+        param
+            .data
+            .iter()
+            .map(|w| self.l1 * w.abs() + self.l2 * w * w)
+            .sum()
+    }
+
+    // `grad` holds the descent direction like the rest of `Gradients`, so the
+    // penalty's derivative is subtracted from it. Like `Loss::gradient` it is
+    // the exact derivative of what `penalty` adds to the reported loss, so
+    // the two stay in proportion.
+    pub fn add_gradient(&self, param: &Matrix, grad: &mut Matrix) {
+        for (g, w) in grad.data.iter_mut().zip(&param.data) {
+            *g -= self.l1 * sign(*w) + 2.0 * self.l2 * w;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param() -> Matrix {
+        Matrix {
+            rows: 1,
+            cols: 3,
+            data: vec![0.5, -2.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn penalty_sums_both_terms() {
+        let reg = Regularizer::elastic_net(0.1, 0.2);
+        assert!((reg.penalty(&param()) - (0.1 * 2.5 + 0.2 * 4.25)).abs() < 1e-6);
+        assert_eq!(Regularizer::l1(0.3).l2, 0.0);
+        assert!(!Regularizer::l2(0.3).exclude_biases().include_biases);
+    }
+
+    #[test]
+    fn gradient_is_the_negative_penalty_derivative() {
+        let reg = Regularizer::elastic_net(0.1, 0.2);
+        let mut grad = Matrix::new(1, 3);
+        reg.add_gradient(&param(), &mut grad);
+        // The subgradient of |w| at 0 is taken as 0.
+        assert_eq!(grad.data[2], 0.0);
+        for i in 0..2 {
+            let (mut plus, mut minus) = (param(), param());
+            plus.data[i] += 1e-3;
+            minus.data[i] -= 1e-3;
+            let numeric = (reg.penalty(&plus) - reg.penalty(&minus)) / 2e-3;
+            assert!((grad.data[i] + numeric).abs() < 1e-3, "{}: {}", i, grad.data[i]);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::f32::consts::PI;
+
+// Whether a schedule advances once per optimizer step or once per epoch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interval {
+    Step,
+    Epoch,
+}
+
+// A learning-rate schedule. `rate` maps the network's base learning rate and
+// the number of completed steps or epochs (see `interval`) to the rate used
+// for the next update. `observe` receives the monitored loss at the end of
+// every epoch, for schedules that react to training progress.
+pub trait LrScheduler: Send {
+    fn interval(&self) -> Interval;
+
+    fn rate(&self, base: f32, t: usize) -> f32;
+
+    fn observe(&mut self, _metric: f32) {}
+}
+
+// Multiplies the rate by `gamma` every `step_size` intervals.
+pub struct StepDecay {
+    pub step_size: usize,
+    pub gamma: f32,
+    pub interval: Interval,
+}
+
+impl StepDecay {
+    pub fn new(step_size: usize, gamma: f32) -> Self {
+        StepDecay {
+            step_size: step_size.max(1),
+            gamma,
+            interval: Interval::Epoch,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Interval) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn interval(&self) -> Interval {
+        self.interval
+    }
+
+    fn rate(&self, base: f32, t: usize) -> f32 {
+        base * self.gamma.powi((t / self.step_size) as i32)
+    }
+}
+
+pub struct ExponentialDecay {
+    pub gamma: f32,
+    pub interval: Interval,
+}
+
+impl ExponentialDecay {
+    pub fn new(gamma: f32) -> Self {
+        ExponentialDecay {
+            gamma,
+            interval: Interval::Epoch,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Interval) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl LrScheduler for ExponentialDecay {
+    fn interval(&self) -> Interval {
+        self.interval
+    }
+
+    fn rate(&self, base: f32, t: usize) -> f32 {
+        base * self.gamma.powi(t as i32)
+    }
+}
+
+// SGDR: cosine decay from the base rate down to `min_rate` over a cycle of
+// `cycle_length` intervals, then a jump back to the base rate. Every cycle is
+// `cycle_mult` times longer than the previous one.
+pub struct CosineAnnealingWarmRestarts {
+    pub cycle_length: usize,
+    pub cycle_mult: usize,
+    pub min_rate: f32,
+    pub interval: Interval,
+}
+
+impl CosineAnnealingWarmRestarts {
+    pub fn new(cycle_length: usize) -> Self {
+        CosineAnnealingWarmRestarts {
+            cycle_length: cycle_length.max(1),
+            cycle_mult: 1,
+            min_rate: 0.0,
+            interval: Interval::Epoch,
+        }
+    }
+
+    pub fn with_cycle_mult(mut self, cycle_mult: usize) -> Self {
+        self.cycle_mult = cycle_mult.max(1);
+        self
+    }
+
+    pub fn with_min_rate(mut self, min_rate: f32) -> Self {
+        self.min_rate = min_rate;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Interval) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl LrScheduler for CosineAnnealingWarmRestarts {
+    fn interval(&self) -> Interval {
+        self.interval
+    }
+
+    fn rate(&self, base: f32, t: usize) -> f32 {
+        let mut position = t;
+        let mut length = self.cycle_length;
+        while position >= length {
+            position -= length;
+            length *= self.cycle_mult;
+        }
+
+        let progress = position as f32 / length as f32;
+        self.min_rate + 0.5 * (base - self.min_rate) * (1.0 + (PI * progress).cos())
+    }
+}
+
+// Ramps the rate linearly from `start_factor * base` up to `base` over
+// `warmup` intervals, then hands over to the wrapped schedule (if any), which
+// sees time counted from the end of the warmup.
+pub struct LinearWarmup {
+    pub warmup: usize,
+    pub start_factor: f32,
+    pub interval: Interval,
+    after: Option<Box<dyn LrScheduler>>,
+}
+
+impl LinearWarmup {
+    pub fn new(warmup: usize) -> Self {
+        LinearWarmup {
+            warmup,
+            start_factor: 0.0,
+            interval: Interval::Step,
+            after: None,
+        }
+    }
+
+    pub fn with_start_factor(mut self, start_factor: f32) -> Self {
+        self.start_factor = start_factor;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Interval) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn then<S: LrScheduler + 'static>(mut self, schedule: S) -> Self {
+        self.after = Some(Box::new(schedule));
+        self
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    fn interval(&self) -> Interval {
+        self.interval
+    }
+
+    fn rate(&self, base: f32, t: usize) -> f32 {
+        if t < self.warmup {
+            let progress = t as f32 / self.warmup as f32;
+            return base * (self.start_factor + (1.0 - self.start_factor) * progress);
+        }
+
+        match &self.after {
+            Some(schedule) => schedule.rate(base, t - self.warmup),
+            None => base,
+        }
+    }
+
+    fn observe(&mut self, metric: f32) {
+        if let Some(schedule) = &mut self.after {
+            schedule.observe(metric);
+        }
+    }
+}
+
+// The one-cycle policy: cosine ramp from `max_rate / div_factor` up to
+// `max_rate` over the first `pct_start` of `total` steps, then cosine decay
+// down to `max_rate / (div_factor * final_div_factor)`. The base rate of the
+// network is ignored.
+pub struct OneCycle {
+    pub max_rate: f32,
+    pub total: usize,
+    pub pct_start: f32,
+    pub div_factor: f32,
+    pub final_div_factor: f32,
+    pub interval: Interval,
+}
+
+impl OneCycle {
+    pub fn new(max_rate: f32, total: usize) -> Self {
+        OneCycle {
+            max_rate,
+            total: total.max(1),
+            pct_start: 0.3,
+            div_factor: 25.0,
+            final_div_factor: 1e4,
+            interval: Interval::Step,
+        }
+    }
+
+    pub fn with_pct_start(mut self, pct_start: f32) -> Self {
+        self.pct_start = pct_start.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_div_factors(mut self, div_factor: f32, final_div_factor: f32) -> Self {
+        self.div_factor = div_factor;
+        self.final_div_factor = final_div_factor;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Interval) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl LrScheduler for OneCycle {
+    fn interval(&self) -> Interval {
+        self.interval
+    }
+
+    fn rate(&self, _base: f32, t: usize) -> f32 {
+        let initial = self.max_rate / self.div_factor;
+        let min = initial / self.final_div_factor;
+
+        let last = (self.total - 1) as f32;
+        let peak = (self.pct_start * last).round();
+        let t = (t as f32).min(last);
+
+        let anneal = |from: f32, to: f32, progress: f32| {
+            to + 0.5 * (from - to) * (1.0 + (PI * progress).cos())
+        };
+        if t <= peak {
+            let progress = if peak > 0.0 { t / peak } else { 1.0 };
+            anneal(initial, self.max_rate, progress)
+        } else {
+            anneal(self.max_rate, min, (t - peak) / (last - peak))
+        }
+    }
+}
+
+// Multiplies the rate by `factor` once the monitored loss has not improved
+// by more than `min_delta` for `patience` epochs, then waits `cooldown`
+// epochs before watching again. The rate never drops below `min_rate`.
+pub struct ReduceOnPlateau {
+    pub factor: f32,
+    pub patience: usize,
+    pub min_delta: f32,
+    pub cooldown: usize,
+    pub min_rate: f32,
+    scale: f32,
+    best: f32,
+    wait: usize,
+    cooldown_left: usize,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(factor: f32, patience: usize) -> Self {
+        ReduceOnPlateau {
+            factor,
+            patience,
+            min_delta: 1e-4,
+            cooldown: 0,
+            min_rate: 0.0,
+            scale: 1.0,
+            best: f32::INFINITY,
+            wait: 0,
+            cooldown_left: 0,
+        }
+    }
+
+    pub fn with_min_delta(mut self, min_delta: f32) -> Self {
+        self.min_delta = min_delta.abs();
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: usize) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn with_min_rate(mut self, min_rate: f32) -> Self {
+        self.min_rate = min_rate;
+        self
+    }
+}
+
+impl LrScheduler for ReduceOnPlateau {
+    fn interval(&self) -> Interval {
+        Interval::Epoch
+    }
+
+    fn rate(&self, base: f32, _t: usize) -> f32 {
+        (base * self.scale).max(self.min_rate)
+    }
+
+    fn observe(&mut self, metric: f32) {
+        if self.cooldown_left > 0 {
+            self.cooldown_left -= 1;
+            self.wait = 0;
+        }
+
+        if metric < self.best - self.min_delta {
+            self.best = metric;
+            self.wait = 0;
+            return;
+        }
+
+        if self.cooldown_left == 0 {
+            self.wait += 1;
+            if self.wait >= self.patience {
+                self.scale *= self.factor;
+                self.cooldown_left = self.cooldown;
+                self.wait = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rates(schedule: &dyn LrScheduler, base: f32, steps: usize) -> Vec<f32> {
+        (0..steps).map(|t| schedule.rate(base, t)).collect()
+    }
+
+    fn assert_rates(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (t, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-6, "t = {}: {} != {}", t, a, e);
+        }
+    }
+
+    #[test]
+    fn step_and_exponential_decay() {
+        assert_rates(&rates(&StepDecay::new(2, 0.5), 1.0, 6), &[1.0, 1.0, 0.5, 0.5, 0.25, 0.25]);
+        assert_rates(&rates(&ExponentialDecay::new(0.9), 2.0, 3), &[2.0, 1.8, 1.62]);
+    }
+
+    #[test]
+    fn cosine_annealing_restarts_with_longer_cycles() {
+        let (a, b) = (0.853_553_4, 0.146_446_6);
+        let fixed = CosineAnnealingWarmRestarts::new(4);
+        assert_rates(&rates(&fixed, 1.0, 5), &[1.0, a, 0.5, b, 1.0]);
+        let growing = CosineAnnealingWarmRestarts::new(2).with_cycle_mult(2);
+        assert_rates(&rates(&growing, 1.0, 7), &[1.0, 0.5, 1.0, a, 0.5, b, 1.0]);
+        let floored = CosineAnnealingWarmRestarts::new(2).with_min_rate(0.2);
+        assert_rates(&rates(&floored, 1.0, 2), &[1.0, 0.6]);
+    }
+
+    #[test]
+    fn warmup_hands_over_with_its_own_clock() {
+        let warmup = LinearWarmup::new(4).then(StepDecay::new(1, 0.5));
+        assert_rates(&rates(&warmup, 1.0, 7), &[0.0, 0.25, 0.5, 0.75, 1.0, 0.5, 0.25]);
+        let warmup = LinearWarmup::new(2).with_start_factor(0.5);
+        assert_rates(&rates(&warmup, 2.0, 3), &[1.0, 1.5, 2.0]);
+    }
+
+    #[test]
+    fn one_cycle_rises_then_anneals_below_the_start() {
+        let cycle = OneCycle::new(1.0, 11);
+        let r = rates(&cycle, 123.0, 12);
+        assert_rates(&r[..2], &[0.04, 0.28]);
+        assert_rates(&r[3..4], &[1.0]);
+        assert_rates(&r[10..], &[4e-6, 4e-6]);
+        assert!(r[4..11].windows(2).all(|w| w[1] < w[0]));
+    }
+
+    #[test]
+    fn reduce_on_plateau_waits_and_cools_down() {
+        let mut plateau = ReduceOnPlateau::new(0.5, 2).with_cooldown(1).with_min_rate(0.3);
+        let mut seen = Vec::new();
+        for _ in 0..5 {
+            plateau.observe(1.0);
+            seen.push(plateau.rate(1.0, 0));
+        }
+        assert_rates(&seen, &[1.0, 1.0, 0.5, 0.5, 0.3]);
+        // An improvement resets the wait.
+        plateau.observe(0.5);
+        plateau.observe(0.6);
+        assert_rates(&[plateau.rate(10.0, 0)], &[2.5]);
+    }
+}
@@ -0,0 +1,536 @@
+use std::path::Path;
+
+use rand::rngs::StdRng;
+
+use crate::clipping::GradientClipping;
+use crate::error::Result;
+use crate::graph::{Graph, Node};
+use crate::layer::Layer;
+use crate::loss::Loss;
+use crate::matrix::Matrix;
+use crate::model::Model;
+use crate::optimizer::Optimizer;
+use crate::scheduler::LrScheduler;
+
+// A stack of layers, each feeding the next. Unlike `Network` the layers can
+// be anything implementing `Layer`, e.g.
+//
+//     Sequential::new(vec![784], 0.01)
+//         .with_layer(Dense::new(128))
+//         .with_layer(NormalizationLayer::batch())
+//         .with_layer(ActivationLayer::new(Activation::Relu))
+//         .with_layer(DropoutLayer::new(Dropout::Standard(0.2)))
+//         .with_layer(Dense::new(10).with_activation(Activation::Softmax))
+//
+// It is a `Graph` with a single chain of layer nodes, so training,
+// optimizer state and persistence all go through the graph.
+pub struct Sequential {
+    graph: Graph,
+    input: Node,
+    last: Node,
+    loss: Loss,
+}
+
+impl Sequential {
+    pub fn new(input_shape: Vec<usize>, learning_rate: f32) -> Self {
+        let mut graph = Graph::new(learning_rate);
+        let input = graph.input(input_shape);
+        let loss = Loss::MeanSquaredError;
+        graph.set_output(input, loss);
+        Sequential {
+            graph,
+            input,
+            last: input,
+            loss,
+        }
+    }
+
+    pub fn with_layer<L: Layer + 'static>(self, layer: L) -> Self {
+        self.try_with_layer(layer)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Builds `layer` on the current output shape, drawing its parameters
+    // from the model's random source.
+    pub fn try_with_layer<L: Layer + 'static>(mut self, layer: L) -> Result<Self> {
+        self.last = self.graph.try_layer(layer, self.last)?;
+        self.graph.set_output(self.last, self.loss);
+        Ok(self)
+    }
+
+    pub fn with_loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self.graph.set_output(self.last, loss);
+        self
+    }
+
+    // Reseeds the model's random source and rebuilds every layer from it, so
+    // the same seed always yields the same starting parameters.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.graph = self.graph.with_seed(seed);
+        self
+    }
+
+    pub fn with_optimizer<O: Optimizer + 'static>(mut self, optimizer: O) -> Self {
+        self.graph = self.graph.with_optimizer(optimizer);
+        self
+    }
+
+    // The scheduler's clock starts from zero whenever one is attached.
+    pub fn with_scheduler<S: LrScheduler + 'static>(mut self, scheduler: S) -> Self {
+        self.graph = self.graph.with_scheduler(scheduler);
+        self
+    }
+
+    pub fn with_gradient_clipping(mut self, clipping: GradientClipping) -> Self {
+        self.graph = self.graph.with_gradient_clipping(clipping);
+        self
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        self.graph.rng()
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = &dyn Layer> {
+        self.graph.layers()
+    }
+
+    pub fn input_shape(&self) -> &[usize] {
+        self.graph.shape(self.input)
+    }
+
+    pub fn output_shape(&self) -> &[usize] {
+        self.graph.shape(self.last)
+    }
+
+    pub fn loss(&self) -> Loss {
+        self.loss
+    }
+
+    // Base rate, before any scheduler is applied.
+    pub fn learning_rate(&self) -> f32 {
+        self.graph.learning_rate()
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.graph.set_learning_rate(learning_rate);
+    }
+
+    // Rate the next training step will use.
+    pub fn current_learning_rate(&self) -> f32 {
+        self.graph.current_learning_rate()
+    }
+
+    // Advances per-epoch schedules and feeds them the monitored loss.
+    pub fn end_epoch(&mut self, metric: f32) {
+        self.graph.end_epoch(metric);
+    }
+
+    // Global norm of the last applied gradients, measured before clipping.
+    pub fn gradient_norm(&self) -> f32 {
+        self.graph.gradient_norm()
+    }
+
+    // Every trainable tensor, in layer order.
+    pub fn parameters(&self) -> Vec<&Matrix> {
+        self.graph.parameters()
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.graph.parameter_count()
+    }
+
+    // Replaces every trainable tensor, e.g. to roll back to a snapshot taken
+    // from `parameters()`. Optimizer state is left untouched.
+    pub fn set_parameters(&mut self, parameters: Vec<Matrix>) -> Result<()> {
+        self.graph.set_parameters(parameters)
+    }
+
+    // Every state tensor, such as running statistics, in layer order.
+    pub fn state(&self) -> Vec<&Matrix> {
+        self.graph.state()
+    }
+
+    // Saves the parameters and state, but not the layers: load them with
+    // `load_parameters` into a model built the same way.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.graph.save(path)
+    }
+
+    pub fn load_parameters<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.graph.load_parameters(path)
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.graph.save_json(path)
+    }
+
+    pub fn load_parameters_json<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.graph.load_parameters_json(path)
+    }
+
+    // Total regularization penalty of the current parameters. It is already
+    // included in the losses returned by `evaluate` and the training methods.
+    pub fn regularization_loss(&self) -> f32 {
+        self.graph.regularization_loss()
+    }
+
+    // Fewest samples a training batch may hold: 2 when any layer uses batch
+    // normalization.
+    pub fn min_batch_size(&self) -> usize {
+        self.graph.min_batch_size()
+    }
+
+    pub fn try_forward(&self, input: &[f32]) -> Result<Vec<f32>> {
+        self.graph.try_forward(input)
+    }
+
+    pub fn forward(&self, input: &[f32]) -> Vec<f32> {
+        self.graph.forward(input)
+    }
+
+    // Inference on a batch matrix with one sample per column.
+    pub fn predict(&self, inputs: &Matrix) -> Matrix {
+        let inputs = std::slice::from_ref(inputs);
+        self.graph.predict(inputs).pop().unwrap()
+    }
+
+    pub fn evaluate(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> f32 {
+        self.graph.evaluate(inputs, targets)
+    }
+
+    pub fn try_evaluate(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<f32> {
+        self.graph.try_evaluate(inputs, targets)
+    }
+
+    pub fn try_train(&mut self, input: &[f32], target: &[f32]) -> Result<f32> {
+        self.graph.try_train(input, target)
+    }
+
+    pub fn train(&mut self, input: &[f32], target: &[f32]) -> f32 {
+        self.graph.train(input, target)
+    }
+
+    pub fn try_train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> Result<f32> {
+        self.graph
+            .try_train_batch_parallel(inputs, targets, num_threads)
+    }
+
+    // One optimizer step on the batch-averaged gradients. The batch is split
+    // into chunks, run on up to `num_threads` threads, that go through the
+    // layers in lockstep, so layers needing whole-batch statistics get them;
+    // partial results are combined in chunk order to keep seeded runs
+    // bit-identical for any thread count. Panics on batches smaller than
+    // `min_batch_size`.
+    pub fn train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> f32 {
+        self.graph
+            .train_batch_parallel(inputs, targets, num_threads)
+    }
+}
+
+impl Model for Sequential {
+    fn rng(&mut self) -> &mut StdRng {
+        self.graph.rng()
+    }
+
+    fn check_batch(&self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> Result<()> {
+        self.graph.check_batch(inputs, targets)
+    }
+
+    fn min_batch_size(&self) -> usize {
+        self.graph.min_batch_size()
+    }
+
+    fn current_learning_rate(&self) -> f32 {
+        self.graph.current_learning_rate()
+    }
+
+    fn train_batch_parallel(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        num_threads: usize,
+    ) -> f32 {
+        self.graph
+            .train_batch_parallel(inputs, targets, num_threads)
+    }
+
+    fn evaluate(&mut self, inputs: &[Vec<f32>], targets: &[Vec<f32>]) -> f32 {
+        self.graph.evaluate(inputs, targets)
+    }
+
+    fn end_epoch(&mut self, metric: f32) {
+        self.graph.end_epoch(metric);
+    }
+
+    fn snapshot(&self) -> Vec<Matrix> {
+        self.graph.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: Vec<Matrix>) -> Result<()> {
+        self.graph.restore(snapshot)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        self.graph.save(path)
+    }
+
+    fn save_json(&self, path: &Path) -> Result<()> {
+        self.graph.save_json(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::Activation;
+    use crate::conv::{Conv2D, Padding};
+    use crate::embedding::Embedding;
+    use crate::error::RustingBrainError;
+    use crate::gradcheck::check_model;
+    use crate::layer::{ActivationLayer, Dense, DropoutLayer, Flatten, NormalizationLayer};
+    use crate::network::Network;
+    use crate::pooling::MaxPool2D;
+
+    fn inputs() -> Vec<Vec<f32>> {
+        vec![
+            vec![0.5, -0.2, 0.1, 0.9],
+            vec![0.2, 0.8, -0.4, -0.1],
+            vec![-0.9, 0.1, 0.7, 0.2],
+        ]
+    }
+
+    // Ends with sigmoid and binary cross-entropy, which backpropagate fused
+    // from the logits.
+    fn fused(learning_rate: f32) -> Sequential {
+        Sequential::new(vec![4], learning_rate)
+            .with_layer(Dense::new(3).with_activation(Activation::Tanh))
+            .with_layer(Dense::new(2).with_activation(Activation::Sigmoid))
+            .with_loss(Loss::BinaryCrossEntropy)
+            .with_seed(3)
+    }
+
+    #[test]
+    fn seeded_training_ignores_the_thread_count() {
+        use crate::dropout::Dropout;
+        let inputs: Vec<Vec<f32>> = (0..40).map(|i| inputs()[i % 3].clone()).collect();
+        let targets: Vec<Vec<f32>> = (0..40).map(|i| vec![(i % 5) as f32 * 0.1]).collect();
+        let run = |threads| {
+            let mut model = Sequential::new(vec![4], 0.1)
+                .with_layer(Dense::new(6))
+                .with_layer(NormalizationLayer::batch())
+                .with_layer(ActivationLayer::new(Activation::Relu))
+                .with_layer(DropoutLayer::new(Dropout::Standard(0.3)))
+                .with_layer(Dense::new(1))
+                .with_seed(8);
+            for _ in 0..3 {
+                model.train_batch_parallel(&inputs, &targets, threads);
+            }
+            let tensors = model.parameters().into_iter().chain(model.state());
+            tensors.flat_map(|m| m.data.clone()).map(f32::to_bits).collect::<Vec<_>>()
+        };
+        let one = run(1);
+        assert_eq!(one, run(3));
+        assert_eq!(one, run(8));
+    }
+
+    #[test]
+    fn evaluate_checks_the_lengths() {
+        let model = fused(0.1);
+        let targets = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0]];
+        assert!(matches!(
+            model.try_evaluate(&inputs(), &targets),
+            Err(RustingBrainError::ShapeMismatch { .. })
+        ));
+        assert!(matches!(
+            model.try_evaluate(&inputs(), &targets[..2]),
+            Err(RustingBrainError::BatchSizeMismatch { inputs: 3, targets: 2 })
+        ));
+    }
+
+    #[test]
+    fn fused_output_gradients() {
+        let targets = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]];
+        check_model(fused, &inputs(), &targets, |_| true);
+    }
+
+    // Batch statistics are shared by every chunk of the checked steps.
+    fn normalized(learning_rate: f32) -> Sequential {
+        Sequential::new(vec![4], learning_rate)
+            .with_layer(Dense::new(3))
+            .with_layer(NormalizationLayer::batch())
+            .with_layer(ActivationLayer::new(Activation::Tanh))
+            .with_layer(Dense::new(1))
+            .with_seed(5)
+    }
+
+    #[test]
+    fn batch_normalized_gradients() {
+        let targets = vec![vec![0.5], vec![-1.0], vec![2.0]];
+        // Running statistics follow the parameters in the snapshot.
+        let parameters = normalized(0.0).parameters().len();
+        check_model(normalized, &inputs(), &targets, |k| k < parameters);
+    }
+
+    // Convolution and pooling feeding a dense head through Flatten.
+    fn image(learning_rate: f32) -> Sequential {
+        Sequential::new(vec![1, 2, 2], learning_rate)
+            .with_layer(Conv2D::new(2, (2, 2)).with_padding(Padding::Same))
+            .with_layer(MaxPool2D::new((2, 2)))
+            .with_layer(Flatten::new())
+            .with_layer(Dense::new(1))
+            .with_seed(7)
+    }
+
+    #[test]
+    fn image_model_gradients() {
+        let targets = vec![vec![0.5], vec![-1.0], vec![2.0]];
+        check_model(image, &inputs(), &targets, |_| true);
+    }
+
+    // Token indices through an embedding, which trains through row-sparse
+    // gradients.
+    fn embedded(learning_rate: f32) -> Sequential {
+        Sequential::new(vec![3], learning_rate)
+            .with_layer(Embedding::new(5, 2))
+            .with_layer(Flatten::new())
+            .with_layer(Dense::new(1))
+            .with_seed(9)
+    }
+
+    #[test]
+    fn embedding_model_gradients() {
+        let tokens = vec![vec![1.0, 3.0, 1.0], vec![0.0, 2.0, 3.0]];
+        let targets = vec![vec![0.5], vec![-1.0]];
+        check_model(embedded, &tokens, &targets, |_| true);
+
+        let mut model = embedded(0.1);
+        assert!(matches!(
+            model.try_train(&[1.0, 5.0, 0.0], &[0.0]),
+            Err(RustingBrainError::InvalidIndex { limit: 5, .. })
+        ));
+        assert!(model.try_forward(&[1.0, 2.5, 0.0]).is_err());
+    }
+
+    #[test]
+    fn dense_stack_matches_network() {
+        let mut net = Network::with_activations(
+            vec![4, 3, 2],
+            vec![Activation::Relu, Activation::Sigmoid],
+            0.1,
+        );
+        let mut model = Sequential::new(vec![4], 0.1)
+            .with_layer(Dense::new(3).with_activation(Activation::Relu))
+            .with_layer(Dense::new(2).with_activation(Activation::Sigmoid));
+        let parameters = vec![
+            net.weights()[0].clone(),
+            net.biases()[0].clone(),
+            net.weights()[1].clone(),
+            net.biases()[1].clone(),
+        ];
+        model.set_parameters(parameters).unwrap();
+        for input in inputs() {
+            assert_eq!(model.forward(&input), net.forward(&input));
+        }
+    }
+
+    #[test]
+    fn shapes_are_checked() {
+        let mut model = fused(0.1);
+        assert!(matches!(
+            model.try_forward(&[1.0]),
+            Err(RustingBrainError::ShapeMismatch { .. })
+        ));
+        assert!(matches!(
+            model.try_train(&[0.0; 4], &[1.0]),
+            Err(RustingBrainError::ShapeMismatch { .. })
+        ));
+        assert!(matches!(
+            model.try_train_batch_parallel(&[], &[], 2),
+            Err(RustingBrainError::EmptyBatch)
+        ));
+        assert!(matches!(
+            model.set_parameters(Vec::new()),
+            Err(RustingBrainError::InvalidArchitecture(_))
+        ));
+    }
+
+    #[test]
+    fn seeded_models_train_identically() {
+        let targets = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]];
+        let run = || {
+            let mut model = fused(0.5);
+            for _ in 0..3 {
+                model.train_batch_parallel(&inputs(), &targets, 2);
+            }
+            model.parameters().iter().map(|p| p.data.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn saved_parameters_and_statistics_load_back() {
+        let targets = vec![vec![0.5], vec![-1.0], vec![2.0]];
+        let mut model = normalized(0.1);
+        model.train_batch_parallel(&inputs(), &targets, 2);
+
+        let dir = std::env::temp_dir();
+        let bin = dir.join(format!("rb-seq-{}.bin", std::process::id()));
+        let json = dir.join(format!("rb-seq-{}.json", std::process::id()));
+        model.save(&bin).unwrap();
+        model.save_json(&json).unwrap();
+
+        let mut from_bin = normalized(0.1).with_seed(11);
+        from_bin.load_parameters(&bin).unwrap();
+        let mut from_json = normalized(0.1).with_seed(12);
+        from_json.load_parameters_json(&json).unwrap();
+        let mut other = fused(0.1);
+        let mismatch = other.load_parameters(&bin);
+        std::fs::remove_file(&bin).unwrap();
+        std::fs::remove_file(&json).unwrap();
+
+        for loaded in [&from_bin, &from_json] {
+            assert_eq!(loaded.snapshot().len(), model.snapshot().len());
+            for (a, b) in loaded.snapshot().iter().zip(model.snapshot()) {
+                assert_eq!(a.data, b.data);
+            }
+        }
+        assert!(mismatch.is_err());
+    }
+
+    #[test]
+    fn batch_normalization_rejects_single_samples() {
+        let mut model = normalized(0.1);
+        assert_eq!(model.min_batch_size(), 2);
+        assert_eq!(fused(0.1).min_batch_size(), 1);
+        assert!(matches!(
+            model.try_train(&inputs()[0], &[1.0]),
+            Err(RustingBrainError::BatchTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn regularized_layer_gradients() {
+        use crate::regularizer::Regularizer;
+        let build = |lr| {
+            Sequential::new(vec![1, 2, 2], lr)
+                .with_layer(Conv2D::new(2, (2, 2)).with_regularizer(Regularizer::l2(0.1)))
+                .with_layer(Flatten::new())
+                .with_layer(Dense::new(1).with_regularizer(Regularizer::elastic_net(0.02, 0.05)))
+                .with_seed(4)
+        };
+        let inputs = vec![vec![0.5, -0.2, 0.1, 0.9], vec![0.2, 0.8, -0.4, -0.1]];
+        let targets = vec![vec![0.5], vec![-1.0]];
+        check_model(build, &inputs, &targets, |_| true);
+    }
+}
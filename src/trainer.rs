@@ -0,0 +1,328 @@
+use std::thread::available_parallelism;
+
+use rand::seq::SliceRandom;
+
+use crate::callback::Callback;
+use crate::error::Result;
+use crate::model::{self, Model};
+
+// Inputs and targets of one dataset split.
+type Split<'a> = (&'a [Vec<f32>], &'a [Vec<f32>]);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EpochStats {
+    pub epoch: usize,
+    pub train_loss: f32,
+    pub val_loss: Option<f32>,
+    // Effective learning rate at the start of the epoch.
+    pub learning_rate: f32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    pub epochs: Vec<EpochStats>,
+}
+
+impl History {
+    pub fn train_loss(&self) -> Vec<f32> {
+        self.epochs.iter().map(|e| e.train_loss).collect()
+    }
+
+    pub fn val_loss(&self) -> Vec<f32> {
+        self.epochs.iter().filter_map(|e| e.val_loss).collect()
+    }
+}
+
+pub struct Trainer {
+    epochs: usize,
+    batch_size: usize,
+    shuffle: bool,
+    num_threads: usize,
+    verbose: bool,
+    callbacks: Vec<Box<dyn Callback>>,
+}
+
+impl Trainer {
+    pub fn new(epochs: usize, batch_size: usize) -> Self {
+        Trainer {
+            epochs,
+            batch_size: batch_size.max(1),
+            shuffle: true,
+            num_threads: available_parallelism().map(|n| n.get()).unwrap_or(1),
+            verbose: false,
+            callbacks: Vec::new(),
+        }
+    }
+
+    pub fn with_shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    pub fn with_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn with_callback<C: Callback + 'static>(mut self, callback: C) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    pub fn fit(
+        &mut self,
+        net: &mut dyn Model,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+    ) -> Result<History> {
+        self.run(net, inputs, targets, None)
+    }
+
+    pub fn fit_with_validation(
+        &mut self,
+        net: &mut dyn Model,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        val_inputs: &[Vec<f32>],
+        val_targets: &[Vec<f32>],
+    ) -> Result<History> {
+        net.check_batch(val_inputs, val_targets)?;
+        self.run(net, inputs, targets, Some((val_inputs, val_targets)))
+    }
+
+    fn run(
+        &mut self,
+        net: &mut dyn Model,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        validation: Option<Split>,
+    ) -> Result<History> {
+        net.check_batch(inputs, targets)?;
+
+        // Every mini-batch must be trainable, the last, possibly partial,
+        // one included.
+        let samples = inputs.len();
+        let smallest = match samples % self.batch_size {
+            0 => self.batch_size,
+            rest => rest,
+        };
+        model::check_batch_size(smallest, net.min_batch_size())?;
+
+        let mut order: Vec<usize> = (0..samples).collect();
+        let mut history = History::default();
+
+        for callback in &mut self.callbacks {
+            callback.on_train_begin(net)?;
+        }
+
+        for epoch in 0..self.epochs {
+            for callback in &mut self.callbacks {
+                callback.on_epoch_begin(epoch, net)?;
+            }
+
+            // Shuffling draws from the model's generator, so a seeded
+            // model also gets a reproducible sample order.
+            if self.shuffle {
+                order.shuffle(net.rng());
+            }
+
+            let learning_rate = net.current_learning_rate();
+            let mut loss_sum = 0.0;
+            let mut start = 0usize;
+            let mut batch = 0usize;
+            while start < samples {
+                let end = (start + self.batch_size).min(samples);
+                for callback in &mut self.callbacks {
+                    callback.on_batch_begin(batch, net)?;
+                }
+
+                let batch_loss = if self.shuffle {
+                    let batch_inputs: Vec<Vec<f32>> = order[start..end]
+                        .iter()
+                        .map(|&i| inputs[i].clone())
+                        .collect();
+                    let batch_targets: Vec<Vec<f32>> = order[start..end]
+                        .iter()
+                        .map(|&i| targets[i].clone())
+                        .collect();
+                    net.train_batch_parallel(&batch_inputs, &batch_targets, self.num_threads)
+                } else {
+                    net.train_batch_parallel(
+                        &inputs[start..end],
+                        &targets[start..end],
+                        self.num_threads,
+                    )
+                };
+
+                for callback in &mut self.callbacks {
+                    callback.on_batch_end(batch, batch_loss, net)?;
+                }
+
+                loss_sum += batch_loss * (end - start) as f32;
+                start = end;
+                batch += 1;
+            }
+
+            let stats = EpochStats {
+                epoch,
+                train_loss: loss_sum / samples as f32,
+                val_loss: validation.map(|(vi, vt)| net.evaluate(vi, vt)),
+                learning_rate,
+            };
+
+            if self.verbose {
+                match stats.val_loss {
+                    Some(val) => println!(
+                        "Epoch {}/{} - loss: {} - val_loss: {}",
+                        epoch + 1,
+                        self.epochs,
+                        stats.train_loss,
+                        val
+                    ),
+                    None => println!(
+                        "Epoch {}/{} - loss: {}",
+                        epoch + 1,
+                        self.epochs,
+                        stats.train_loss
+                    ),
+                }
+            }
+
+            for callback in &mut self.callbacks {
+                callback.on_epoch_end(&stats, net)?;
+            }
+            net.end_epoch(stats.val_loss.unwrap_or(stats.train_loss));
+            history.epochs.push(stats);
+
+            if self.callbacks.iter().any(|c| c.stop_training()) {
+                if self.verbose {
+                    println!("Stopping early after epoch {}", epoch + 1);
+                }
+                break;
+            }
+        }
+
+        for callback in &mut self.callbacks {
+            callback.on_train_end(net)?;
+        }
+
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RustingBrainError;
+    use crate::network::Network;
+
+    fn dataset() -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let inputs: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32 / 10.0]).collect();
+        let targets = inputs.iter().map(|x| vec![2.0 * x[0] - 0.5]).collect();
+        (inputs, targets)
+    }
+
+    fn prediction(net: &mut Network) -> Vec<f32> {
+        net.forward(&[0.3])
+    }
+
+    #[test]
+    fn history_has_one_entry_per_epoch() {
+        let (inputs, targets) = dataset();
+        let mut net = Network::new_with_seed(vec![1, 4, 1], 0.05, 1);
+        let history = Trainer::new(5, 3)
+            .fit_with_validation(&mut net, &inputs, &targets, &inputs[..4], &targets[..4])
+            .unwrap();
+        assert_eq!(history.epochs.len(), 5);
+        assert_eq!(history.epochs.iter().map(|e| e.epoch).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(history.val_loss().len(), 5);
+        // The last validation loss is measured after the last step.
+        let last = history.epochs[4].val_loss.unwrap();
+        assert_eq!(last, net.evaluate(&inputs[..4], &targets[..4]));
+        assert!(history.train_loss()[4] < history.train_loss()[0]);
+    }
+
+    #[test]
+    fn unshuffled_epochs_walk_the_batches_in_order() {
+        let (inputs, targets) = dataset();
+        let mut trained = Network::new_with_seed(vec![1, 4, 1], 0.05, 2);
+        let history = Trainer::new(2, 4)
+            .with_shuffle(false)
+            .with_threads(1)
+            .fit(&mut trained, &inputs, &targets)
+            .unwrap();
+
+        let mut manual = Network::new_with_seed(vec![1, 4, 1], 0.05, 2);
+        let mut losses = Vec::new();
+        for _ in 0..2 {
+            let mut sum = 0.0;
+            for (x, t) in inputs.chunks(4).zip(targets.chunks(4)) {
+                sum += manual.train_batch_parallel(x, t, 1) * x.len() as f32;
+            }
+            losses.push(sum / 10.0);
+        }
+        assert_eq!(history.train_loss(), losses);
+        assert_eq!(prediction(&mut trained), prediction(&mut manual));
+    }
+
+    #[test]
+    fn shuffling_is_seeded_by_the_network() {
+        let (inputs, targets) = dataset();
+        let run = |shuffle: bool| {
+            let mut net = Network::new_with_seed(vec![1, 4, 1], 0.05, 3);
+            Trainer::new(3, 4)
+                .with_shuffle(shuffle)
+                .with_threads(1)
+                .fit(&mut net, &inputs, &targets)
+                .unwrap();
+            prediction(&mut net)
+        };
+        assert_eq!(run(true), run(true));
+        assert_ne!(run(true), run(false));
+    }
+
+    #[test]
+    fn epochs_record_the_scheduled_rate() {
+        let (inputs, targets) = dataset();
+        let mut net = Network::new(vec![1, 4, 1], 0.4)
+            .with_scheduler(crate::scheduler::StepDecay::new(1, 0.5));
+        let history = Trainer::new(3, 5).fit(&mut net, &inputs, &targets).unwrap();
+        let rates: Vec<f32> = history.epochs.iter().map(|e| e.learning_rate).collect();
+        assert_eq!(rates, [0.4, 0.2, 0.1]);
+    }
+
+    #[test]
+    fn fit_checks_both_splits() {
+        let (inputs, targets) = dataset();
+        let mut net = Network::new(vec![1, 4, 1], 0.05);
+        let mut trainer = Trainer::new(1, 4);
+        assert!(trainer.fit(&mut net, &inputs, &targets[..3]).is_err());
+        assert!(trainer.fit(&mut net, &[], &[]).is_err());
+        let bad = vec![vec![1.0, 2.0]];
+        assert!(trainer.fit_with_validation(&mut net, &inputs, &targets, &bad, &[vec![0.0]]).is_err());
+    }
+
+    #[test]
+    fn a_trailing_batch_of_one_is_rejected_up_front() {
+        let (inputs, targets) = dataset();
+        let mut net = Network::new_with_seed(vec![1, 4, 1], 0.05, 1).with_batch_norm(1);
+        let before = prediction(&mut net);
+        let batch_size = inputs.len() - 1;
+        let result = Trainer::new(2, batch_size).fit(&mut net, &inputs, &targets);
+        assert!(matches!(
+            result,
+            Err(RustingBrainError::BatchTooSmall {
+                found: 1,
+                minimum: 2
+            })
+        ));
+        // Nothing was trained before the error.
+        assert_eq!(prediction(&mut net), before);
+        assert!(Trainer::new(1, 2).fit(&mut net, &inputs, &targets).is_ok());
+    }
+}
@@ -0,0 +1,278 @@
+use rand::rngs::StdRng;
+
+use crate::activation::Activation;
+use crate::attention::{MultiHeadAttention, sequences, tokens};
+use crate::conv::sequence_shape;
+use crate::error::Result;
+use crate::initializer::Initializer;
+use crate::layer::{Cache, Dense, Layer, NormalizationLayer, Pass, add_to};
+use crate::matrix::Matrix;
+
+// Transformer encoder block over `[d_model, steps]` sequences: multi-head
+// self-attention and a position-wise feed-forward network, each wrapped in
+// a residual connection and layer normalization. Post-norm as in the
+// original paper by default; pre-norm is usually easier to train deep.
+pub struct TransformerEncoder {
+    norm_first: bool,
+    model_dim: usize,
+    attention: MultiHeadAttention,
+    attention_norm: NormalizationLayer,
+    feed_forward: Dense,
+    projection: Dense,
+    feed_forward_norm: NormalizationLayer,
+    output_shape: Vec<usize>,
+}
+
+// Sublayers in the order their parameters and caches are laid out.
+const SUBLAYERS: usize = 5;
+
+impl TransformerEncoder {
+    pub fn new(heads: usize, feed_forward_units: usize) -> Self {
+        TransformerEncoder {
+            norm_first: false,
+            model_dim: 0,
+            attention: MultiHeadAttention::new(heads),
+            attention_norm: NormalizationLayer::layer(),
+            feed_forward: Dense::new(feed_forward_units)
+                .with_activation(Activation::Relu)
+                .with_initializer(Initializer::XavierUniform, Initializer::Zeros),
+            projection: Dense::new(0)
+                .with_initializer(Initializer::XavierUniform, Initializer::Zeros),
+            feed_forward_norm: NormalizationLayer::layer(),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_causal_mask(mut self, causal: bool) -> Self {
+        self.attention = self.attention.with_causal_mask(causal);
+        self
+    }
+
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.feed_forward = self.feed_forward.with_activation(activation);
+        self
+    }
+
+    // Normalizes the input of each sublayer instead of the residual sum.
+    pub fn with_norm_first(mut self, norm_first: bool) -> Self {
+        self.norm_first = norm_first;
+        self
+    }
+
+    pub fn attention(&self) -> &MultiHeadAttention {
+        &self.attention
+    }
+
+    fn sublayers(&self) -> [&dyn Layer; SUBLAYERS] {
+        [
+            &self.attention,
+            &self.attention_norm,
+            &self.feed_forward,
+            &self.projection,
+            &self.feed_forward_norm,
+        ]
+    }
+}
+
+fn sum(mut a: Matrix, b: &Matrix) -> Matrix {
+    add_to(&mut a, b);
+    a
+}
+
+impl Layer for TransformerEncoder {
+    fn name(&self) -> &'static str {
+        "transformer_encoder"
+    }
+
+    fn build(&mut self, input_shape: &[usize], rng: &mut StdRng) -> Result<Vec<usize>> {
+        let (model_dim, _) = sequence_shape(self.name(), input_shape)?;
+        self.model_dim = model_dim;
+        self.attention.build(input_shape, rng)?;
+        self.attention_norm.build(&[model_dim], rng)?;
+        let hidden = self.feed_forward.build(&[model_dim], rng)?;
+        self.projection =
+            Dense::new(model_dim).with_initializer(Initializer::XavierUniform, Initializer::Zeros);
+        self.projection.build(&hidden, rng)?;
+        self.feed_forward_norm.build(&[model_dim], rng)?;
+        self.output_shape = input_shape.to_vec();
+        Ok(self.output_shape.clone())
+    }
+
+    fn output_shape(&self) -> &[usize] {
+        &self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Matrix> {
+        let mut params = self.attention.parameters();
+        params.extend(self.attention_norm.parameters());
+        params.extend(self.feed_forward.parameters());
+        params.extend(self.projection.parameters());
+        params.extend(self.feed_forward_norm.parameters());
+        params
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix> {
+        let mut params = self.attention.parameters_mut();
+        params.extend(self.attention_norm.parameters_mut());
+        params.extend(self.feed_forward.parameters_mut());
+        params.extend(self.projection.parameters_mut());
+        params.extend(self.feed_forward_norm.parameters_mut());
+        params
+    }
+
+    fn state(&self) -> Vec<&Matrix> {
+        let mut state = self.attention_norm.state();
+        state.extend(self.feed_forward_norm.state());
+        state
+    }
+
+    fn state_mut(&mut self) -> Vec<&mut Matrix> {
+        let mut state = self.attention_norm.state_mut();
+        state.extend(self.feed_forward_norm.state_mut());
+        state
+    }
+
+    // Normalization and feed-forward sublayers see one token per column.
+    // Every sublayer keeps its own cache, in sublayer order.
+    fn forward(&self, input: &Matrix, cache: &mut Cache, pass: &mut Pass) -> Matrix {
+        let (d, batch) = (self.model_dim, input.cols);
+        let [
+            attention,
+            attention_norm,
+            feed_forward,
+            projection,
+            feed_forward_norm,
+        ] = self.sublayers();
+        let mut caches: [Cache; SUBLAYERS] = Default::default();
+        let [c_att, c_att_norm, c_ff, c_proj, c_ff_norm] = &mut caches;
+        let x = tokens(input.clone(), d);
+
+        let out = if self.norm_first {
+            let normed = attention_norm.forward(&x, c_att_norm, pass);
+            let attended = attention.forward(&sequences(normed, batch), c_att, pass);
+            let residual = sum(x, &tokens(attended, d));
+            let normed = feed_forward_norm.forward(&residual, c_ff_norm, pass);
+            let hidden = feed_forward.forward(&normed, c_ff, pass);
+            let projected = projection.forward(&hidden, c_proj, pass);
+            sum(residual, &projected)
+        } else {
+            let attended = attention.forward(input, c_att, pass);
+            let residual = sum(x, &tokens(attended, d));
+            let normed = attention_norm.forward(&residual, c_att_norm, pass);
+            let hidden = feed_forward.forward(&normed, c_ff, pass);
+            let projected = projection.forward(&hidden, c_proj, pass);
+            let residual = sum(normed, &projected);
+            feed_forward_norm.forward(&residual, c_ff_norm, pass)
+        };
+
+        for c in caches {
+            cache.push_sublayer(c);
+        }
+        sequences(out, batch)
+    }
+
+    fn backward(
+        &self,
+        error: &Matrix,
+        cache: &Cache,
+        gradients: &mut [Matrix],
+        pass: &mut Pass,
+    ) -> Matrix {
+        let (d, batch) = (self.model_dim, error.cols);
+        let sublayers = self.sublayers();
+
+        let caches: [&Cache; SUBLAYERS] = std::array::from_fn(|i| cache.sublayer(i));
+        let mut grads: [&mut [Matrix]; SUBLAYERS] = Default::default();
+        let mut remaining = gradients;
+        for (slot, layer) in grads.iter_mut().zip(sublayers) {
+            let (head, tail) = remaining.split_at_mut(layer.parameters().len());
+            *slot = head;
+            remaining = tail;
+        }
+
+        let [
+            attention,
+            attention_norm,
+            feed_forward,
+            projection,
+            feed_forward_norm,
+        ] = sublayers;
+        let [c_att, c_att_norm, c_ff, c_proj, c_ff_norm] = caches;
+        let [g_att, g_att_norm, g_ff, g_proj, g_ff_norm] = grads;
+        let error = tokens(error.clone(), d);
+
+        let input_error = if self.norm_first {
+            let e = projection.backward(&error, c_proj, g_proj, pass);
+            let e = feed_forward.backward(&e, c_ff, g_ff, pass);
+            let e = feed_forward_norm.backward(&e, c_ff_norm, g_ff_norm, pass);
+            let residual_error = sum(error, &e);
+            let e = attention.backward(
+                &sequences(residual_error.clone(), batch),
+                c_att,
+                g_att,
+                pass,
+            );
+            let e = attention_norm.backward(&tokens(e, d), c_att_norm, g_att_norm, pass);
+            sum(residual_error, &e)
+        } else {
+            let residual_error = feed_forward_norm.backward(&error, c_ff_norm, g_ff_norm, pass);
+            let e = projection.backward(&residual_error, c_proj, g_proj, pass);
+            let e = feed_forward.backward(&e, c_ff, g_ff, pass);
+            let normed_error = sum(residual_error, &e);
+            let residual_error =
+                attention_norm.backward(&normed_error, c_att_norm, g_att_norm, pass);
+            let e = attention.backward(
+                &sequences(residual_error.clone(), batch),
+                c_att,
+                g_att,
+                pass,
+            );
+            sum(residual_error, &tokens(e, d))
+        };
+        sequences(input_error, batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::check_layer;
+
+    #[test]
+    fn post_norm_gradients() {
+        check_layer(TransformerEncoder::new(2, 6), &[4, 3], 2);
+    }
+
+    #[test]
+    fn pre_norm_gradients() {
+        check_layer(
+            TransformerEncoder::new(2, 6)
+                .with_norm_first(true)
+                .with_causal_mask(true)
+                .with_activation(Activation::Gelu),
+            &[4, 3],
+            2,
+        );
+    }
+
+    #[test]
+    fn sublayer_caches_stay_separate() {
+        use rand::SeedableRng;
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut encoder = TransformerEncoder::new(2, 6);
+        encoder.build(&[4, 3], &mut rng).unwrap();
+        let input = crate::gradcheck::random(12, 2, &mut rng);
+        let mut pass = Pass {
+            training: true,
+            rng: &mut rng,
+            statistics: None,
+            batch_size: 2,
+        };
+        let mut cache = Cache::new();
+        encoder.forward(&input, &mut cache, &mut pass);
+        assert!(cache.matrices().is_empty());
+        for i in 0..SUBLAYERS {
+            assert!(!cache.sublayer(i).matrices().is_empty());
+        }
+    }
+}